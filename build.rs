@@ -0,0 +1,10 @@
+fn main() {
+    // Build with a known protoc rather than whichever, if any, is installed.
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform");
+    std::env::set_var("PROTOC", protoc);
+
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/player_face.proto"], &["proto"])
+        .expect("failed to compile protobuf definitions");
+}
@@ -0,0 +1,101 @@
+use image::{DynamicImage, imageops, Rgb, RgbaImage, RgbImage};
+use image::imageops::FilterType;
+
+use crate::options::{Factor, FaceOptions, Filter, Flip, Resample, Shape, TintMode, Upscale};
+use crate::render;
+
+const DEFAULT_TINT_STRENGTH: f32 = 0.5;
+
+const ONLINE_COLOR: Rgb<u8> = Rgb([67, 181, 129]);
+const OFFLINE_COLOR: Rgb<u8> = Rgb([116, 127, 141]);
+
+/// Renders a face at `size` from the face as the skin has it, with everything the options ask
+/// for along with the label and badge, if any.
+pub fn render(raw_face: &RgbImage, size: u32, options: &FaceOptions, label: Option<&str>, badge: Option<&RgbaImage>) -> DynamicImage {
+    let face = render_variant(raw_face, size, options);
+    let face = match badge {
+        Some(badge) => DynamicImage::ImageRgba8(render::draw_badge(&face.into_rgba8(), badge)),
+        None => face,
+    };
+    let face = match options.online {
+        Some(online) => {
+            let color = if online { ONLINE_COLOR } else { OFFLINE_COLOR };
+            DynamicImage::ImageRgba8(render::draw_dot(&face.into_rgba8(), color))
+        }
+        None => face,
+    };
+    match label {
+        Some(label) => DynamicImage::ImageRgba8(render::draw_label(&face.into_rgba8(), label)),
+        None => face,
+    }
+}
+
+fn render_variant(raw_face: &RgbImage, size: u32, options: &FaceOptions) -> DynamicImage {
+    let raw_face = apply_pixel_options(raw_face, options);
+
+    let raw_size = raw_face.width();
+    let mut face = if size > raw_size {
+        let factor = size / raw_size;
+        match options.upscale {
+            Upscale::Nearest => render::rescale(&raw_face, factor),
+            Upscale::Hq2x => render::upscale(&raw_face, factor, render::hq2x),
+            Upscale::Xbr => render::upscale(&raw_face, factor, render::xbr),
+        }
+    } else if size < raw_size {
+        match options.resample {
+            Resample::Area => render::downscale(&raw_face, size, size),
+            Resample::Nearest => imageops::resize(&raw_face, size, size, FilterType::Nearest),
+            Resample::Triangle => imageops::resize(&raw_face, size, size, FilterType::Triangle),
+            Resample::Lanczos => imageops::resize(&raw_face, size, size, FilterType::Lanczos3),
+        }
+    } else {
+        raw_face
+    };
+
+    if let Some(border) = options.border {
+        let width = (options.border_width.unwrap_or(1) * size / raw_size).max(1);
+        match options.shape {
+            Shape::Square => render::draw_border(&mut face, border.to_rgb(), width),
+            Shape::Circle => render::draw_circle_border(&mut face, border.to_rgb(), width),
+        }
+    }
+
+    match options.shape {
+        Shape::Square => DynamicImage::ImageRgb8(face),
+        Shape::Circle => DynamicImage::ImageRgba8(render::mask_circle(&face)),
+    }
+}
+
+/// Applies the filter, tint, adjustments, palette and flip to the unscaled face.
+pub fn apply_pixel_options(raw_face: &RgbImage, options: &FaceOptions) -> RgbImage {
+    let mut raw_face = raw_face.clone();
+    match options.filter {
+        Some(Filter::Grayscale) => render::grayscale(&mut raw_face),
+        Some(Filter::Sepia) => render::sepia(&mut raw_face),
+        Some(Filter::Invert) => render::invert(&mut raw_face),
+        None => (),
+    }
+
+    if let Some(tint) = options.tint {
+        let strength = options.tint_strength.map(|s| s.get()).unwrap_or(DEFAULT_TINT_STRENGTH);
+        let overlay = options.tint_mode == TintMode::Overlay;
+        render::tint(&mut raw_face, tint.to_rgb(), overlay, strength);
+    }
+
+    if options.brightness.is_some() || options.contrast.is_some() || options.gamma.is_some() {
+        let [brightness, contrast, gamma] = [options.brightness, options.contrast, options.gamma]
+            .map(|factor| factor.map_or(1.0, Factor::get));
+        render::adjust(&mut raw_face, brightness, contrast, gamma);
+    }
+
+    if let Some(palette) = &options.palette {
+        let colors: Vec<Rgb<u8>> = palette.colors().iter().map(|color| color.to_rgb()).collect();
+        render::quantize(&mut raw_face, &colors, options.dither);
+    }
+
+    if let Some(Flip::Horizontal) = options.flip {
+        imageops::flip_horizontal_in_place(&mut raw_face);
+    }
+
+    raw_face
+}
@@ -0,0 +1,47 @@
+//! Skin parsing and rendering, free of any networking or async runtime so that it builds for
+//! `wasm32` and renders exactly as the server does wherever it runs.
+
+pub mod face;
+pub mod options;
+pub mod render;
+pub mod skin;
+
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+use image::{ColorType, DynamicImage, GenericImageView, ImageBuffer, ImageResult, Pixel};
+use image::codecs::png::PngEncoder;
+
+pub fn encode_png(image: &DynamicImage) -> ImageResult<Vec<u8>> {
+    encode_png_pixels(image.as_bytes(), image.width(), image.height(), image.color())
+}
+
+/// Like [`encode_png`], but borrowing a buffer rather than needing it moved or cloned into a
+/// `DynamicImage`.
+pub fn encode_png_buffer<P: Pixel<Subpixel = u8> + 'static>(image: &ImageBuffer<P, Vec<u8>>) -> ImageResult<Vec<u8>> {
+    encode_png_pixels(image.as_raw(), image.width(), image.height(), P::COLOR_TYPE)
+}
+
+fn encode_png_pixels(pixels: &[u8], width: u32, height: u32, color: ColorType) -> ImageResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    let encoder = PngEncoder::new(&mut bytes);
+    encoder.encode(pixels, width, height, color)?;
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn buffers_encode_as_their_dynamic_images_do() {
+        let image = RgbImage::from_fn(8, 8, |x, y| Rgb([x as u8 * 30, y as u8 * 30, 7]));
+        let png = encode_png_buffer(&image).unwrap();
+        assert_eq!(png, encode_png(&DynamicImage::ImageRgb8(image.clone())).unwrap());
+        assert_eq!(image::load_from_memory(&png).unwrap().into_rgb8(), image);
+    }
+}
@@ -0,0 +1,614 @@
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+pub const MAX_BORDER_WIDTH: u32 = 4;
+pub const MAX_CAPTION_LENGTH: usize = 32;
+pub const MAX_QUALITY: u32 = 4;
+pub const MAX_LAYOUT_SCALE: u32 = 8;
+pub const MAX_BANNER_SCALE: u32 = 4;
+pub const MAX_CARD_SCALE: u32 = 4;
+pub const MAX_PALETTE_COLORS: usize = 16;
+pub const MAX_CHIBI_HEAD_SCALE: u32 = 3;
+pub const MAX_CAPE_SCALE: u32 = 16;
+pub const MAX_CAPE_FPS: u32 = 20;
+const MAX_GAME_LENGTH: usize = 64;
+
+pub fn allowed_sizes() -> impl Iterator<Item = u32> {
+    (1..8).chain((0..6).map(|shift| 8 << shift))
+}
+
+/// Sizes below 8 are downscaled thumbnails; anything larger must be a power-of-two multiple of 8.
+#[inline]
+pub fn parse_size(size: u32) -> Option<u32> {
+    let valid = if size < 8 {
+        size > 0
+    } else {
+        size.is_multiple_of(8) && size <= 256 && (size / 8).is_power_of_two()
+    };
+
+    if valid {
+        Some(size)
+    } else {
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct FaceOptions {
+    pub shape: Shape,
+    pub border: Option<Color>,
+    /// Border width in skin pixels, scaled along with the face.
+    pub border_width: Option<u32>,
+    pub filter: Option<Filter>,
+    pub tint: Option<Color>,
+    pub tint_mode: TintMode,
+    pub tint_strength: Option<Fraction>,
+    /// Scales every color, dimming the face below 1 and lifting it above.
+    pub brightness: Option<Factor>,
+    /// Scales every color's distance from mid gray, flattening the face below 1.
+    pub contrast: Option<Factor>,
+    /// Lightens the face's darker colors above 1 and darkens them below, which must be above 0.
+    pub gamma: Option<Factor>,
+    /// Reduces the face to a named palette such as `gameboy`, or to a comma-separated list of
+    /// hex colors, after the filter and tint.
+    pub palette: Option<Palette>,
+    /// Dithers between palette colors in an ordered pattern rather than taking the nearest.
+    pub dither: bool,
+    /// Mirrors the face, leaving the label, badge and status dot as they are.
+    pub flip: Option<Flip>,
+    /// Renders the player's name beneath the face.
+    pub label: bool,
+    /// Renders the given text beneath the face, taking precedence over `label`.
+    pub caption: Option<String>,
+    /// Composites the configured badge by this name onto the corner of the face.
+    pub badge: Option<String>,
+    /// Composites a dot onto the corner of the face showing whether the player is online on the
+    /// configured status server.
+    pub status: bool,
+    /// Filled in from the status server for `status` requests, so that faces showing either
+    /// state are cached apart.
+    #[serde(skip_deserializing)]
+    pub online: Option<bool>,
+    pub format: OutputFormat,
+    /// How faces smaller than the skin are resampled. Named `resample` since `filter` picks a
+    /// color filter.
+    pub resample: Resample,
+    /// How faces larger than the skin are scaled up, when enabled by the server.
+    pub upscale: Upscale,
+}
+
+impl FaceOptions {
+    pub fn is_valid(&self) -> bool {
+        let caption_valid = match &self.caption {
+            Some(caption) => !caption.is_empty() && caption.chars().count() <= MAX_CAPTION_LENGTH,
+            None => true,
+        };
+
+        let labeled = self.label || self.caption.is_some();
+        let composited = labeled || self.badge.is_some() || self.status;
+        let label_valid = !(composited && self.format == OutputFormat::Svg);
+
+        let gamma_valid = self.gamma.is_none_or(|gamma| gamma.get() > 0.0);
+
+        caption_valid && label_valid && gamma_valid
+            && matches!(self.border_width, None | Some(1..=MAX_BORDER_WIDTH))
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Tga,
+    /// Unencoded RGBA pixels for consumers that would otherwise have to decode a PNG.
+    Raw,
+    /// A favicon bundling several fixed sizes, ignoring the requested size.
+    Ico,
+    /// Scalable pixel art, ignoring the requested size. Labels, badges and status dots are not
+    /// supported.
+    Svg,
+    /// Colored half-block characters for printing straight to a terminal, two pixels to a
+    /// character.
+    Ansi,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct HeadOptions {
+    pub yaw: Angle,
+    pub pitch: Angle,
+    /// Supersampling factor per axis, from 1 (no anti-aliasing) to [`MAX_QUALITY`].
+    pub quality: u32,
+    pub flip: Option<Flip>,
+    /// Crops the fully transparent rows and columns around the head.
+    pub trim: bool,
+}
+
+impl HeadOptions {
+    pub fn is_valid(&self) -> bool {
+        (-180..=180).contains(&self.yaw.0) && (-90..=90).contains(&self.pitch.0)
+            && (1..=MAX_QUALITY).contains(&self.quality)
+    }
+}
+
+impl Default for HeadOptions {
+    fn default() -> Self {
+        HeadOptions {
+            yaw: Angle(45),
+            pitch: Angle(30),
+            quality: 1,
+            flip: None,
+            trim: false,
+        }
+    }
+}
+
+/// Options for the compass sprite sheet of heads, which are all seen from the same side.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct CompassOptions {
+    pub pitch: Angle,
+    /// Supersampling factor per axis, from 1 (no anti-aliasing) to [`MAX_QUALITY`].
+    pub quality: u32,
+}
+
+impl CompassOptions {
+    pub fn is_valid(&self) -> bool {
+        (-90..=90).contains(&self.pitch.0) && (1..=MAX_QUALITY).contains(&self.quality)
+    }
+}
+
+impl Default for CompassOptions {
+    fn default() -> Self {
+        CompassOptions {
+            pitch: Angle(30),
+            quality: 1,
+        }
+    }
+}
+
+/// Options for the flat body renders, `/views` and `/preview`.
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct BodyOptions {
+    /// Mirrors each view of the body in place, keeping them in their usual order.
+    pub flip: Option<Flip>,
+    /// Crops the fully transparent rows and columns around the views as a whole, so that they
+    /// stay evenly spaced.
+    pub trim: bool,
+}
+
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct ChibiOptions {
+    /// How many times its usual size the head is drawn, from 1 for the usual proportions to
+    /// [`MAX_CHIBI_HEAD_SCALE`].
+    pub head_scale: u32,
+    pub flip: Option<Flip>,
+    /// Crops the fully transparent rows and columns around the body and its oversized head.
+    pub trim: bool,
+}
+
+impl ChibiOptions {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_CHIBI_HEAD_SCALE).contains(&self.head_scale)
+    }
+}
+
+impl Default for ChibiOptions {
+    fn default() -> Self {
+        ChibiOptions {
+            head_scale: 2,
+            flip: None,
+            trim: false,
+        }
+    }
+}
+
+/// Options for `/cape`, which answers with an animated GIF of every frame of an animated cape
+/// unless a single frame is asked for.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct CapeOptions {
+    /// Pixels per cape pixel, from 1 to [`MAX_CAPE_SCALE`].
+    pub scale: u32,
+    /// Zero-based index of the single frame to answer with, as a PNG.
+    pub frame: Option<u32>,
+    /// Frames per second of the animation, from 1 to [`MAX_CAPE_FPS`].
+    pub fps: u32,
+}
+
+impl CapeOptions {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_CAPE_SCALE).contains(&self.scale) && (1..=MAX_CAPE_FPS).contains(&self.fps)
+    }
+}
+
+impl Default for CapeOptions {
+    fn default() -> Self {
+        CapeOptions {
+            scale: 8,
+            frame: None,
+            fps: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct BannerOptions {
+    /// The upper line of text, in yellow. Defaults to "Achievement Get!".
+    pub title: Option<String>,
+    /// The lower line of text, in white. Defaults to the player's name.
+    pub subtitle: Option<String>,
+    /// Pixels per banner pixel, from 1 to [`MAX_BANNER_SCALE`].
+    pub scale: u32,
+}
+
+impl BannerOptions {
+    pub fn is_valid(&self) -> bool {
+        let text_valid = |text: &Option<String>| text.as_ref()
+            .is_none_or(|text| !text.is_empty() && text.chars().count() <= MAX_CAPTION_LENGTH);
+
+        text_valid(&self.title) && text_valid(&self.subtitle) && (1..=MAX_BANNER_SCALE).contains(&self.scale)
+    }
+}
+
+impl Default for BannerOptions {
+    fn default() -> Self {
+        BannerOptions {
+            title: None,
+            subtitle: None,
+            scale: 2,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct CardOptions {
+    /// The game namespace to show statistics for, rather than the games played of each.
+    pub game: Option<String>,
+    /// Pixels per card pixel, from 1 to [`MAX_CARD_SCALE`].
+    pub scale: u32,
+}
+
+impl CardOptions {
+    pub fn is_valid(&self) -> bool {
+        let game_valid = self.game.as_ref().is_none_or(|game| {
+            (1..=MAX_GAME_LENGTH).contains(&game.len())
+                && game.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b':'))
+        });
+
+        game_valid && (1..=MAX_CARD_SCALE).contains(&self.scale)
+    }
+}
+
+impl Default for CardOptions {
+    fn default() -> Self {
+        CardOptions {
+            game: None,
+            scale: 2,
+        }
+    }
+}
+
+/// An angle in degrees, quantized to multiples of [`Angle::STEP`] to bound cache key cardinality.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Angle(i16);
+
+impl Angle {
+    pub const STEP: f32 = 5.0;
+
+    #[inline]
+    pub fn degrees(self) -> f32 {
+        self.0 as f32
+    }
+}
+
+impl<'de> Deserialize<'de> for Angle {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = f32::deserialize(deserializer)?;
+        if value.is_finite() && value.abs() <= 360.0 {
+            Ok(Angle(((value / Angle::STEP).round() * Angle::STEP) as i16))
+        } else {
+            Err(de::Error::invalid_value(de::Unexpected::Float(value as f64), &"an angle in degrees"))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    #[default]
+    Square,
+    Circle,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    #[serde(alias = "greyscale")]
+    Grayscale,
+    Sepia,
+    Invert,
+}
+
+/// Mirrors a render, such as to show two players facing each other.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Flip {
+    Horizontal,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Resample {
+    /// Coverage-weighted mean of the overlapped source pixels.
+    #[default]
+    Area,
+    Nearest,
+    Triangle,
+    Lanczos,
+}
+
+/// Pixel-art interpolation applied in doublings, with nearest-neighbour covering odd factors.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Upscale {
+    #[default]
+    Nearest,
+    Hq2x,
+    Xbr,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TintMode {
+    #[default]
+    Multiply,
+    Overlay,
+}
+
+/// The colors a face is reduced to, from 2 to [`MAX_PALETTE_COLORS`] of them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Palette(Vec<Color>);
+
+impl Palette {
+    const GAMEBOY: [u32; 4] = [0x0f380f, 0x306230, 0x8bac0f, 0x9bbc0f];
+    const C64: [u32; 16] = [
+        0x000000, 0xffffff, 0x880000, 0xaaffee, 0xcc44cc, 0x00cc55, 0x0000aa, 0xeeee77,
+        0xdd8855, 0x664400, 0xff7777, 0x333333, 0x777777, 0xaaff66, 0x0088ff, 0xbbbbbb,
+    ];
+    const PICO8: [u32; 16] = [
+        0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8,
+        0xff004d, 0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+    ];
+
+    pub fn parse(value: &str) -> Option<Palette> {
+        let preset: Option<&[u32]> = match value {
+            "gameboy" => Some(&Palette::GAMEBOY),
+            "c64" => Some(&Palette::C64),
+            "pico8" => Some(&Palette::PICO8),
+            _ => None,
+        };
+        if let Some(preset) = preset {
+            let colors = preset.iter().map(|&value| Color([(value >> 16) as u8, (value >> 8) as u8, value as u8]));
+            return Some(Palette(colors.collect()));
+        }
+
+        let colors = value.split(',').map(Color::parse).collect::<Option<Vec<Color>>>()?;
+        if (2..=MAX_PALETTE_COLORS).contains(&colors.len()) {
+            Some(Palette(colors))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn colors(&self) -> &[Color] {
+        &self.0
+    }
+}
+
+/// As the comma-separated hex colors it parses from.
+impl Serialize for Palette {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let colors: Vec<String> = self.0.iter().map(Color::to_hex).collect();
+        serializer.serialize_str(&colors.join(","))
+    }
+}
+
+impl<'de> Deserialize<'de> for Palette {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Palette::parse(&value).ok_or_else(|| de::Error::invalid_value(
+            de::Unexpected::Str(&value),
+            &"gameboy, c64, pico8, or 2 to 16 comma-separated hex colors",
+        ))
+    }
+}
+
+/// A value in `0..=1`, quantized to hundredths so that it can take part in cache keys.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Fraction(u8);
+
+impl Fraction {
+    #[inline]
+    pub fn get(self) -> f32 {
+        self.0 as f32 / 100.0
+    }
+}
+
+impl Serialize for Fraction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(self.get())
+    }
+}
+
+impl<'de> Deserialize<'de> for Fraction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = f32::deserialize(deserializer)?;
+        if (0.0..=1.0).contains(&value) {
+            Ok(Fraction((value * 100.0).round() as u8))
+        } else {
+            Err(de::Error::invalid_value(de::Unexpected::Float(value as f64), &"a value between 0 and 1"))
+        }
+    }
+}
+
+/// A non-negative factor up to [`Factor::MAX`], quantized to multiples of [`Factor::STEP`] to bound
+/// cache key cardinality.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Factor(u8);
+
+impl Factor {
+    pub const STEP: f32 = 0.05;
+    pub const MAX: f32 = 4.0;
+
+    #[inline]
+    pub fn get(self) -> f32 {
+        self.0 as f32 * Factor::STEP
+    }
+}
+
+impl Serialize for Factor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(self.get())
+    }
+}
+
+impl<'de> Deserialize<'de> for Factor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = f32::deserialize(deserializer)?;
+        if (0.0..=Factor::MAX).contains(&value) {
+            Ok(Factor((value / Factor::STEP).round() as u8))
+        } else {
+            Err(de::Error::invalid_value(de::Unexpected::Float(value as f64), &"a factor between 0 and 4"))
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Color(pub [u8; 3]);
+
+impl Color {
+    pub fn parse(hex: &str) -> Option<Color> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        Some(Color([(value >> 16) as u8, (value >> 8) as u8, value as u8]))
+    }
+
+    #[inline]
+    pub fn to_rgb(self) -> image::Rgb<u8> {
+        image::Rgb(self.0)
+    }
+
+    /// As `RRGGBB`, without the leading `#`.
+    pub fn to_hex(&self) -> String {
+        format!("{:02x}{:02x}{:02x}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Color;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a hex color in the form RRGGBB")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Color, E> {
+                Color::parse(value).ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::value::Error;
+    use serde::de::IntoDeserializer;
+
+    use super::*;
+
+    fn deserialize<'de, T: Deserialize<'de>>(value: impl IntoDeserializer<'de, Error>) -> Option<T> {
+        T::deserialize(value.into_deserializer()).ok()
+    }
+
+    #[test]
+    fn sizes_are_thumbnails_or_power_of_two_multiples_of_eight() {
+        assert_eq!(allowed_sizes().collect::<Vec<_>>(), [1, 2, 3, 4, 5, 6, 7, 8, 16, 32, 64, 128, 256]);
+        for size in 0..=512 {
+            assert_eq!(parse_size(size).is_some(), allowed_sizes().any(|allowed| allowed == size), "size {}", size);
+        }
+    }
+
+    #[test]
+    fn colors_parse_from_hex() {
+        assert_eq!(Color::parse("ff8000"), Some(Color([255, 128, 0])));
+        assert_eq!(Color::parse("#FF8000"), Some(Color([255, 128, 0])));
+        assert_eq!(Color::parse("ff800"), None);
+        assert_eq!(Color::parse("ff80000"), None);
+        assert_eq!(Color::parse("gg8000"), None);
+        assert_eq!(Color::parse("+f8000"), None);
+        assert_eq!(Color([1, 2, 255]).to_hex(), "0102ff");
+        assert_eq!(deserialize::<Color>("#00ff00"), Some(Color([0, 255, 0])));
+        assert_eq!(deserialize::<Color>("green"), None);
+    }
+
+    #[test]
+    fn fractions_are_quantized_to_hundredths() {
+        assert_eq!(deserialize::<Fraction>(0.5f32).map(Fraction::get), Some(0.5));
+        assert_eq!(deserialize::<Fraction>(0.123f32), deserialize::<Fraction>(0.12f32));
+        assert!(deserialize::<Fraction>(0.0f32).is_some());
+        assert!(deserialize::<Fraction>(1.0f32).is_some());
+        assert_eq!(deserialize::<Fraction>(1.01f32), None);
+        assert_eq!(deserialize::<Fraction>(-0.1f32), None);
+        assert_eq!(deserialize::<Fraction>(f32::NAN), None);
+    }
+
+    #[test]
+    fn palettes_are_presets_or_lists_of_colors() {
+        assert_eq!(Palette::parse("gameboy").map(|palette| palette.colors().len()), Some(4));
+        assert_eq!(Palette::parse("pico8").map(|palette| palette.colors()[0]), Some(Color([0, 0, 0])));
+        assert_eq!(
+            Palette::parse("000000,#ffffff").map(|palette| palette.colors().to_vec()),
+            Some(vec![Color([0, 0, 0]), Color([255, 255, 255])]),
+        );
+
+        assert_eq!(Palette::parse("000000"), None);
+        assert_eq!(Palette::parse(&vec!["000000"; MAX_PALETTE_COLORS + 1].join(",")), None);
+        assert_eq!(Palette::parse("000000,white"), None);
+        assert_eq!(Palette::parse("000000,"), None);
+        assert!(deserialize::<Palette>("c64").is_some());
+    }
+
+    #[test]
+    fn factors_are_quantized_to_steps() {
+        assert_eq!(deserialize::<Factor>(1.02f32), deserialize::<Factor>(1.0f32));
+        assert_eq!(deserialize::<Factor>(1.03f32), deserialize::<Factor>(1.05f32));
+        assert_ne!(deserialize::<Factor>(1.0f32), deserialize::<Factor>(1.05f32));
+        assert!(deserialize::<Factor>(Factor::MAX).is_some());
+        assert_eq!(deserialize::<Factor>(Factor::MAX + 0.1), None);
+        assert_eq!(deserialize::<Factor>(-1.0f32), None);
+    }
+}
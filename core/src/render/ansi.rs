@@ -0,0 +1,46 @@
+use std::fmt::Write;
+
+use image::{Rgba, RgbaImage};
+
+const UPPER_HALF: char = '▀';
+const LOWER_HALF: char = '▄';
+const RESET: &str = "\x1b[0m";
+
+/// Renders the image as lines of 24-bit colored half blocks for printing to a terminal, each
+/// character showing two pixels one above the other. Transparent pixels are left in the
+/// terminal's own background color.
+pub fn render_ansi(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut ansi = String::new();
+
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let top = visible(image.get_pixel(x, y));
+            let bottom = if y + 1 < height { visible(image.get_pixel(x, y + 1)) } else { None };
+
+            match (top, bottom) {
+                (Some([r, g, b]), Some([br, bg, bb])) => {
+                    write!(ansi, "\x1b[38;2;{};{};{};48;2;{};{};{}m{}", r, g, b, br, bg, bb, UPPER_HALF).unwrap();
+                }
+                (Some([r, g, b]), None) => write!(ansi, "{}\x1b[38;2;{};{};{}m{}", RESET, r, g, b, UPPER_HALF).unwrap(),
+                (None, Some([r, g, b])) => write!(ansi, "{}\x1b[38;2;{};{};{}m{}", RESET, r, g, b, LOWER_HALF).unwrap(),
+                (None, None) => write!(ansi, "{} ", RESET).unwrap(),
+            }
+        }
+        ansi.push_str(RESET);
+        ansi.push('\n');
+    }
+
+    ansi
+}
+
+/// Terminals can't blend, so pixels are either drawn opaque or not at all.
+#[inline]
+fn visible(pixel: &Rgba<u8>) -> Option<[u8; 3]> {
+    let [r, g, b, a] = pixel.0;
+    if a >= 128 {
+        Some([r, g, b])
+    } else {
+        None
+    }
+}
@@ -0,0 +1,48 @@
+use image::{Rgba, RgbaImage, RgbImage};
+
+use super::{rescale, text};
+
+const MIN_WIDTH: u32 = 160;
+const HEIGHT: u32 = 32;
+
+const ICON_SIZE: u32 = 16;
+const ICON_OFFSET: u32 = 8;
+const TEXT_X: u32 = 30;
+const TITLE_Y: u32 = 7;
+const SUBTITLE_Y: u32 = 18;
+
+const BACKGROUND: Rgba<u8> = Rgba([33, 33, 33, 255]);
+const BORDER: Rgba<u8> = Rgba([85, 85, 85, 255]);
+const TITLE_COLOR: Rgba<u8> = Rgba([255, 255, 0, 255]);
+const SUBTITLE_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Renders an "Achievement Get!" style toast with the face as its icon, widened past the usual
+/// 160 pixels if the text doesn't fit.
+pub fn render_banner(face: &RgbImage, title: &str, subtitle: &str) -> RgbaImage {
+    let text_width = text::measure(title).max(text::measure(subtitle));
+    let width = MIN_WIDTH.max(TEXT_X + text_width + ICON_OFFSET);
+
+    let mut banner = RgbaImage::from_fn(width, HEIGHT, |x, y| {
+        let edge_x = x == 0 || x == width - 1;
+        let edge_y = y == 0 || y == HEIGHT - 1;
+        if edge_x && edge_y {
+            // The corners are cut off for a rounded look.
+            Rgba([0, 0, 0, 0])
+        } else if edge_x || edge_y {
+            BORDER
+        } else {
+            BACKGROUND
+        }
+    });
+
+    let icon = rescale(face, (ICON_SIZE / face.width()).max(1));
+    for (x, y, pixel) in icon.enumerate_pixels() {
+        let [r, g, b] = pixel.0;
+        banner.put_pixel(ICON_OFFSET + x, ICON_OFFSET + y, Rgba([r, g, b, 255]));
+    }
+
+    text::draw(&mut banner, title, TEXT_X, TITLE_Y, 1, TITLE_COLOR);
+    text::draw(&mut banner, subtitle, TEXT_X, SUBTITLE_Y, 1, SUBTITLE_COLOR);
+
+    banner
+}
@@ -0,0 +1,165 @@
+use image::{Pixel, RgbaImage};
+
+use crate::skin::{CuboidTex, Format, Skin, TexRegion};
+
+pub const BODY_WIDTH: u32 = 16;
+pub const BODY_HEIGHT: u32 = 32;
+pub const VIEW_GAP: u32 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum View {
+    Front,
+    Back,
+    Left,
+    Right,
+}
+
+impl View {
+    pub const ALL: [View; 4] = [View::Front, View::Back, View::Left, View::Right];
+}
+
+/// Picks which face of a cuboid is visible in a view.
+type FaceSelector = fn(&CuboidTex) -> TexRegion;
+
+/// A cuboid part of the player model paired with its optional overlay layer.
+#[derive(Copy, Clone)]
+struct Part {
+    base: CuboidTex,
+    overlay: Option<CuboidTex>,
+}
+
+impl Part {
+    #[inline]
+    fn new(base: CuboidTex, overlay: Option<CuboidTex>) -> Part {
+        Part { base, overlay }
+    }
+}
+
+/// Renders a flat, unlit view of the whole player at one pixel per texel onto a
+/// [`BODY_WIDTH`] x [`BODY_HEIGHT`] canvas. Legacy skins are normalized first so that the left
+/// limbs are mirrored as in game.
+pub fn render_body(skin: &Skin, view: View) -> RgbaImage {
+    let skin = skin.normalize();
+    let format = skin.format;
+    let mut canvas = RgbaImage::new(BODY_WIDTH, BODY_HEIGHT);
+
+    for (part, region, (x, y)) in layout(&format, view) {
+        draw_part(&mut canvas, &skin.image, part, region, x, y, 1);
+    }
+
+    canvas
+}
+
+/// Renders a flat view of the player as with [`render_body`], but with the head drawn
+/// `head_scale` times its size and centred above the body.
+pub fn render_chibi(skin: &Skin, view: View, head_scale: u32) -> RgbaImage {
+    let skin = skin.normalize();
+    let format = skin.format;
+
+    let head_size = 8 * head_scale;
+    let width = BODY_WIDTH.max(head_size);
+    let mut canvas = RgbaImage::new(width, head_size + BODY_HEIGHT - 8);
+
+    // The rest of the body keeps its usual layout, moved down to make room for the head.
+    let (body_x, body_y) = ((width - BODY_WIDTH) / 2, head_size - 8);
+    let mut parts = layout(&format, view).into_iter();
+    let (head, head_region, _) = parts.next().expect("layouts start with the head");
+    for (part, region, (x, y)) in parts {
+        draw_part(&mut canvas, &skin.image, part, region, body_x + x, body_y + y, 1);
+    }
+    draw_part(&mut canvas, &skin.image, head, head_region, (width - head_size) / 2, 0, head_scale);
+
+    canvas
+}
+
+/// Renders the front, back, left, and right views side by side, separated by [`VIEW_GAP`], each
+/// mirrored if `mirror`.
+pub fn render_views(skin: &Skin, mirror: bool) -> RgbaImage {
+    render_side_by_side(skin, &View::ALL, mirror)
+}
+
+/// Renders the front and back views side by side, as skin sites preview a skin, each mirrored if
+/// `mirror`.
+pub fn render_preview(skin: &Skin, mirror: bool) -> RgbaImage {
+    render_side_by_side(skin, &[View::Front, View::Back], mirror)
+}
+
+fn render_side_by_side(skin: &Skin, views: &[View], mirror: bool) -> RgbaImage {
+    let count = views.len() as u32;
+    let width = BODY_WIDTH * count + VIEW_GAP * (count - 1);
+    let mut result = RgbaImage::new(width, BODY_HEIGHT);
+
+    for (i, &view) in views.iter().enumerate() {
+        let mut body = render_body(skin, view);
+        if mirror {
+            image::imageops::flip_horizontal_in_place(&mut body);
+        }
+        image::imageops::replace(&mut result, &body, i as u32 * (BODY_WIDTH + VIEW_GAP), 0);
+    }
+
+    result
+}
+
+/// Parts in back-to-front draw order starting with the head, with the cuboid face to show and
+/// its canvas position.
+fn layout(format: &Format, view: View) -> Vec<(Part, FaceSelector, (u32, u32))> {
+    let head = Part::new(format.head, Some(format.hat));
+    let body = Part::new(format.body, format.jacket);
+    let right_arm = Part::new(format.right_arm, format.right_sleeves);
+    let left_arm = Part::new(format.left_arm, format.left_sleeves);
+    let right_leg = Part::new(format.right_leg, format.right_pants);
+    let left_leg = Part::new(format.left_leg, format.left_pants);
+
+    let arm_width = format.right_arm.front.size.0;
+
+    match view {
+        View::Front => vec![
+            (head, |c| c.front, (4, 0)),
+            (body, |c| c.front, (4, 8)),
+            (right_arm, |c| c.front, (4 - arm_width, 8)),
+            (left_arm, |c| c.front, (12, 8)),
+            (right_leg, |c| c.front, (4, 20)),
+            (left_leg, |c| c.front, (8, 20)),
+        ],
+        View::Back => vec![
+            (head, |c| c.back, (4, 0)),
+            (body, |c| c.back, (4, 8)),
+            (left_arm, |c| c.back, (4 - arm_width, 8)),
+            (right_arm, |c| c.back, (12, 8)),
+            (left_leg, |c| c.back, (4, 20)),
+            (right_leg, |c| c.back, (8, 20)),
+        ],
+        View::Right => vec![
+            (head, |c| c.right, (4, 0)),
+            (body, |c| c.right, (6, 8)),
+            (right_leg, |c| c.right, (6, 20)),
+            (right_arm, |c| c.right, (6, 8)),
+        ],
+        View::Left => vec![
+            (head, |c| c.left, (4, 0)),
+            (body, |c| c.left, (6, 8)),
+            (left_leg, |c| c.left, (6, 20)),
+            (left_arm, |c| c.left, (6, 8)),
+        ],
+    }
+}
+
+/// Draws the part at `scale` canvas pixels per texel.
+fn draw_part(canvas: &mut RgbaImage, skin: &RgbaImage, part: Part, region: FaceSelector, x: u32, y: u32, scale: u32) {
+    draw_region(canvas, skin, region(&part.base), x, y, scale);
+    if let Some(overlay) = part.overlay {
+        draw_region(canvas, skin, region(&overlay), x, y, scale);
+    }
+}
+
+fn draw_region(canvas: &mut RgbaImage, skin: &RgbaImage, region: TexRegion, x: u32, y: u32, scale: u32) {
+    let (ox, oy) = region.origin;
+    let (width, height) = region.size;
+
+    for dy in 0..height * scale {
+        for dx in 0..width * scale {
+            let source = skin.get_pixel(ox + dx / scale, oy + dy / scale);
+            canvas.get_pixel_mut(x + dx, y + dy).blend(source);
+        }
+    }
+}
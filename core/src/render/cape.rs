@@ -0,0 +1,29 @@
+use image::{imageops, RgbaImage};
+
+/// The width of a cape texture at the usual resolution. Each frame is half as tall as it is wide.
+const TEXTURE_WIDTH: u32 = 64;
+
+/// Where the front of the cape sits in a frame at the usual resolution: x, y, width, height.
+const FRONT: (u32, u32, u32, u32) = (1, 1, 10, 16);
+
+/// Cuts the front of the cape out of each frame of a cape texture. Animated capes stack their
+/// frames top to bottom, each with the 2:1 aspect ratio of a still cape, so the number of frames
+/// follows from the texture's height; rows left over below the last whole frame are ignored.
+///
+/// Returns `None` if the texture is not a multiple of the usual width or too short to hold a
+/// single frame.
+pub fn cape_frames(texture: &RgbaImage) -> Option<Vec<RgbaImage>> {
+    let (width, height) = texture.dimensions();
+    if width == 0 || !width.is_multiple_of(TEXTURE_WIDTH) || height < width / 2 {
+        return None;
+    }
+
+    let unit = width / TEXTURE_WIDTH;
+    let frame_height = width / 2;
+    let (x, y, front_width, front_height) = FRONT;
+
+    let frames = (0..height / frame_height)
+        .map(|frame| imageops::crop_imm(texture, x * unit, frame * frame_height + y * unit, front_width * unit, front_height * unit).to_image())
+        .collect();
+    Some(frames)
+}
@@ -0,0 +1,50 @@
+use image::{Rgb, Rgba, RgbaImage, RgbImage};
+
+use super::{draw_border, rescale, text};
+
+const FACE_SIZE: u32 = 32;
+const PADDING: u32 = 8;
+const LINE_SPACING: u32 = 2;
+/// Space between a statistic's label and its value.
+const VALUE_GAP: u32 = 6;
+
+const BACKGROUND: Rgba<u8> = Rgba([33, 33, 33, 255]);
+const FACE_BORDER: Rgb<u8> = Rgb([85, 85, 85]);
+const NAME_COLOR: Rgba<u8> = Rgba([255, 255, 85, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([170, 170, 170, 255]);
+const VALUE_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Renders a card with the face on the left and the name above a column of labeled values on
+/// the right, each value aligned past the widest label.
+pub fn render_card(face: &RgbImage, name: &str, lines: &[(String, String)]) -> RgbaImage {
+    let line_height = text::LINE_HEIGHT + LINE_SPACING;
+
+    let label_width = lines.iter().map(|(label, _)| text::measure(label)).max().unwrap_or(0);
+    let value_width = lines.iter().map(|(_, value)| text::measure(value)).max().unwrap_or(0);
+    let text_width = text::measure(name).max(label_width + VALUE_GAP + value_width);
+    let text_height = line_height * (lines.len() as u32 + 1) + LINE_SPACING;
+
+    let text_x = PADDING + FACE_SIZE + PADDING;
+    let width = text_x + text_width + PADDING;
+    let height = 2 * PADDING + FACE_SIZE.max(text_height);
+
+    let mut card = RgbaImage::from_pixel(width, height, BACKGROUND);
+
+    let mut face = rescale(face, (FACE_SIZE / face.width()).max(1));
+    draw_border(&mut face, FACE_BORDER, 1);
+    for (x, y, pixel) in face.enumerate_pixels() {
+        let [r, g, b] = pixel.0;
+        card.put_pixel(PADDING + x, PADDING + y, Rgba([r, g, b, 255]));
+    }
+
+    text::draw(&mut card, name, text_x, PADDING, 1, NAME_COLOR);
+
+    let mut y = PADDING + line_height + LINE_SPACING;
+    for (label, value) in lines {
+        text::draw(&mut card, label, text_x, y, 1, LABEL_COLOR);
+        text::draw(&mut card, value, text_x + label_width + VALUE_GAP, y, 1, VALUE_COLOR);
+        y += line_height;
+    }
+
+    card
+}
@@ -0,0 +1,70 @@
+use image::{Rgb, RgbImage};
+use serde::Serialize;
+
+/// Bits of each channel that colors must share to count as the same for the dominant color, so
+/// that shading across a skin's hair or skin tone is taken as one color.
+const BUCKET_BITS: u32 = 3;
+
+/// Colors that a face's colors can be summed up by.
+#[derive(Serialize, Debug)]
+pub struct FaceColors {
+    /// The most common color, averaged over the pixels of nearly that color.
+    pub dominant: Swatch,
+    /// The average of every pixel.
+    pub average: Swatch,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Swatch {
+    /// As `#rrggbb`.
+    pub hex: String,
+    pub rgb: [u8; 3],
+}
+
+impl Swatch {
+    fn new(rgb: [u8; 3]) -> Swatch {
+        Swatch {
+            hex: format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]),
+            rgb,
+        }
+    }
+}
+
+pub fn face_colors(face: &RgbImage) -> FaceColors {
+    let shift = 8 - BUCKET_BITS;
+    let mut buckets = vec![Sum::default(); 1 << (3 * BUCKET_BITS)];
+    let mut total = Sum::default();
+
+    for pixel in face.pixels() {
+        let [r, g, b] = pixel.0.map(|c| (c >> shift) as usize);
+        buckets[(r << (2 * BUCKET_BITS)) | (g << BUCKET_BITS) | b].add(pixel);
+        total.add(pixel);
+    }
+
+    let dominant = buckets.iter().max_by_key(|bucket| bucket.count).unwrap();
+    FaceColors {
+        dominant: Swatch::new(dominant.mean()),
+        average: Swatch::new(total.mean()),
+    }
+}
+
+#[derive(Default, Copy, Clone)]
+struct Sum {
+    channels: [u32; 3],
+    count: u32,
+}
+
+impl Sum {
+    #[inline]
+    fn add(&mut self, pixel: &Rgb<u8>) {
+        for (sum, &c) in self.channels.iter_mut().zip(pixel.0.iter()) {
+            *sum += c as u32;
+        }
+        self.count += 1;
+    }
+
+    fn mean(&self) -> [u8; 3] {
+        let count = self.count.max(1);
+        self.channels.map(|sum| ((sum + count / 2) / count) as u8)
+    }
+}
@@ -0,0 +1,60 @@
+use image::{ImageBuffer, Rgb, RgbImage};
+use serde::Serialize;
+
+/// Summed channel difference up to which two pixels count as the same, so that colors nudged
+/// slightly to dodge an exact comparison still match.
+const TOLERANCE: u32 = 24;
+const HIGHLIGHT: Rgb<u8> = Rgb([255, 0, 64]);
+
+/// How alike two images of the same size are.
+#[derive(Serialize, Debug)]
+pub struct Similarity {
+    /// From 0 for opposite colors everywhere to 1 for identical images.
+    pub score: f32,
+    /// Pixels differing by more than the tolerance.
+    pub differing_pixels: u32,
+    pub pixels: u32,
+}
+
+pub fn compare(a: &RgbImage, b: &RgbImage) -> Similarity {
+    let mut total = 0;
+    let mut differing_pixels = 0;
+    for (a, b) in a.pixels().zip(b.pixels()) {
+        let difference = difference(a, b);
+        total += difference;
+        if difference > TOLERANCE {
+            differing_pixels += 1;
+        }
+    }
+
+    let pixels = a.width() * a.height();
+    Similarity {
+        score: 1.0 - total as f32 / (pixels * 3 * 255) as f32,
+        differing_pixels,
+        pixels,
+    }
+}
+
+/// Renders `a` faded towards gray, with the pixels that differ in `b` highlighted more strongly
+/// the more they differ.
+pub fn render_diff(a: &RgbImage, b: &RgbImage) -> RgbImage {
+    ImageBuffer::from_fn(a.width(), a.height(), |x, y| {
+        let (pixel, other) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        let [r, g, b] = pixel.0.map(|c| c as f32);
+        let faded = (0.299 * r + 0.587 * g + 0.114 * b) / 3.0 + 170.0;
+
+        let difference = difference(pixel, other);
+        // Even slight differences are highlighted plainly enough to spot.
+        let strength = if difference > TOLERANCE {
+            (difference as f32 / (3.0 * 255.0)).sqrt().max(0.5)
+        } else {
+            0.0
+        };
+        Rgb(HIGHLIGHT.0.map(|highlight| (faded + (highlight as f32 - faded) * strength).round() as u8))
+    })
+}
+
+#[inline]
+fn difference(a: &Rgb<u8>, b: &Rgb<u8>) -> u32 {
+    a.0.iter().zip(b.0.iter()).map(|(&a, &b)| (a as i32 - b as i32).unsigned_abs()).sum()
+}
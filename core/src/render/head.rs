@@ -0,0 +1,352 @@
+use image::{Pixel, Rgba, RgbaImage};
+
+use crate::skin::{CuboidTex, Skin, TexRegion};
+
+/// Per-face brightness, loosely following the game's directional block shading.
+const SHADE_TOP: f32 = 1.0;
+const SHADE_FRONT_BACK: f32 = 0.9;
+const SHADE_SIDE: f32 = 0.75;
+const SHADE_BOTTOM: f32 = 0.6;
+
+const HALF_EXTENT: f32 = 0.5;
+/// The hat stands half a skin pixel proud of the head on every side, as the game draws it.
+const HAT_HALF_EXTENT: f32 = HALF_EXTENT * 9.0 / 8.0;
+
+/// How many directions [`render_head_compass`] renders the head turned towards.
+pub const COMPASS_DIRECTIONS: u32 = 8;
+
+type Vec3 = [f32; 3];
+/// Screen-space bounds as `(min, max)` corners.
+type Bounds = ((f32, f32), (f32, f32));
+
+/// An orthographic camera orbiting the head. A yaw and pitch of zero looks straight at the face;
+/// positive yaw swings towards the player's left side and positive pitch looks down from above.
+struct Camera {
+    forward: Vec3,
+    right: Vec3,
+    up: Vec3,
+}
+
+impl Camera {
+    fn new(yaw: f32, pitch: f32) -> Camera {
+        let (sin_yaw, cos_yaw) = yaw.to_radians().sin_cos();
+        let (sin_pitch, cos_pitch) = pitch.to_radians().sin_cos();
+
+        Camera {
+            forward: [-sin_yaw * cos_pitch, -sin_pitch, -cos_yaw * cos_pitch],
+            right: [cos_yaw, 0.0, -sin_yaw],
+            up: [-sin_yaw * sin_pitch, cos_pitch, -cos_yaw * sin_pitch],
+        }
+    }
+
+    /// The screen-space bounds of a cube with the given half extent.
+    fn project_bounds(&self, extent: f32) -> Bounds {
+        let mut min = (f32::MAX, f32::MAX);
+        let mut max = (f32::MIN, f32::MIN);
+
+        for corner in 0..8 {
+            let point = [
+                if corner & 1 == 0 { -extent } else { extent },
+                if corner & 2 == 0 { -extent } else { extent },
+                if corner & 4 == 0 { -extent } else { extent },
+            ];
+            let (x, y) = (dot(point, self.right), dot(point, self.up));
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+
+        (min, max)
+    }
+
+    fn ray(&self, x: f32, y: f32) -> Ray {
+        let origin = [
+            x * self.right[0] + y * self.up[0] - 4.0 * self.forward[0],
+            x * self.right[1] + y * self.up[1] - 4.0 * self.forward[1],
+            x * self.right[2] + y * self.up[2] - 4.0 * self.forward[2],
+        ];
+        Ray { origin, direction: self.forward }
+    }
+}
+
+struct Ray {
+    origin: Vec3,
+    direction: Vec3,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Face {
+    Front,
+    Back,
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Face {
+    #[inline]
+    fn region(self, cuboid: &CuboidTex) -> TexRegion {
+        match self {
+            Face::Front => cuboid.front,
+            Face::Back => cuboid.back,
+            Face::Left => cuboid.left,
+            Face::Right => cuboid.right,
+            Face::Top => cuboid.top,
+            Face::Bottom => cuboid.bottom,
+        }
+    }
+
+    #[inline]
+    fn shade(self) -> f32 {
+        match self {
+            Face::Top => SHADE_TOP,
+            Face::Front | Face::Back => SHADE_FRONT_BACK,
+            Face::Left | Face::Right => SHADE_SIDE,
+            Face::Bottom => SHADE_BOTTOM,
+        }
+    }
+}
+
+/// Where a ray crossed the surface of a cube: the face it crossed and the normalized texture
+/// coordinates on it, oriented the same way the game maps cuboid faces.
+struct Hit {
+    face: Face,
+    uv: (f32, f32),
+}
+
+/// Where a ray entered and left a cube, if it hit it at all. World space has +X to the viewer's
+/// right (the player's left), +Y up, and +Z out of the face.
+fn intersect(ray: &Ray, extent: f32) -> Option<(Hit, Hit)> {
+    let mut near = f32::MIN;
+    let mut far = f32::MAX;
+    let mut near_axis = 0;
+    let mut far_axis = 0;
+
+    for axis in 0..3 {
+        let (origin, direction) = (ray.origin[axis], ray.direction[axis]);
+        if direction.abs() < f32::EPSILON {
+            if origin.abs() > extent {
+                return None;
+            }
+            continue;
+        }
+
+        let t1 = (-extent - origin) / direction;
+        let t2 = (extent - origin) / direction;
+        let (t_min, t_max) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+
+        if t_min > near {
+            near = t_min;
+            near_axis = axis;
+        }
+        if t_max < far {
+            far = t_max;
+            far_axis = axis;
+        }
+    }
+
+    if near > far {
+        return None;
+    }
+
+    Some((surface(ray, near, near_axis, extent, true), surface(ray, far, far_axis, extent, false)))
+}
+
+/// Where the ray crosses the face of the cube along `axis` at `t`, on the way in if `entering`.
+fn surface(ray: &Ray, t: f32, axis: usize, extent: f32, entering: bool) -> Hit {
+    let point = [
+        ray.origin[0] + ray.direction[0] * t,
+        ray.origin[1] + ray.direction[1] * t,
+        ray.origin[2] + ray.direction[2] * t,
+    ];
+    let [x, y, z] = point.map(|c| (c / extent).clamp(-1.0, 1.0) * 0.5 + 0.5);
+
+    // Rays enter through the faces they travel against and leave through those they travel along.
+    let positive = (ray.direction[axis] < 0.0) == entering;
+    let (face, uv) = match (axis, positive) {
+        (0, true) => (Face::Left, (1.0 - z, 1.0 - y)),
+        (0, false) => (Face::Right, (z, 1.0 - y)),
+        (1, true) => (Face::Top, (x, z)),
+        (1, false) => (Face::Bottom, (x, z)),
+        (_, true) => (Face::Front, (x, 1.0 - y)),
+        (_, false) => (Face::Back, (1.0 - x, 1.0 - y)),
+    };
+
+    Hit { face, uv }
+}
+
+#[inline]
+fn sample(image: &RgbaImage, region: TexRegion, uv: (f32, f32)) -> Rgba<u8> {
+    let x = ((uv.0 * region.size.0 as f32) as u32).min(region.size.0 - 1);
+    let y = ((uv.1 * region.size.1 as f32) as u32).min(region.size.1 - 1);
+    *image.get_pixel(region.origin.0 + x, region.origin.1 + y)
+}
+
+#[inline]
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Renders the head cuboid from the given angles (in degrees), fitted to a `size` x `size`
+/// image with a transparent background. The hat layer is drawn as a slightly larger cube around
+/// the head, so that what stands out of it keeps its depth. Edges are anti-aliased by rendering at `supersampling` times the size along each axis.
+pub fn render_head(skin: &Skin, size: u32, yaw: f32, pitch: f32, supersampling: u32) -> RgbaImage {
+    let camera = Camera::new(yaw, pitch);
+    let bounds = camera.project_bounds(HAT_HALF_EXTENT);
+    render_fitted(skin, size, &camera, bounds, supersampling)
+}
+
+/// Renders the head turned towards each of the [`COMPASS_DIRECTIONS`] in turn, clockwise from
+/// north, into a sprite sheet of `size` x `size` frames side by side. The head is seen from the
+/// south of a map with north up, looking down at `pitch`, so that a marker can show the frame
+/// nearest its player's heading. Every frame shares one scale so that the head doesn't grow and
+/// shrink as it turns.
+pub fn render_head_compass(skin: &Skin, size: u32, pitch: f32, supersampling: u32) -> RgbaImage {
+    // Seen corner-on, the head is at its widest.
+    let bounds = Camera::new(45.0, pitch).project_bounds(HAT_HALF_EXTENT);
+
+    let mut sheet = RgbaImage::new(size * COMPASS_DIRECTIONS, size);
+    for direction in 0..COMPASS_DIRECTIONS {
+        // A player heading north turns their back to the viewer, and one heading east their
+        // right side.
+        let heading = direction as f32 * 360.0 / COMPASS_DIRECTIONS as f32;
+        let camera = Camera::new(heading - 180.0, pitch);
+        let frame = render_fitted(skin, size, &camera, bounds, supersampling);
+        image::imageops::replace(&mut sheet, &frame, direction * size, 0);
+    }
+    sheet
+}
+
+fn render_fitted(skin: &Skin, size: u32, camera: &Camera, bounds: Bounds, supersampling: u32) -> RgbaImage {
+    let image = rasterize(skin, size * supersampling, camera, bounds);
+    if supersampling > 1 {
+        downsample(&image, supersampling)
+    } else {
+        image
+    }
+}
+
+/// Rasterizes the head as `camera` sees it, scaled so that `bounds` fill the image.
+fn rasterize(skin: &Skin, size: u32, camera: &Camera, bounds: Bounds) -> RgbaImage {
+    let format = skin.format;
+
+    let ((min_x, min_y), (max_x, max_y)) = bounds;
+    let scale = size as f32 / (max_x - min_x).max(max_y - min_y);
+    let (center_x, center_y) = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+
+    RgbaImage::from_fn(size, size, |px, py| {
+        let x = (px as f32 + 0.5 - size as f32 / 2.0) / scale + center_x;
+        let y = (size as f32 / 2.0 - (py as f32 + 0.5)) / scale + center_y;
+
+        // The head sits wholly inside the hat, so rays that miss the hat miss the head too.
+        let ray = camera.ray(x, y);
+        let (hat_near, hat_far) = match intersect(&ray, HAT_HALF_EXTENT) {
+            Some(hits) => hits,
+            None => return Rgba([0, 0, 0, 0]),
+        };
+
+        // Through gaps in the near side of the hat shows the head or, past its edges, the inside
+        // of the far side of the hat.
+        let mut color = match intersect(&ray, HALF_EXTENT) {
+            Some((head, _)) => shaded(sample(&skin.image, head.face.region(&format.head), head.uv), head.face),
+            None => shaded(sample(&skin.image, hat_far.face.region(&format.hat), hat_far.uv), hat_far.face),
+        };
+        color.blend(&shaded(sample(&skin.image, hat_near.face.region(&format.hat), hat_near.uv), hat_near.face));
+        color
+    })
+}
+
+#[inline]
+fn shaded(color: Rgba<u8>, face: Face) -> Rgba<u8> {
+    let shade = face.shade();
+    color.map_with_alpha(|c| (c as f32 * shade).round() as u8, |a| a)
+}
+
+/// Box-filters the image down by `factor`, weighting colors by alpha so that transparent
+/// background samples don't darken the edges.
+fn downsample(image: &RgbaImage, factor: u32) -> RgbaImage {
+    let (width, height) = (image.width() / factor, image.height() / factor);
+    let samples = (factor * factor) as f32;
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let mut color = [0.0f32; 3];
+        let mut alpha = 0.0;
+
+        for sy in 0..factor {
+            for sx in 0..factor {
+                let [r, g, b, a] = image.get_pixel(x * factor + sx, y * factor + sy).0;
+                let a = a as f32;
+                color[0] += r as f32 * a;
+                color[1] += g as f32 * a;
+                color[2] += b as f32 * a;
+                alpha += a;
+            }
+        }
+
+        if alpha == 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+
+        let [r, g, b] = color.map(|c| (c / alpha).round() as u8);
+        Rgba([r, g, b, (alpha / samples).round() as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::skin::Model;
+
+    use super::*;
+
+    const FRONT: Rgba<u8> = Rgba([200, 0, 0, 255]);
+    const TOP: Rgba<u8> = Rgba([0, 200, 0, 255]);
+
+    /// A skin whose head is blue but for a red front and a green top, without a hat.
+    fn skin() -> Skin {
+        let image = RgbaImage::from_fn(64, 64, |x, y| match (x, y) {
+            (8..=15, 8..=15) => FRONT,
+            (8..=15, 0..=7) => TOP,
+            (0..=31, 0..=15) => Rgba([0, 0, 200, 255]),
+            _ => Rgba([0, 0, 0, 0]),
+        });
+        Skin::new(image, Model::Wide).unwrap()
+    }
+
+    #[test]
+    fn looks_straight_at_the_face() {
+        let head = render_head(&skin(), 18, 0.0, 0.0, 1);
+        assert_eq!(head.dimensions(), (18, 18));
+        assert_eq!(*head.get_pixel(9, 9), shaded(FRONT, Face::Front));
+        // The hat is empty, so the space it would take up is left clear.
+        assert_eq!(head.get_pixel(0, 0)[3], 0);
+    }
+
+    #[test]
+    fn looks_down_on_the_top() {
+        let head = render_head(&skin(), 18, 0.0, 90.0, 1);
+        assert_eq!(*head.get_pixel(9, 9), shaded(TOP, Face::Top));
+    }
+
+    #[test]
+    fn supersampling_softens_edges() {
+        let sharp = render_head(&skin(), 32, 30.0, 20.0, 1);
+        let smooth = render_head(&skin(), 32, 30.0, 20.0, 4);
+        assert_eq!(smooth.dimensions(), (32, 32));
+
+        let partial = |image: &RgbaImage| image.pixels().any(|pixel| pixel[3] > 0 && pixel[3] < 255);
+        assert!(!partial(&sharp));
+        assert!(partial(&smooth));
+    }
+
+    #[test]
+    fn compass_frames_turn_the_head() {
+        let sheet = render_head_compass(&skin(), 16, 30.0, 1);
+        assert_eq!(sheet.dimensions(), (16 * COMPASS_DIRECTIONS, 16));
+
+        let frame = |direction: u32| image::imageops::crop_imm(&sheet, direction * 16, 0, 16, 16).to_image();
+        // Heading south, the player faces the viewer; heading north, they turn their back.
+        let (south, north) = (frame(COMPASS_DIRECTIONS / 2), frame(0));
+        assert_eq!(south.get_pixel(8, 10)[0], shaded(FRONT, Face::Front)[0]);
+        assert_ne!(north, south);
+    }
+}
@@ -0,0 +1,129 @@
+use image::{Pixel, Rgba, RgbaImage};
+
+use crate::skin::{CuboidTex, Skin, TexRegion};
+
+use super::{text, LABEL_COLOR};
+
+/// Texels left between the faces of a cuboid and between cuboids.
+const GAP: u32 = 2;
+
+/// Fills each face before its texels are drawn so that transparent regions still show their bounds.
+const BACKDROP: Rgba<u8> = Rgba([0, 0, 0, 48]);
+
+/// A labeled cuboid to lay out.
+type Cell = (&'static str, CuboidTex);
+
+/// Renders every cuboid of the skin unfolded into a net, each face apart from the others and
+/// labeled by part, with base layers on the left and their overlays on the right. Each texel
+/// becomes `scale` pixels square.
+pub fn render_layout(skin: &Skin, scale: u32) -> RgbaImage {
+    let format = skin.format;
+    let rows: [(Cell, Option<Cell>); 6] = [
+        (("Head", format.head), Some(("Hat", format.hat))),
+        (("Body", format.body), format.jacket.map(|jacket| ("Jacket", jacket))),
+        (("Right Arm", format.right_arm), format.right_sleeves.map(|sleeves| ("Right Sleeve", sleeves))),
+        (("Left Arm", format.left_arm), format.left_sleeves.map(|sleeves| ("Left Sleeve", sleeves))),
+        (("Right Leg", format.right_leg), format.right_pants.map(|pants| ("Right Pants", pants))),
+        (("Left Leg", format.left_leg), format.left_pants.map(|pants| ("Left Pants", pants))),
+    ];
+
+    let metrics = Metrics::new(scale);
+
+    let base_width = rows.iter().map(|(base, _)| metrics.cell_size(base).0).max().unwrap_or(0);
+    let overlay_width = rows.iter().filter_map(|(_, overlay)| overlay.map(|overlay| metrics.cell_size(&overlay).0)).max();
+    let row_heights: Vec<u32> = rows.iter()
+        .map(|(base, overlay)| {
+            let overlay_height = overlay.map_or(0, |overlay| metrics.cell_size(&overlay).1);
+            metrics.cell_size(base).1.max(overlay_height)
+        })
+        .collect();
+
+    let width = metrics.gap + base_width + metrics.gap + overlay_width.map_or(0, |width| width + metrics.gap);
+    let height = metrics.gap + row_heights.iter().map(|height| height + metrics.gap).sum::<u32>();
+    let mut canvas = RgbaImage::new(width, height);
+
+    let mut y = metrics.gap;
+    for ((base, overlay), row_height) in rows.iter().zip(row_heights) {
+        metrics.draw_cell(&mut canvas, &skin.image, base, metrics.gap, y);
+        if let Some(overlay) = overlay {
+            metrics.draw_cell(&mut canvas, &skin.image, overlay, 2 * metrics.gap + base_width, y);
+        }
+        y += row_height + metrics.gap;
+    }
+
+    canvas
+}
+
+/// Pixel sizes at a given scale. Labels are drawn at half the scale of the texels so that they
+/// don't dwarf the thinner limbs.
+struct Metrics {
+    scale: u32,
+    text_scale: u32,
+    gap: u32,
+    label_height: u32,
+}
+
+impl Metrics {
+    fn new(scale: u32) -> Metrics {
+        let text_scale = (scale / 2).max(1);
+        Metrics {
+            scale,
+            text_scale,
+            gap: GAP * scale,
+            label_height: (text::LINE_HEIGHT + 2) * text_scale,
+        }
+    }
+
+    fn cell_size(&self, (label, cuboid): &Cell) -> (u32, u32) {
+        let (net_width, net_height) = self.net_size(cuboid);
+        let label_width = (text::measure(label) + 1) * self.text_scale;
+        (net_width.max(label_width), self.label_height + net_height)
+    }
+
+    fn net_size(&self, cuboid: &CuboidTex) -> (u32, u32) {
+        let (width, height) = cuboid.front.size;
+        let depth = cuboid.left.size.0;
+        ((2 * depth + 2 * width) * self.scale + 3 * self.gap, (depth + height) * self.scale + self.gap)
+    }
+
+    fn draw_cell(&self, canvas: &mut RgbaImage, skin: &RgbaImage, (label, cuboid): &Cell, x: u32, y: u32) {
+        text::draw(canvas, label, x, y, self.text_scale, LABEL_COLOR);
+        self.draw_net(canvas, skin, cuboid, x, y + self.label_height);
+    }
+
+    /// Draws the faces of a cuboid arranged as they are in the skin texture, with top and bottom
+    /// above the front and left faces, and the right, front, left and back faces in a row.
+    fn draw_net(&self, canvas: &mut RgbaImage, skin: &RgbaImage, cuboid: &CuboidTex, x: u32, y: u32) {
+        let width = cuboid.front.size.0 * self.scale;
+        let depth = cuboid.left.size.0 * self.scale;
+
+        let columns = [0, depth + self.gap, depth + width + 2 * self.gap, 2 * depth + width + 3 * self.gap];
+        let side_y = y + depth + self.gap;
+
+        let faces = [
+            (cuboid.top, x + columns[1], y),
+            (cuboid.bottom, x + columns[2], y),
+            (cuboid.right, x + columns[0], side_y),
+            (cuboid.front, x + columns[1], side_y),
+            (cuboid.left, x + columns[2], side_y),
+            (cuboid.back, x + columns[3], side_y),
+        ];
+
+        for (region, x, y) in faces {
+            self.draw_face(canvas, skin, region, x, y);
+        }
+    }
+
+    fn draw_face(&self, canvas: &mut RgbaImage, skin: &RgbaImage, region: TexRegion, x: u32, y: u32) {
+        let (ox, oy) = region.origin;
+        let (width, height) = region.size;
+
+        for py in 0..height * self.scale {
+            for px in 0..width * self.scale {
+                let mut pixel = BACKDROP;
+                pixel.blend(skin.get_pixel(ox + px / self.scale, oy + py / self.scale));
+                canvas.put_pixel(x + px, y + py, pixel);
+            }
+        }
+    }
+}
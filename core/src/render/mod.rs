@@ -0,0 +1,423 @@
+use image::{ImageBuffer, Pixel, Rgb, Rgba, RgbaImage, RgbImage};
+use image::imageops::FilterType;
+
+use crate::skin::{self, Skin};
+
+mod ansi;
+mod banner;
+mod body;
+mod cape;
+mod card;
+mod colors;
+mod diff;
+mod head;
+mod layout;
+mod svg;
+mod text;
+mod upscale;
+
+pub use ansi::render_ansi;
+pub use banner::render_banner;
+pub use body::{render_chibi, render_preview, render_views, View};
+pub use cape::cape_frames;
+pub use card::render_card;
+pub use colors::{face_colors, FaceColors, Swatch};
+pub use diff::{compare, render_diff, Similarity};
+pub use head::{render_head, render_head_compass, COMPASS_DIRECTIONS};
+pub use layout::render_layout;
+pub use svg::render_svg;
+pub use upscale::{hq2x, upscale, xbr};
+
+const LABEL_BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 64]);
+const LABEL_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+pub fn rescale<P: Pixel + 'static>(image: &ImageBuffer<P, Vec<P::Subpixel>>, factor: u32) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let (width, height) = image.dimensions();
+    let (scaled_width, scaled_height) = (width * factor, height * factor);
+
+    ImageBuffer::from_fn(scaled_width, scaled_height, |scaled_x, scaled_y| {
+        let x = scaled_x / factor;
+        let y = scaled_y / factor;
+        *image.get_pixel(x, y)
+    })
+}
+
+/// Area-averaged downscale: each output pixel is the coverage-weighted mean of the source
+/// pixels it overlaps, so uneven ratios like 8 -> 3 don't drop rows.
+pub fn downscale(image: &RgbImage, width: u32, height: u32) -> RgbImage {
+    let (src_width, src_height) = image.dimensions();
+    let scale_x = src_width as f32 / width as f32;
+    let scale_y = src_height as f32 / height as f32;
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let (x0, x1) = (x as f32 * scale_x, (x + 1) as f32 * scale_x);
+        let (y0, y1) = (y as f32 * scale_y, (y + 1) as f32 * scale_y);
+
+        let mut sum = [0.0f32; 3];
+        let mut total = 0.0;
+
+        for sy in (y0 as u32)..(y1.ceil() as u32).min(src_height) {
+            let wy = overlap(y0, y1, sy);
+            for sx in (x0 as u32)..(x1.ceil() as u32).min(src_width) {
+                let weight = overlap(x0, x1, sx) * wy;
+                let pixel = image.get_pixel(sx, sy);
+                for (sum, channel) in sum.iter_mut().zip(pixel.0.iter()) {
+                    *sum += *channel as f32 * weight;
+                }
+                total += weight;
+            }
+        }
+
+        let [r, g, b] = sum.map(|channel| (channel / total).round() as u8);
+        image::Rgb([r, g, b])
+    })
+}
+
+#[inline]
+fn overlap(start: f32, end: f32, pixel: u32) -> f32 {
+    let (pixel_start, pixel_end) = (pixel as f32, (pixel + 1) as f32);
+    (end.min(pixel_end) - start.max(pixel_start)).max(0.0)
+}
+
+/// Crops the fully transparent rows and columns from the edges of the image, leaving an image
+/// with nothing visible in it as it is.
+pub fn trim(image: RgbaImage) -> RgbaImage {
+    let mut visible = image.enumerate_pixels().filter(|(_, _, pixel)| pixel[3] > 0);
+    let (x, y, _) = match visible.next() {
+        Some(pixel) => pixel,
+        None => return image,
+    };
+    let ((min_x, min_y), (max_x, max_y)) = visible.fold(((x, y), (x, y)), |((min_x, min_y), (max_x, max_y)), (x, y, _)| {
+        ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y)))
+    });
+
+    if (min_x, min_y, max_x + 1, max_y + 1) == (0, 0, image.width(), image.height()) {
+        return image;
+    }
+    image::imageops::crop_imm(&image, min_x, min_y, max_x - min_x + 1, max_y - min_y + 1).to_image()
+}
+
+/// Masks the image to an inscribed circle, with edge pixels given partial alpha by coverage.
+pub fn mask_circle(image: &RgbImage) -> RgbaImage {
+    const SAMPLES: u32 = 4;
+
+    let (width, height) = image.dimensions();
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let radius_sq = cx.min(cy) * cx.min(cy);
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let mut covered = 0;
+        for sy in 0..SAMPLES {
+            for sx in 0..SAMPLES {
+                let dx = x as f32 + (sx as f32 + 0.5) / SAMPLES as f32 - cx;
+                let dy = y as f32 + (sy as f32 + 0.5) / SAMPLES as f32 - cy;
+                if dx * dx + dy * dy <= radius_sq {
+                    covered += 1;
+                }
+            }
+        }
+
+        let alpha = (covered * 255 / (SAMPLES * SAMPLES)) as u8;
+        image.get_pixel(x, y).to_rgba().map_with_alpha(|c| c, |_| alpha)
+    })
+}
+
+pub fn grayscale(image: &mut RgbImage) {
+    for pixel in image.pixels_mut() {
+        let [r, g, b] = pixel.0.map(|c| c as f32);
+        let luma = (0.299 * r + 0.587 * g + 0.114 * b).round() as u8;
+        *pixel = Rgb([luma, luma, luma]);
+    }
+}
+
+pub fn sepia(image: &mut RgbImage) {
+    for pixel in image.pixels_mut() {
+        let [r, g, b] = pixel.0.map(|c| c as f32);
+        let sr = 0.393 * r + 0.769 * g + 0.189 * b;
+        let sg = 0.349 * r + 0.686 * g + 0.168 * b;
+        let sb = 0.272 * r + 0.534 * g + 0.131 * b;
+        *pixel = Rgb([sr, sg, sb].map(|c| c.min(255.0) as u8));
+    }
+}
+
+#[inline]
+pub fn invert(image: &mut RgbImage) {
+    image::imageops::invert(image);
+}
+
+/// Blends `color` into every pixel by `strength`, either multiplying it in or using the overlay blend mode.
+pub fn tint(image: &mut RgbImage, color: Rgb<u8>, overlay: bool, strength: f32) {
+    for pixel in image.pixels_mut() {
+        for (channel, tint) in pixel.0.iter_mut().zip(color.0.iter()) {
+            let base = *channel as f32 / 255.0;
+            let tint = *tint as f32 / 255.0;
+
+            let blended = if !overlay {
+                base * tint
+            } else if base < 0.5 {
+                2.0 * base * tint
+            } else {
+                1.0 - 2.0 * (1.0 - base) * (1.0 - tint)
+            };
+
+            let result = base + (blended - base) * strength;
+            *channel = (result * 255.0).round() as u8;
+        }
+    }
+}
+
+/// Scales every channel by `brightness`, then its distance from mid gray by `contrast`, then
+/// raises it to the power of `1 / gamma`, with 1 leaving the image as it is for each.
+pub fn adjust(image: &mut RgbImage, brightness: f32, contrast: f32, gamma: f32) {
+    let levels: Vec<u8> = (0..=255u8)
+        .map(|c| {
+            let c = c as f32 / 255.0 * brightness;
+            let c = ((c - 0.5) * contrast + 0.5).clamp(0.0, 1.0);
+            (c.powf(1.0 / gamma) * 255.0).round() as u8
+        })
+        .collect();
+
+    for pixel in image.pixels_mut() {
+        pixel.0 = pixel.0.map(|c| levels[c as usize]);
+    }
+}
+
+/// Snaps every pixel to the nearest color of `palette`, first offset by a 4x4 Bayer matrix if
+/// `dither` so that colors between two of the palette's come out as a pattern of both.
+pub fn quantize(image: &mut RgbImage, palette: &[Rgb<u8>], dither: bool) {
+    const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+    const DITHER_SPREAD: f32 = 64.0;
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let offset = if dither {
+            ((BAYER[y as usize % 4][x as usize % 4] as f32 + 0.5) / 16.0 - 0.5) * DITHER_SPREAD
+        } else {
+            0.0
+        };
+        let target = pixel.0.map(|c| c as f32 + offset);
+
+        let nearest = palette.iter().min_by(|a, b| {
+            color_distance(target, a).total_cmp(&color_distance(target, b))
+        });
+        if let Some(nearest) = nearest {
+            *pixel = *nearest;
+        }
+    }
+}
+
+/// Squared distance weighted roughly by how sensitive the eye is to each channel.
+#[inline]
+fn color_distance(target: [f32; 3], color: &Rgb<u8>) -> f32 {
+    let [r, g, b] = [0, 1, 2].map(|i| target[i] - color.0[i] as f32);
+    2.0 * r * r + 4.0 * g * g + 3.0 * b * b
+}
+
+pub fn draw_border(image: &mut RgbImage, color: Rgb<u8>, width: u32) {
+    let (image_width, image_height) = image.dimensions();
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let distance = x.min(y).min(image_width - 1 - x).min(image_height - 1 - y);
+        if distance < width {
+            *pixel = color;
+        }
+    }
+}
+
+/// Like [`draw_border`], but following the circle used by [`mask_circle`].
+pub fn draw_circle_border(image: &mut RgbImage, color: Rgb<u8>, width: u32) {
+    let (image_width, image_height) = image.dimensions();
+    let (cx, cy) = (image_width as f32 / 2.0, image_height as f32 / 2.0);
+    let inner_radius = cx.min(cy) - width as f32;
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let dx = x as f32 + 0.5 - cx;
+        let dy = y as f32 + 0.5 - cy;
+        if (dx * dx + dy * dy).sqrt() >= inner_radius {
+            *pixel = color;
+        }
+    }
+}
+
+/// Composites a badge onto the bottom right corner of the face, scaled to a third of its width.
+pub fn draw_badge(face: &RgbaImage, badge: &RgbaImage) -> RgbaImage {
+    let (face_width, face_height) = face.dimensions();
+    let width = (face_width / 3).max(1);
+    let height = (badge.height() * width / badge.width()).clamp(1, face_height);
+
+    // Pixel art badges stay crisp when enlarged, but need smoothing when shrunk.
+    let filter = if width >= badge.width() { FilterType::Nearest } else { FilterType::Triangle };
+    let badge = image::imageops::resize(badge, width, height, filter);
+
+    let mut result = face.clone();
+    image::imageops::overlay(&mut result, &badge, face_width - width, face_height - height);
+    result
+}
+
+/// Composites a dot outlined in black onto the top right corner of the face, a quarter of its
+/// width across, with edge pixels given partial alpha by coverage as in [`mask_circle`].
+pub fn draw_dot(face: &RgbaImage, color: Rgb<u8>) -> RgbaImage {
+    const SAMPLES: u32 = 4;
+
+    let (face_width, _) = face.dimensions();
+    // Thumbnails are too small for a quarter-width dot to be seen, but no wider than the face.
+    let diameter = (face_width / 4).max(2).min(face_width);
+    let radius = diameter as f32 / 2.0;
+    let outline = (radius / 4.0).max(0.5);
+    let origin = face_width - diameter;
+
+    let mut result = face.clone();
+    for y in 0..diameter {
+        for x in 0..diameter {
+            let (mut inner, mut outer) = (0, 0);
+            for sy in 0..SAMPLES {
+                for sx in 0..SAMPLES {
+                    let dx = x as f32 + (sx as f32 + 0.5) / SAMPLES as f32 - radius;
+                    let dy = y as f32 + (sy as f32 + 0.5) / SAMPLES as f32 - radius;
+                    let distance = (dx * dx + dy * dy).sqrt();
+                    if distance <= radius - outline {
+                        inner += 1;
+                    } else if distance <= radius {
+                        outer += 1;
+                    }
+                }
+            }
+
+            let samples = SAMPLES * SAMPLES;
+            let mut pixel = *result.get_pixel(origin + x, y);
+            pixel.blend(&Rgba([0, 0, 0, ((inner + outer) * 255 / samples) as u8]));
+            pixel.blend(&color.to_rgba().map_with_alpha(|c| c, |_| (inner * 255 / samples) as u8));
+            result.put_pixel(origin + x, y, pixel);
+        }
+    }
+
+    result
+}
+
+/// Composites a name-tag style label centered beneath the face, widening the canvas if the text
+/// doesn't fit. Text is drawn at one font pixel per 32 pixels of face size.
+pub fn draw_label(face: &RgbaImage, label: &str) -> RgbaImage {
+    let (face_width, face_height) = face.dimensions();
+    let scale = (face_width / 32).max(1);
+
+    let text_width = text::measure(label) * scale;
+    let text_height = text::LINE_HEIGHT * scale;
+    let padding = scale;
+
+    let box_width = text_width + 2 * padding;
+    let box_height = text_height + 2 * padding;
+
+    let width = face_width.max(box_width);
+    let height = face_height + padding + box_height;
+
+    let mut result = RgbaImage::new(width, height);
+    image::imageops::overlay(&mut result, face, (width - face_width) / 2, 0);
+
+    let box_x = (width - box_width) / 2;
+    let box_y = face_height + padding;
+    for y in box_y..box_y + box_height {
+        for x in box_x..box_x + box_width {
+            result.put_pixel(x, y, LABEL_BACKGROUND);
+        }
+    }
+
+    text::draw(&mut result, label, box_x + padding, box_y + padding, scale, LABEL_COLOR);
+
+    result
+}
+
+pub fn render_face(skin: &Skin) -> RgbImage {
+    let format = skin.format;
+
+    let face = TexView::of(format.head.front, &skin.image);
+    let hat = TexView::of(format.hat.front, &skin.image);
+
+    let mut result = ImageBuffer::new(face.width, face.height);
+
+    for y in 0..face.height {
+        for x in 0..face.width {
+            let mut face = *face.get_pixel(x, y);
+            face.blend(hat.get_pixel(x, y));
+
+            let [r, g, b, _] = face.0;
+            result.put_pixel(x, y, image::Rgb([r, g, b]));
+        }
+    }
+
+    result
+}
+
+struct TexView<'a> {
+    offset: (u32, u32),
+    width: u32,
+    height: u32,
+    image: &'a RgbaImage,
+}
+
+impl<'a> TexView<'a> {
+    #[inline]
+    fn of(region: skin::TexRegion, image: &image::RgbaImage) -> TexView<'_> {
+        TexView {
+            offset: region.origin,
+            width: region.size.0,
+            height: region.size.1,
+            image,
+        }
+    }
+
+    #[inline]
+    fn get_pixel(&self, x: u32, y: u32) -> &Rgba<u8> {
+        if x >= self.width || y >= self.height {
+            panic!("tried to access pixel at ({}; {}) which is out of bounds for {}x{} view", x, y, self.width, self.height);
+        }
+
+        let (ox, oy) = self.offset;
+        self.image.get_pixel(x + ox, y + oy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downscale_averages_whole_blocks() {
+        let image = RgbImage::from_fn(4, 4, |x, _| if x < 2 { Rgb([0, 0, 0]) } else { Rgb([200, 100, 50]) });
+        let scaled = downscale(&image, 2, 2);
+        assert_eq!(scaled.dimensions(), (2, 2));
+        assert_eq!(*scaled.get_pixel(0, 1), Rgb([0, 0, 0]));
+        assert_eq!(*scaled.get_pixel(1, 0), Rgb([200, 100, 50]));
+    }
+
+    #[test]
+    fn downscale_weighs_uneven_ratios_by_coverage() {
+        // Each of the 2 output pixels covers one and a half source pixels.
+        let image = RgbImage::from_fn(3, 1, |x, _| Rgb([[0, 90, 240][x as usize], 0, 0]));
+        let scaled = downscale(&image, 2, 1);
+        assert_eq!(*scaled.get_pixel(0, 0), Rgb([30, 0, 0]));
+        assert_eq!(*scaled.get_pixel(1, 0), Rgb([190, 0, 0]));
+    }
+
+    #[test]
+    fn downscale_to_the_same_size_is_unchanged() {
+        let image = RgbImage::from_fn(8, 8, |x, y| Rgb([x as u8 * 30, y as u8 * 30, 7]));
+        assert_eq!(downscale(&image, 8, 8), image);
+    }
+
+    #[test]
+    fn dots_fit_tiny_faces() {
+        for size in [1, 2] {
+            let face = RgbaImage::from_pixel(size, size, Rgba([255, 255, 255, 255]));
+            let dotted = draw_dot(&face, Rgb([0, 255, 0]));
+            assert_eq!(dotted.dimensions(), (size, size));
+            assert_ne!(dotted, face, "size {}", size);
+        }
+    }
+
+    #[test]
+    fn dots_sit_in_the_top_right_corner() {
+        let face = RgbaImage::from_pixel(32, 32, Rgba([255, 255, 255, 255]));
+        let dotted = draw_dot(&face, Rgb([0, 255, 0]));
+        assert_eq!(*dotted.get_pixel(28, 4), Rgba([0, 255, 0, 255]));
+        assert_eq!(*dotted.get_pixel(3, 4), Rgba([255, 255, 255, 255]));
+        assert_eq!(*dotted.get_pixel(28, 28), Rgba([255, 255, 255, 255]));
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt::Write;
+
+use image::{Rgb, RgbImage};
+
+/// Renders the face as resolution-independent SVG with one pixel per user unit. Horizontal runs
+/// of the same color are merged into a single `<rect>`. The border width is given in pixels of
+/// the source image.
+pub fn render_svg(face: &RgbImage, circle: bool, border: Option<(Rgb<u8>, u32)>) -> String {
+    let (width, height) = face.dimensions();
+    let mut svg = String::new();
+
+    write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}" shape-rendering="crispEdges">"#,
+        width, height,
+    ).unwrap();
+
+    if circle {
+        write!(
+            svg,
+            r#"<clipPath id="c"><circle cx="{0}" cy="{1}" r="{2}"/></clipPath><g clip-path="url(#c)">"#,
+            width as f32 / 2.0, height as f32 / 2.0, width.min(height) as f32 / 2.0,
+        ).unwrap();
+    } else {
+        svg.push_str("<g>");
+    }
+
+    for y in 0..height {
+        let mut x = 0;
+        while x < width {
+            let color = *face.get_pixel(x, y);
+            let run = (x..width).take_while(|&x| *face.get_pixel(x, y) == color).count() as u32;
+
+            write!(svg, r#"<rect x="{}" y="{}" width="{}" height="1" fill="{}"/>"#, x, y, run, hex(color)).unwrap();
+            x += run;
+        }
+    }
+
+    if let Some((color, stroke)) = border {
+        let inset = stroke as f32 / 2.0;
+        if circle {
+            write!(
+                svg,
+                r#"<circle cx="{}" cy="{}" r="{}" fill="none" stroke="{}" stroke-width="{}"/>"#,
+                width as f32 / 2.0, height as f32 / 2.0, width.min(height) as f32 / 2.0 - inset, hex(color), stroke,
+            ).unwrap();
+        } else {
+            write!(
+                svg,
+                r#"<rect x="{0}" y="{0}" width="{1}" height="{2}" fill="none" stroke="{3}" stroke-width="{4}"/>"#,
+                inset, width as f32 - stroke as f32, height as f32 - stroke as f32, hex(color), stroke,
+            ).unwrap();
+        }
+    }
+
+    svg.push_str("</g></svg>");
+    svg
+}
+
+#[inline]
+fn hex(color: Rgb<u8>) -> String {
+    let [r, g, b] = color.0;
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+
+    #[test]
+    fn merges_runs_of_one_color() {
+        let face = RgbImage::from_fn(3, 2, |x, y| if x < 2 || y == 1 { RED } else { BLUE });
+        let svg = render_svg(&face, false, None);
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 3 2""#));
+        assert!(svg.ends_with("</g></svg>"));
+
+        let rects: Vec<&str> = svg.split("/>").filter_map(|tag| Some(&tag[tag.find("<rect")?..])).collect();
+        assert_eq!(rects, [
+            r##"<rect x="0" y="0" width="2" height="1" fill="#ff0000""##,
+            r##"<rect x="2" y="0" width="1" height="1" fill="#0000ff""##,
+            r##"<rect x="0" y="1" width="3" height="1" fill="#ff0000""##,
+        ]);
+    }
+
+    #[test]
+    fn circles_clip_the_face_and_its_border() {
+        let face = RgbImage::from_pixel(8, 8, RED);
+        let svg = render_svg(&face, true, Some((BLUE, 2)));
+        assert!(svg.contains(r#"<clipPath id="c"><circle cx="4" cy="4" r="4"/></clipPath><g clip-path="url(#c)">"#));
+        assert!(svg.contains(r##"<circle cx="4" cy="4" r="3" fill="none" stroke="#0000ff" stroke-width="2"/>"##));
+    }
+
+    #[test]
+    fn square_borders_sit_inside_the_face() {
+        let face = RgbImage::from_pixel(8, 8, RED);
+        let svg = render_svg(&face, false, Some((BLUE, 2)));
+        assert!(!svg.contains("clipPath"));
+        assert!(svg.contains(r##"<rect x="1" y="1" width="6" height="6" fill="none" stroke="#0000ff" stroke-width="2"/>"##));
+    }
+}
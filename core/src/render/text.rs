@@ -0,0 +1,114 @@
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+
+const ASCII_BYTES: &[u8] = include_bytes!("ascii.png");
+
+const GLYPH_SIZE: u32 = 8;
+const SPACE_WIDTH: u32 = 3;
+
+/// A Minecraft-style bitmap font: a 16x16 grid of 8x8 glyphs indexed by character code, with
+/// each glyph's width derived from its rightmost opaque column.
+struct Font {
+    atlas: RgbaImage,
+    widths: [u32; 256],
+}
+
+impl Font {
+    fn load() -> Font {
+        let cursor = std::io::Cursor::new(ASCII_BYTES);
+        let atlas = match image::io::Reader::with_format(cursor, ImageFormat::Png).decode() {
+            Ok(DynamicImage::ImageRgba8(atlas)) => atlas,
+            _ => panic!("malformed font atlas"),
+        };
+
+        let mut widths = [0; 256];
+        for (code, width) in widths.iter_mut().enumerate() {
+            let (ox, oy) = glyph_origin(code as u8);
+            *width = (0..GLYPH_SIZE).rev()
+                .find(|&x| (0..GLYPH_SIZE).any(|y| atlas.get_pixel(ox + x, oy + y)[3] != 0))
+                .map(|x| x + 1)
+                .unwrap_or(0);
+        }
+        widths[b' ' as usize] = SPACE_WIDTH;
+
+        Font { atlas, widths }
+    }
+
+    #[inline]
+    fn width(&self, code: u8) -> u32 {
+        self.widths[code as usize]
+    }
+}
+
+#[inline]
+fn font() -> &'static Font {
+    use lazy_static::lazy_static;
+
+    lazy_static! {
+        static ref FONT: Font = Font::load();
+    }
+
+    &FONT
+}
+
+#[inline]
+fn glyph_origin(code: u8) -> (u32, u32) {
+    ((code as u32 % 16) * GLYPH_SIZE, (code as u32 / 16) * GLYPH_SIZE)
+}
+
+#[inline]
+fn glyph_code(c: char) -> u8 {
+    if c.is_ascii_graphic() || c == ' ' {
+        c as u8
+    } else {
+        b'?'
+    }
+}
+
+pub const LINE_HEIGHT: u32 = GLYPH_SIZE;
+
+/// Unscaled width of `text` in pixels, excluding the trailing glyph spacing.
+pub fn measure(text: &str) -> u32 {
+    let font = font();
+    let advance: u32 = text.chars().map(|c| font.width(glyph_code(c)) + 1).sum();
+    advance.saturating_sub(1)
+}
+
+/// Draws `text` with its top-left corner at `(x, y)`, followed by a darkened drop shadow as the
+/// game does. Pixels falling outside the image are clipped.
+pub fn draw(image: &mut RgbaImage, text: &str, x: u32, y: u32, scale: u32, color: Rgba<u8>) {
+    let [r, g, b, a] = color.0;
+    let shadow = Rgba([r / 4, g / 4, b / 4, a]);
+
+    draw_glyphs(image, text, x + scale, y + scale, scale, shadow);
+    draw_glyphs(image, text, x, y, scale, color);
+}
+
+fn draw_glyphs(image: &mut RgbaImage, text: &str, x: u32, y: u32, scale: u32, color: Rgba<u8>) {
+    let font = font();
+    let (width, height) = image.dimensions();
+
+    let mut cursor = x;
+    for c in text.chars() {
+        let code = glyph_code(c);
+        let (ox, oy) = glyph_origin(code);
+
+        for gy in 0..GLYPH_SIZE {
+            for gx in 0..font.width(code) {
+                if font.atlas.get_pixel(ox + gx, oy + gy)[3] == 0 {
+                    continue;
+                }
+
+                for sy in 0..scale {
+                    for sx in 0..scale {
+                        let (px, py) = (cursor + gx * scale + sx, y + gy * scale + sy);
+                        if px < width && py < height {
+                            image.put_pixel(px, py, color);
+                        }
+                    }
+                }
+            }
+        }
+
+        cursor += (font.width(code) + 1) * scale;
+    }
+}
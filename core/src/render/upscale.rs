@@ -0,0 +1,183 @@
+use image::{ImageBuffer, Rgb, RgbImage};
+
+use super::rescale;
+
+/// Differences in Y, U and V past which two pixels are told apart, as in HQx.
+const THRESHOLD_Y: i32 = 48;
+const THRESHOLD_U: i32 = 7;
+const THRESHOLD_V: i32 = 6;
+
+/// Scales by `factor` through as many doublings as divide it, finishing off with
+/// nearest-neighbour for whatever factor remains.
+pub fn upscale(image: &RgbImage, factor: u32, double: fn(&RgbImage) -> RgbImage) -> RgbImage {
+    let mut image = image.clone();
+    let mut factor = factor;
+    while factor > 1 && factor.is_multiple_of(2) {
+        image = double(&image);
+        factor /= 2;
+    }
+
+    if factor > 1 {
+        rescale(&image, factor)
+    } else {
+        image
+    }
+}
+
+/// A reduced HQ2x: each quadrant of a pixel is decided by the three neighbours toward its corner
+/// rather than the full 3x3 pattern, using the same YUV thresholds to tell colors apart.
+pub fn hq2x(image: &RgbImage) -> RgbImage {
+    double(image, |view| {
+        let e = view.get(0, 0);
+        let (a, b, d) = (view.get(-1, -1), view.get(0, -1), view.get(-1, 0));
+
+        if !differs(b, d) && differs(e, b) {
+            if differs(e, a) && !differs(a, b) {
+                blend(&[(e, 2), (b, 3), (d, 3)])
+            } else {
+                blend(&[(e, 2), (b, 1), (d, 1)])
+            }
+        } else if differs(e, a) && !differs(e, b) && !differs(e, d) {
+            blend(&[(e, 3), (a, 1)])
+        } else {
+            e
+        }
+    })
+}
+
+/// 2xBR: a pixel's corner takes on the color across an edge where the weighted color distances
+/// find the edge to run through that corner rather than along the opposite diagonal.
+pub fn xbr(image: &RgbImage) -> RgbImage {
+    double(image, |view| {
+        let e = view.get(0, 0);
+        let (b, d, f, h) = (view.get(0, -1), view.get(-1, 0), view.get(1, 0), view.get(0, 1));
+        let (a, c, g) = (view.get(-1, -1), view.get(1, -1), view.get(-1, 1));
+
+        let along = distance(e, c) + distance(e, g) + distance(a, view.get(-2, 0)) + distance(a, view.get(0, -2)) + 4 * distance(b, d);
+        let across = distance(b, view.get(-1, -2)) + distance(b, f) + distance(d, view.get(-2, -1)) + distance(d, h) + 4 * distance(e, a);
+
+        if along < across {
+            let edge = if distance(e, b) <= distance(e, d) { b } else { d };
+            blend(&[(e, 1), (edge, 1)])
+        } else {
+            e
+        }
+    })
+}
+
+/// Doubles the image, computing each output pixel from the source neighbourhood rotated such
+/// that its corner lies toward negative x and y.
+fn double(image: &RgbImage, corner: impl Fn(&View) -> Rgb<u8>) -> RgbImage {
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width * 2, height * 2, |x, y| {
+        let view = View {
+            image,
+            x: (x / 2) as i32,
+            y: (y / 2) as i32,
+            flip_x: x % 2 == 1,
+            flip_y: y % 2 == 1,
+        };
+        corner(&view)
+    })
+}
+
+/// The neighbourhood of a pixel, mirrored toward the corner being computed and clamped at the
+/// image edges.
+struct View<'a> {
+    image: &'a RgbImage,
+    x: i32,
+    y: i32,
+    flip_x: bool,
+    flip_y: bool,
+}
+
+impl View<'_> {
+    #[inline]
+    fn get(&self, dx: i32, dy: i32) -> Rgb<u8> {
+        let dx = if self.flip_x { -dx } else { dx };
+        let dy = if self.flip_y { -dy } else { dy };
+
+        let (width, height) = self.image.dimensions();
+        let x = (self.x + dx).clamp(0, width as i32 - 1);
+        let y = (self.y + dy).clamp(0, height as i32 - 1);
+        *self.image.get_pixel(x as u32, y as u32)
+    }
+}
+
+fn yuv(pixel: Rgb<u8>) -> [i32; 3] {
+    let [r, g, b] = pixel.0.map(|c| c as i32);
+    let y = (r * 299 + g * 587 + b * 114) / 1000;
+    let u = (b - y) * 492 / 1000 + 128;
+    let v = (r - y) * 877 / 1000 + 128;
+    [y, u, v]
+}
+
+#[inline]
+fn differs(a: Rgb<u8>, b: Rgb<u8>) -> bool {
+    let ([ay, au, av], [by, bu, bv]) = (yuv(a), yuv(b));
+    (ay - by).abs() > THRESHOLD_Y || (au - bu).abs() > THRESHOLD_U || (av - bv).abs() > THRESHOLD_V
+}
+
+/// Weighted YUV distance, with luma counting for the most as in xBR.
+#[inline]
+fn distance(a: Rgb<u8>, b: Rgb<u8>) -> i32 {
+    let ([ay, au, av], [by, bu, bv]) = (yuv(a), yuv(b));
+    48 * (ay - by).abs() + 7 * (au - bu).abs() + 6 * (av - bv).abs()
+}
+
+fn blend(weighted: &[(Rgb<u8>, u32)]) -> Rgb<u8> {
+    let total: u32 = weighted.iter().map(|(_, weight)| weight).sum();
+    let mut sum = [0u32; 3];
+    for (pixel, weight) in weighted {
+        for (sum, channel) in sum.iter_mut().zip(pixel.0.iter()) {
+            *sum += *channel as u32 * weight;
+        }
+    }
+    Rgb(sum.map(|channel| ((channel + total / 2) / total) as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+    const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+
+    /// Black below the diagonal and white above it.
+    fn diagonal() -> RgbImage {
+        RgbImage::from_fn(8, 8, |x, y| if x < y { BLACK } else { WHITE })
+    }
+
+    #[test]
+    fn upscales_by_any_factor() {
+        let image = diagonal();
+        for factor in [1, 2, 3, 4, 6] {
+            for double in [hq2x, xbr] {
+                assert_eq!(upscale(&image, factor, double).dimensions(), (8 * factor, 8 * factor));
+            }
+        }
+    }
+
+    #[test]
+    fn straight_edges_stay_sharp() {
+        let image = RgbImage::from_fn(8, 8, |x, _| if x < 4 { BLACK } else { WHITE });
+        assert_eq!(hq2x(&image), rescale(&image, 2));
+        assert_eq!(xbr(&image), rescale(&image, 2));
+    }
+
+    #[test]
+    fn diagonal_edges_are_smoothed() {
+        let image = diagonal();
+        let nearest = rescale(&image, 2);
+        for double in [hq2x, xbr] {
+            let doubled = double(&image);
+            assert_ne!(doubled, nearest);
+            // Only pixels along the edge change.
+            for (x, y, pixel) in doubled.enumerate_pixels() {
+                if (x as i32 - y as i32).abs() > 4 {
+                    assert_eq!(pixel, nearest.get_pixel(x, y), "({}, {})", x, y);
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,12 @@
-use image::{DynamicImage, ImageFormat};
-use uuid::Uuid;
+use std::collections::HashMap;
+use std::fmt;
 
-use crate::minecraft::PlayerTexture;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-const STEVE_BYTES: &'static [u8] = include_bytes!("steve.png");
-const ALEX_BYTES: &'static [u8] = include_bytes!("alex.png");
+const STEVE_BYTES: &[u8] = include_bytes!("steve.png");
+const ALEX_BYTES: &[u8] = include_bytes!("alex.png");
 
 #[derive(Copy, Clone, Debug)]
 pub struct Format {
@@ -103,7 +105,7 @@ impl CuboidTex {
                 (size.0, size.1),
             ),
             back: TexRegion::new(
-                (origin.0 + size.2 + size.0, origin.1 + size.2),
+                (origin.0 + 2 * size.2 + size.0, origin.1 + size.2),
                 (size.0, size.1),
             ),
             top: TexRegion::new(
@@ -115,7 +117,7 @@ impl CuboidTex {
                 (size.0, size.2),
             ),
             left: TexRegion::new(
-                (origin.0 + size.2 + 2 * size.0, origin.1 + size.2),
+                (origin.0 + size.2 + size.0, origin.1 + size.2),
                 (size.2, size.1),
             ),
             right: TexRegion::new(
@@ -145,47 +147,124 @@ pub struct Skin {
 }
 
 impl Skin {
-    pub fn from(texture: PlayerTexture) -> Option<Skin> {
-        let model = texture.metadata.get("model");
-        let model = match model.map(|s| s.as_str()) {
-            Some("slim") => Model::Slim,
-            _ => Model::Wide,
-        };
-
-        let format = match (model, texture.image.dimensions()) {
+    /// Takes the image as a skin of the given model, if it has the dimensions of one.
+    pub fn new(image: image::RgbaImage, model: Model) -> Option<Skin> {
+        let format = match (model, image.dimensions()) {
             (Model::Wide, (64, 32)) => Format::LEGACY,
             (Model::Wide, (64, 64)) => Format::WIDE_ARMS,
             (Model::Slim, (64, 64)) => Format::SLIM_ARMS,
             _ => return None,
         };
 
-        Some(Skin {
-            image: texture.image,
-            format,
-        })
+        Some(Skin { image, format })
+    }
+
+    #[inline]
+    pub fn is_legacy(&self) -> bool {
+        self.image.height() == 32
+    }
+
+    /// Converts a legacy 64x32 skin to the 64x64 layout the same way the game does: the right
+    /// limbs are mirrored into the left limb regions, and a fully opaque hat layer is cleared.
+    pub fn normalize(&self) -> Skin {
+        if !self.is_legacy() {
+            return self.clone();
+        }
+
+        let mut image = image::RgbaImage::new(64, 64);
+        image::imageops::replace(&mut image, &self.image, 0, 0);
+
+        const LIMB_COPIES: [(u32, u32, i32, i32, u32, u32); 12] = [
+            (4, 16, 16, 32, 4, 4),
+            (8, 16, 16, 32, 4, 4),
+            (0, 20, 24, 32, 4, 12),
+            (4, 20, 16, 32, 4, 12),
+            (8, 20, 8, 32, 4, 12),
+            (12, 20, 16, 32, 4, 12),
+            (44, 16, -8, 32, 4, 4),
+            (48, 16, -8, 32, 4, 4),
+            (40, 20, 0, 32, 4, 12),
+            (44, 20, -8, 32, 4, 12),
+            (48, 20, -16, 32, 4, 12),
+            (52, 20, -8, 32, 4, 12),
+        ];
+
+        for &(x, y, offset_x, offset_y, width, height) in LIMB_COPIES.iter() {
+            copy_mirrored(&mut image, (x, y), (offset_x, offset_y), (width, height));
+        }
+
+        clear_if_opaque(&mut image, (32, 0), (64, 32));
+
+        Skin {
+            image,
+            format: Format::WIDE_ARMS,
+        }
+    }
+}
+
+fn copy_mirrored(image: &mut image::RgbaImage, origin: (u32, u32), offset: (i32, i32), size: (u32, u32)) {
+    let (x, y) = origin;
+    let (width, height) = size;
+    let dest_x = (x as i32 + offset.0) as u32;
+    let dest_y = (y as i32 + offset.1) as u32;
+
+    for dy in 0..height {
+        for dx in 0..width {
+            let pixel = *image.get_pixel(x + dx, y + dy);
+            image.put_pixel(dest_x + width - 1 - dx, dest_y + dy, pixel);
+        }
+    }
+}
+
+/// Legacy skins commonly fill the hat layer with an opaque color, which the game treats as
+/// if it were empty.
+fn clear_if_opaque(image: &mut image::RgbaImage, from: (u32, u32), to: (u32, u32)) {
+    let opaque = (from.1..to.1)
+        .all(|y| (from.0..to.0).all(|x| image.get_pixel(x, y)[3] >= 128));
+
+    if opaque {
+        for y in from.1..to.1 {
+            for x in from.0..to.0 {
+                image.get_pixel_mut(x, y)[3] = 0;
+            }
+        }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Model {
     Wide,
     Slim,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl Model {
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Model {
+        match metadata.get("model").map(|s| s.as_str()) {
+            Some("slim") => Model::Slim,
+            _ => Model::Wide,
+        }
+    }
+}
+
+/// The skins the game gives players without one of their own, by name in configs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DefaultSkin {
     Steve,
     Alex,
 }
 
 impl DefaultSkin {
+    pub const ALL: [DefaultSkin; 2] = [DefaultSkin::Steve, DefaultSkin::Alex];
+
     #[inline]
     pub fn as_skin(&self) -> &Skin {
         use lazy_static::lazy_static;
 
         lazy_static! {
-            static ref STEVE: Skin = load_default_skin(STEVE_BYTES, Format::WIDE_ARMS);
-            static ref ALEX: Skin = load_default_skin(ALEX_BYTES, Format::SLIM_ARMS);
+            static ref STEVE: Skin = load_default_skin(STEVE_BYTES, Format::WIDE_ARMS).expect("malformed default skins");
+            static ref ALEX: Skin = load_default_skin(ALEX_BYTES, Format::SLIM_ARMS).expect("malformed default skins");
         }
 
         match self {
@@ -193,13 +272,59 @@ impl DefaultSkin {
             DefaultSkin::Alex => &ALEX,
         }
     }
+
+    #[inline]
+    pub fn model(&self) -> Model {
+        match self {
+            DefaultSkin::Steve => Model::Wide,
+            DefaultSkin::Alex => Model::Slim,
+        }
+    }
+
+    #[inline]
+    pub fn png_bytes(&self) -> &'static [u8] {
+        match self {
+            DefaultSkin::Steve => STEVE_BYTES,
+            DefaultSkin::Alex => ALEX_BYTES,
+        }
+    }
+
+    /// Takes the PNG as a replacement for this default skin, such as a network's mascot, held to
+    /// the same checks as the built-in one. It keeps this skin's model.
+    pub fn load_replacement(&self, bytes: &[u8]) -> Result<Skin, InvalidDefaultSkin> {
+        let format = match self.model() {
+            Model::Wide => Format::WIDE_ARMS,
+            Model::Slim => Format::SLIM_ARMS,
+        };
+        load_default_skin(bytes, format)
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidDefaultSkin {
+    Decode(image::ImageError),
+    /// Default skins must be 64x64, since the legacy 64x32 layout has no slim model.
+    Dimensions(u32, u32),
+}
+
+impl fmt::Display for InvalidDefaultSkin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidDefaultSkin::Decode(err) => write!(f, "does not decode: {}", err),
+            InvalidDefaultSkin::Dimensions(width, height) => write!(f, "is {}x{} rather than 64x64", width, height),
+        }
+    }
 }
 
-fn load_default_skin(bytes: &'static [u8], format: Format) -> Skin {
+fn load_default_skin(bytes: &[u8], format: Format) -> Result<Skin, InvalidDefaultSkin> {
     let cursor = std::io::Cursor::new(bytes);
-    match image::io::Reader::with_format(cursor, ImageFormat::Png).decode() {
-        Ok(DynamicImage::ImageRgba8(image)) => Skin { image, format },
-        _ => panic!("malformed default skins"),
+    let image = image::io::Reader::with_format(cursor, ImageFormat::Png).decode()
+        .map_err(InvalidDefaultSkin::Decode)?
+        .into_rgba8();
+
+    match image.dimensions() {
+        (64, 64) => Ok(Skin { image, format }),
+        (width, height) => Err(InvalidDefaultSkin::Dimensions(width, height)),
     }
 }
 
@@ -0,0 +1,82 @@
+//! Bindings for running renders in the browser or at the edge. Each takes the skin as PNG bytes
+//! and render options in the query string form the server takes, and returns a PNG.
+
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageFormat};
+use image::io::Reader;
+use serde::de::DeserializeOwned;
+use wasm_bindgen::prelude::*;
+
+use crate::options::{self, FaceOptions, HeadOptions, OutputFormat};
+use crate::skin::{Model, Skin};
+use crate::{encode_png, face, render};
+
+/// Renders a face as `/face/{size}/{uuid}` would. Badges and online status need the server, so
+/// aren't available here.
+#[wasm_bindgen(js_name = renderFace)]
+pub fn render_face(skin_png: &[u8], slim: bool, size: u32, options: &str) -> Result<Vec<u8>, JsValue> {
+    let skin = decode_skin(skin_png, slim)?;
+    let options: FaceOptions = parse_options(options)?;
+    if options.format != OutputFormat::Png || options.badge.is_some() || options.status {
+        return Err(JsValue::from_str("unsupported options"));
+    }
+
+    let size = match options::parse_size(size) {
+        Some(size) if options.is_valid() => size,
+        _ => return Err(JsValue::from_str("invalid size or options")),
+    };
+
+    let raw_face = render::render_face(&skin);
+    encode(&face::render(&raw_face, size, &options, options.caption.as_deref(), None))
+}
+
+/// Renders a 3D head as `/head/{size}/{uuid}` would.
+#[wasm_bindgen(js_name = renderHead)]
+pub fn render_head(skin_png: &[u8], slim: bool, size: u32, options: &str) -> Result<Vec<u8>, JsValue> {
+    let skin = decode_skin(skin_png, slim)?;
+    let options: HeadOptions = parse_options(options)?;
+    let size = match options::parse_size(size) {
+        Some(size) if options.is_valid() => size,
+        _ => return Err(JsValue::from_str("invalid size or options")),
+    };
+
+    let head = render::render_head(&skin, size, options.yaw.degrees(), options.pitch.degrees(), options.quality);
+    encode(&DynamicImage::ImageRgba8(head))
+}
+
+/// Renders the front, back and sides of the body as `/views/{size}/{uuid}` would.
+#[wasm_bindgen(js_name = renderViews)]
+pub fn render_views(skin_png: &[u8], slim: bool, size: u32) -> Result<Vec<u8>, JsValue> {
+    let skin = decode_skin(skin_png, slim)?;
+    let size = match options::parse_size(size) {
+        Some(size) if size >= 8 => size,
+        _ => return Err(JsValue::from_str("invalid size")),
+    };
+
+    let views = render::render_views(&skin, false);
+    encode(&DynamicImage::ImageRgba8(render::rescale(&views, size / 8)))
+}
+
+fn decode_skin(png: &[u8], slim: bool) -> Result<Skin, JsValue> {
+    let model = if slim { Model::Slim } else { Model::Wide };
+
+    // Check the dimensions before decoding so that a huge image isn't decoded only to be refused.
+    let dimensions = Reader::with_format(Cursor::new(png), ImageFormat::Png).into_dimensions();
+    let image = match dimensions {
+        Ok((64, 32)) | Ok((64, 64)) => Reader::with_format(Cursor::new(png), ImageFormat::Png).decode().ok(),
+        _ => None,
+    };
+
+    image.and_then(|image| Skin::new(image.into_rgba8(), model))
+        .ok_or_else(|| JsValue::from_str("invalid skin"))
+}
+
+fn parse_options<T: DeserializeOwned>(options: &str) -> Result<T, JsValue> {
+    serde_urlencoded::from_str(options.trim_start_matches('?'))
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+fn encode(image: &DynamicImage) -> Result<Vec<u8>, JsValue> {
+    encode_png(image).map_err(|err| JsValue::from_str(&err.to_string()))
+}
@@ -0,0 +1,100 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    pub fn new(allow: Vec<IpNet>, deny: Vec<IpNet>) -> IpFilter {
+        IpFilter { allow, deny }
+    }
+
+    /// Deny entries always win; a non-empty allow list rejects anything it doesn't cover,
+    /// including clients whose address is unknown.
+    pub fn permits(&self, addr: Option<IpAddr>) -> bool {
+        let addr = match addr {
+            Some(addr) => canonical(addr),
+            None => return self.allow.is_empty(),
+        };
+
+        if self.deny.iter().any(|net| net.contains(&addr)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&addr))
+    }
+}
+
+/// Whether `addr` falls within any of `nets`, for lists that grant privileges rather than
+/// restrict access. Unknown addresses are never covered.
+pub fn covers(nets: &[IpNet], addr: Option<IpAddr>) -> bool {
+    addr.map(canonical).is_some_and(|addr| nets.iter().any(|net| net.contains(&addr)))
+}
+
+#[inline]
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+        addr => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nets(nets: &[&str]) -> Vec<IpNet> {
+        nets.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn empty_filter_permits_everyone() {
+        let filter = IpFilter::default();
+        assert!(filter.permits(ip("203.0.113.7")));
+        assert!(filter.permits(ip("2001:db8::1")));
+        assert!(filter.permits(None));
+    }
+
+    #[test]
+    fn allow_list_permits_only_what_it_covers() {
+        let filter = IpFilter::new(nets(&["10.0.0.0/8", "2001:db8::/32"]), Vec::new());
+        assert!(filter.permits(ip("10.1.2.3")));
+        assert!(filter.permits(ip("2001:db8::1")));
+        assert!(!filter.permits(ip("11.0.0.1")));
+        assert!(!filter.permits(ip("2001:db9::1")));
+        assert!(!filter.permits(None));
+    }
+
+    #[test]
+    fn deny_list_wins_over_allow_list() {
+        let filter = IpFilter::new(nets(&["10.0.0.0/8"]), nets(&["10.0.0.0/24"]));
+        assert!(!filter.permits(ip("10.0.0.5")));
+        assert!(filter.permits(ip("10.0.1.5")));
+
+        let filter = IpFilter::new(Vec::new(), nets(&["192.0.2.0/24"]));
+        assert!(!filter.permits(ip("192.0.2.1")));
+        assert!(filter.permits(ip("192.0.3.1")));
+        assert!(filter.permits(None));
+    }
+
+    #[test]
+    fn mapped_ipv6_addresses_match_ipv4_nets() {
+        let filter = IpFilter::new(Vec::new(), nets(&["192.0.2.0/24"]));
+        assert!(!filter.permits(ip("::ffff:192.0.2.1")));
+        assert!(covers(&nets(&["192.0.2.0/24"]), ip("::ffff:192.0.2.1")));
+    }
+
+    #[test]
+    fn unknown_addresses_are_never_covered() {
+        assert!(!covers(&nets(&["0.0.0.0/0", "::/0"]), None));
+        assert!(covers(&nets(&["0.0.0.0/0"]), ip("198.51.100.1")));
+    }
+}
@@ -0,0 +1,82 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use warp::http::{Method, StatusCode};
+
+use crate::cache;
+
+const TARGET: &str = "access";
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    Common,
+    Json,
+}
+
+pub struct Entry<'a> {
+    pub addr: Option<SocketAddr>,
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub query: &'a str,
+    pub status: StatusCode,
+    pub bytes: Option<u64>,
+    pub duration: Duration,
+    pub cache: Option<cache::Outcome>,
+}
+
+impl<'a> Entry<'a> {
+    pub fn log(&self, format: AccessLogFormat) {
+        match format {
+            AccessLogFormat::Common => log::info!(target: TARGET, "{}", self.to_common()),
+            AccessLogFormat::Json => log::info!(target: TARGET, "{}", self.to_json()),
+        }
+    }
+
+    fn to_common(&self) -> String {
+        let host = match self.addr {
+            Some(addr) => addr.ip().to_string(),
+            None => "-".to_owned(),
+        };
+        let bytes = match self.bytes {
+            Some(bytes) => bytes.to_string(),
+            None => "-".to_owned(),
+        };
+        let cache = match self.cache {
+            Some(cache) => cache.as_str(),
+            None => "-",
+        };
+
+        let time = chrono::Local::now().format("%d/%b/%Y:%H:%M:%S %z");
+
+        format!(
+            "{} - - [{}] \"{} {}\" {} {} {:.3} {}",
+            host, time, self.method, self.target(),
+            self.status.as_u16(), bytes, self.duration.as_secs_f64(), cache,
+        )
+    }
+
+    fn to_json(&self) -> String {
+        let entry = serde_json::json!({
+            "time": chrono::Utc::now().to_rfc3339(),
+            "ip": self.addr.map(|addr| addr.ip().to_string()),
+            "method": self.method.as_str(),
+            "path": self.path,
+            "query": self.query,
+            "status": self.status.as_u16(),
+            "bytes": self.bytes,
+            "duration_ms": self.duration.as_secs_f64() * 1000.0,
+            "cache": self.cache.map(|cache| cache.as_str()),
+        });
+        entry.to_string()
+    }
+
+    fn target(&self) -> String {
+        if self.query.is_empty() {
+            self.path.to_owned()
+        } else {
+            format!("{}?{}", self.path, self.query)
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::env;
+use std::net::TcpListener;
+
+/// The first file descriptor passed by the service manager; 0 to 2 are the standard streams.
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Takes the listening sockets passed to us through systemd socket activation, if any. The
+/// activation variables are cleared so that they aren't inherited by child processes.
+#[cfg(unix)]
+pub fn listeners() -> Vec<TcpListener> {
+    use std::os::unix::io::FromRawFd;
+
+    let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let fds = env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<i32>().ok());
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    match (pid, fds) {
+        (Some(pid), Some(fds)) if pid == std::process::id() => {
+            (LISTEN_FDS_START..LISTEN_FDS_START + fds)
+                .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn listeners() -> Vec<TcpListener> {
+    Vec::new()
+}
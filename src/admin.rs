@@ -0,0 +1,237 @@
+use serde::Deserialize;
+use uuid::Uuid;
+use warp::{Filter, Rejection, Reply};
+use warp::http::{header, HeaderValue, StatusCode};
+
+use crate::api::{Api, Tuning};
+use crate::audit::{Actor, AuditLog};
+use crate::snapshot::Snapshot;
+
+const MAX_BODY_BYTES: u64 = 16 * 1024;
+/// Imported caches are far larger than any other admin request.
+const MAX_CACHE_BODY_BYTES: u64 = 64 * 1024 * 1024;
+/// How many clients `/admin/usage` lists unless asked for another number.
+const DEFAULT_USAGE_LIMIT: usize = 50;
+
+/// Operational routes under `/admin`, which require `Authorization: Bearer <token>` and are not
+/// mounted at all without a configured token. Every change made through them is recorded in the
+/// audit log.
+pub fn routes(api: Api, token: Option<String>, audit: AuditLog) -> impl Filter<Extract = (Box<dyn Reply>,), Error = Rejection> + Clone {
+    let stats = warp::path("stats")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map({
+            let api = api.clone();
+            move || Box::new(warp::reply::json(&api.cache_stats())) as Box<dyn Reply>
+        });
+
+    let get_tuning = warp::path("tuning")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map({
+            let api = api.clone();
+            move || Box::new(warp::reply::json(&api.tuning())) as Box<dyn Reply>
+        });
+
+    let patch_tuning = warp::path("tuning")
+        .and(warp::path::end())
+        .and(warp::patch())
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(actor())
+        .map({
+            let api = api.clone();
+            let audit = audit.clone();
+            move |patch, actor| tune(&api, &audit, &actor, patch)
+        });
+
+    let blocklist = warp::path("blocklist")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map({
+            let api = api.clone();
+            move || Box::new(warp::reply::json(&api.blocked_uuids())) as Box<dyn Reply>
+        });
+
+    let block = warp::path("blocklist")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(actor())
+        .map({
+            let api = api.clone();
+            let audit = audit.clone();
+            move |uuid, actor| {
+                if api.block(uuid) {
+                    audit.record(&actor, "block", serde_json::json!({ "uuid": uuid }));
+                }
+                Box::new(StatusCode::NO_CONTENT) as Box<dyn Reply>
+            }
+        });
+
+    let unblock = warp::path("blocklist")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(actor())
+        .map({
+            let api = api.clone();
+            let audit = audit.clone();
+            move |uuid, actor| {
+                if api.unblock(uuid) {
+                    audit.record(&actor, "unblock", serde_json::json!({ "uuid": uuid }));
+                    Box::new(StatusCode::NO_CONTENT) as Box<dyn Reply>
+                } else {
+                    Box::new(StatusCode::NOT_FOUND)
+                }
+            }
+        });
+
+    // The cached profiles and faces in the form of a cache snapshot, to be put to a new
+    // instance's `/admin/cache` so that it doesn't start cold.
+    let export_cache = warp::path("cache")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map({
+            let api = api.clone();
+            move || Box::new(warp::reply::json(&api.export_cache())) as Box<dyn Reply>
+        });
+
+    let import_cache = warp::path("cache")
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::body::content_length_limit(MAX_CACHE_BODY_BYTES))
+        .and(warp::body::json())
+        .and(actor())
+        .map({
+            let api = api.clone();
+            move |snapshot: Snapshot, actor| {
+                let (profiles, faces) = (snapshot.profiles.len(), snapshot.raw_faces.len());
+                if api.import_cache(snapshot) {
+                    audit.record(&actor, "import_cache", serde_json::json!({ "profiles": profiles, "faces": faces }));
+                    Box::new(StatusCode::NO_CONTENT) as Box<dyn Reply>
+                } else {
+                    Box::new(warp::reply::with_status("snapshot has expired", StatusCode::UNPROCESSABLE_ENTITY))
+                }
+            }
+        });
+
+    let tenant_usage = warp::path("usage")
+        .and(warp::path("tenants"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map({
+            let api = api.clone();
+            move || Box::new(warp::reply::json(&api.tenant_usage())) as Box<dyn Reply>
+        });
+
+    let usage = warp::path("usage")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<UsageQuery>())
+        .map(move |query: UsageQuery| Box::new(warp::reply::json(&api.usage(query.limit))) as Box<dyn Reply>);
+
+    let routes = stats
+        .or(usage).unify()
+        .or(tenant_usage).unify()
+        .or(get_tuning).unify()
+        .or(patch_tuning).unify()
+        .or(blocklist).unify()
+        .or(block).unify()
+        .or(unblock).unify()
+        .or(export_cache).unify()
+        .or(import_cache).unify();
+
+    warp::path("admin")
+        .and(authorized(token))
+        .and(routes)
+}
+
+#[derive(Deserialize)]
+struct UsageQuery {
+    #[serde(default = "default_usage_limit")]
+    limit: usize,
+}
+
+fn default_usage_limit() -> usize {
+    DEFAULT_USAGE_LIMIT
+}
+
+/// Applies the fields given in `patch` over the current tuning, leaving the rest as they are.
+fn tune(api: &Api, audit: &AuditLog, actor: &Actor, patch: serde_json::Value) -> Box<dyn Reply> {
+    let mut tuning = serde_json::to_value(api.tuning()).expect("tuning is always serializable");
+    merge(&mut tuning, patch.clone());
+
+    let tuning: Tuning = match serde_json::from_value(tuning) {
+        Ok(tuning) => tuning,
+        Err(err) => return Box::new(warp::reply::with_status(err.to_string(), StatusCode::BAD_REQUEST)),
+    };
+
+    match api.tune(tuning) {
+        Ok(()) => {
+            audit.record(actor, "tune", patch);
+            Box::new(warp::reply::json(&api.tuning()))
+        }
+        Err(err) => Box::new(warp::reply::with_status(err.to_string(), StatusCode::UNPROCESSABLE_ENTITY)),
+    }
+}
+
+/// Overwrites fields of `target` with those in `patch`, descending into objects present in both.
+fn merge(target: &mut serde_json::Value, patch: serde_json::Value) {
+    match (target, patch) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                match target.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => { target.insert(key, value); }
+                }
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+/// Rejects requests that don't carry the admin token, checked before any admin route so that
+/// unauthenticated clients can't probe which routes exist.
+fn authorized(token: Option<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let token = token.clone();
+            async move {
+                let token = token.ok_or_else(warp::reject::not_found)?;
+                let given = authorization.as_deref().and_then(|value| value.strip_prefix("Bearer "));
+                match given {
+                    Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => Ok(()),
+                    _ => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// The actor behind a request that has already passed [`authorized`].
+fn actor() -> impl Filter<Extract = (Actor,), Error = Rejection> + Clone {
+    warp::header::<String>("authorization")
+        .map(|authorization: String| {
+            Actor::from_token(authorization.strip_prefix("Bearer ").unwrap_or(&authorization))
+        })
+}
+
+/// Compares without returning early, so that response timing doesn't reveal how much of the
+/// token a guess got right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+impl Unauthorized {
+    pub fn reply() -> warp::reply::Response {
+        let mut response = StatusCode::UNAUTHORIZED.into_response();
+        response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        response
+    }
+}
@@ -1,162 +1,1997 @@
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
 use std::num::NonZeroU32;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
 use bytes::Bytes;
 use governor::clock::DefaultClock;
-use governor::RateLimiter;
+use governor::{Jitter, RateLimiter};
 use governor::state::keyed::DashMapStateStore;
-use image::{EncodableLayout, RgbImage};
-use image::codecs::png::PngEncoder;
+use ipnet::IpNet;
+use image::{DynamicImage, GenericImageView, RgbaImage, RgbImage};
+use image::{Delay, Frame};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::tga::TgaEncoder;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use warp::http::{header, HeaderValue};
 
-use crate::{Config, minecraft};
-use crate::cache::Cache;
-use crate::render;
-use crate::skin::{self, Skin};
+use crate::{Config, ReadinessConfig, StaleConfig, minecraft};
+use crate::hot::HotPlayers;
+use crate::metrics::{self, Exposition, Kind};
+use crate::minecraft::PlayerProfile;
+use crate::access::{self, IpFilter};
+use crate::cache::{self, Cache, DiskTier, Eviction, Persist, Resize};
+use crate::options::{BannerOptions, BodyOptions, CapeOptions, CardOptions, ChibiOptions, CompassOptions, FaceOptions, Flip, HeadOptions, OutputFormat, Shape, Upscale};
+use crate::nucleoid::{PlayerStats, StatsClient};
+use crate::ping::{self, Pinger, ServerStatus};
+use crate::{face, render};
+use crate::render::{FaceColors, Similarity};
+use crate::timing;
+use crate::render_queue::{Priority, RenderQueue};
+use crate::shared_limit::SharedRateLimiter;
+use crate::skin::{self, DefaultSkin, InvalidDefaultSkin, Model, Skin};
+use crate::snapshot::{RawFace, Snapshot};
+use crate::source::Source;
+use crate::tenant::{Tenant, TenantUsage, Tenants};
+use crate::upstream::{Budget, Reachability, Upstream};
+use crate::usage::{ClientUsage, Usage};
+use crate::watch::Watcher;
 use sha1::Sha1;
+use tokio::sync::broadcast;
 
-const CACHE_CLEAR_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Spreads out clients waiting on the rate limiter so they don't all retry at once.
+const RATE_LIMIT_JITTER: Duration = Duration::from_millis(50);
+
+const DEFAULT_BANNER_TITLE: &str = "Achievement Get!";
+
+const MAX_CARD_LINES: usize = 8;
+
+/// Watched players are each revalidated every interval, so their number is bounded to keep
+/// within the upstream budget.
+const MAX_WATCHED_PLAYERS: usize = 256;
+const GAMES_PLAYED_STAT: &str = "games_played";
+
+type StatCounter = fn(&cache::Stats) -> u64;
+
+type KeyedRateLimiter = RateLimiter<SocketAddr, DashMapStateStore<SocketAddr>, DefaultClock>;
+
+const FAVICON_SIZES: [u32; 3] = [16, 32, 48];
 
 struct Caches {
-    raw_faces: Cache<Uuid, Arc<RgbImage>>,
-    faces: Cache<(Uuid, u32), ImageBytes>,
+    profiles: Cache<Uuid, Option<Arc<PlayerProfile>>>,
+    /// Lowercase usernames to the UUID of the account holding them, if any.
+    usernames: Cache<String, Option<Uuid>>,
+    skins: Cache<Uuid, Arc<PlayerSkin>>,
+    /// Skins keyed by texture hash and model, shared by every player wearing the same texture.
+    textures: Cache<(String, Model), Option<Arc<PlayerSkin>>>,
+    /// Expired skins by texture URL and model, kept so that a re-fetch can be made conditional.
+    stale_skins: Arc<Cache<(String, Model), Arc<PlayerSkin>>>,
+    /// Expired profiles, kept to answer with when a player's profile may not be fetched again.
+    stale_profiles: Arc<Cache<Uuid, Arc<PlayerProfile>>>,
+    normalized_skins: Cache<Uuid, ImageBytes>,
+    raw_faces: Cache<Uuid, Arc<PlayerFace>>,
+    faces: Cache<(Uuid, u32, FaceOptions), ImageBytes>,
+    texture_faces: Cache<(String, u32, FaceOptions), Option<ImageBytes>>,
+    heads: Cache<(Uuid, u32, HeadOptions), ImageBytes>,
+    compasses: Cache<(Uuid, u32, CompassOptions), ImageBytes>,
+    views: Cache<(Uuid, u32, BodyOptions), ImageBytes>,
+    previews: Cache<(Uuid, u32, BodyOptions), ImageBytes>,
+    chibis: Cache<(Uuid, u32, ChibiOptions), ImageBytes>,
+    diffs: Cache<(Uuid, Uuid, u32), ImageBytes>,
+    banners: Cache<(Uuid, BannerOptions), ImageBytes>,
+    /// `None` for players without a cape, or a frame the cape doesn't have.
+    capes: Cache<(Uuid, CapeOptions), Option<ImageBytes>>,
+    /// Expiring along with the statistics they show.
+    cards: Cache<(Uuid, CardOptions), ImageBytes>,
+    layouts: Cache<(Uuid, u32), ImageBytes>,
+}
+
+/// The names caches go by in statistics, when resized, and in `cache_policies`.
+pub const CACHE_NAMES: [&str; 20] = [
+    "profiles", "usernames", "skins", "textures", "stale_skins", "stale_profiles", "normalized_skins",
+    "raw_faces", "faces", "texture_faces", "heads", "compasses", "views", "previews", "chibis", "diffs",
+    "banners", "capes", "cards", "layouts",
+];
+
+impl Caches {
+    /// Face renders are held to `face_bytes` between the player and texture face caches.
+    fn new(config: &Config, disk_cache: Option<&Path>, face_bytes: u64) -> Caches {
+        let face_bytes = (face_bytes / 2).max(1);
+        let mut faces = Cache::weighted(face_bytes, CACHE_TTL, config.cache_policy("faces"))
+            .on_eviction(log_capacity_eviction("faces"));
+
+        if let Some(root) = disk_cache {
+            match DiskTier::new(root.join("faces"), CACHE_TTL, config.disk_cache_bytes) {
+                Ok(tier) => faces = faces.with_tier(tier),
+                Err(err) => log::error!("failed to open disk cache at {:?}: {:?}", root, err),
+            }
+        }
+
+        let stale_ttl = Duration::from_secs(config.stale.ttl_secs);
+        let stale_skins = Arc::new(Cache::new(config.stale.skins, stale_ttl, config.cache_policy("stale_skins")));
+        let stale_profiles = Arc::new(Cache::new(config.stale.profiles, stale_ttl, config.cache_policy("stale_profiles")));
+        let card_ttl = config.nucleoid.as_ref().map_or(CACHE_TTL, |nucleoid| Duration::from_secs(nucleoid.cache_secs));
+
+        Caches {
+            profiles: Cache::new(512, CACHE_TTL, config.cache_policy("profiles"))
+                .on_load_error(|uuid, err| log::warn!("failed to load profile for {}: {:?}", uuid, err))
+                .on_eviction(keep_stale_profile(stale_profiles.clone())),
+            usernames: Cache::new(1024, CACHE_TTL, config.cache_policy("usernames")),
+            skins: Cache::new(128, CACHE_TTL, config.cache_policy("skins"))
+                .on_eviction(keep_stale_skin(stale_skins.clone(), |skin: &Arc<PlayerSkin>| Some(skin))),
+            textures: Cache::new(256, CACHE_TTL, config.cache_policy("textures"))
+                .on_load_error(|(hash, _), err| log::warn!("failed to load texture {}: {:?}", hash, err))
+                .on_eviction(keep_stale_skin(stale_skins.clone(), Option::as_ref)),
+            stale_skins,
+            stale_profiles,
+            normalized_skins: Cache::new(128, CACHE_TTL, config.cache_policy("normalized_skins")),
+            raw_faces: Cache::new(512, CACHE_TTL, config.cache_policy("raw_faces")),
+            faces,
+            texture_faces: Cache::weighted(face_bytes, CACHE_TTL, config.cache_policy("texture_faces"))
+                .on_eviction(log_capacity_eviction("texture_faces")),
+            heads: Cache::new(128, CACHE_TTL, config.cache_policy("heads")),
+            compasses: Cache::new(32, CACHE_TTL, config.cache_policy("compasses")),
+            views: Cache::new(64, CACHE_TTL, config.cache_policy("views")),
+            previews: Cache::new(64, CACHE_TTL, config.cache_policy("previews")),
+            chibis: Cache::new(64, CACHE_TTL, config.cache_policy("chibis")),
+            diffs: Cache::new(32, CACHE_TTL, config.cache_policy("diffs")),
+            banners: Cache::new(128, CACHE_TTL, config.cache_policy("banners")),
+            capes: Cache::new(64, CACHE_TTL, config.cache_policy("capes")),
+            cards: Cache::new(128, card_ttl, config.cache_policy("cards")),
+            layouts: Cache::new(32, CACHE_TTL, config.cache_policy("layouts")),
+        }
+    }
+
+    /// Applies a new time to live and capacities to the stale caches, rebuilding only those that
+    /// change.
+    fn tune_stale(&self, stale: StaleConfig) {
+        let ttl = Duration::from_secs(stale.ttl_secs);
+        let caches: [(&str, &dyn Resize, u64); 2] = [
+            ("stale_skins", &*self.stale_skins, stale.skins),
+            ("stale_profiles", &*self.stale_profiles, stale.profiles),
+        ];
+        for (cache, resize, capacity) in caches {
+            if ttl != resize.ttl() {
+                log::info!("keeping {} for {:?}", cache, ttl);
+                resize.set_ttl(ttl);
+            }
+            if capacity != resize.capacity() {
+                log::info!("resizing {} cache to {}", cache, capacity);
+                resize.set_capacity(capacity);
+            }
+        }
+    }
+
+    /// Statistics and current entry count for every cache, by name.
+    fn stats(&self) -> Vec<(&'static str, &cache::Stats, u64)> {
+        vec![
+            ("profiles", self.profiles.stats(), self.profiles.entry_count()),
+            ("usernames", self.usernames.stats(), self.usernames.entry_count()),
+            ("skins", self.skins.stats(), self.skins.entry_count()),
+            ("textures", self.textures.stats(), self.textures.entry_count()),
+            ("stale_skins", self.stale_skins.stats(), self.stale_skins.entry_count()),
+            ("stale_profiles", self.stale_profiles.stats(), self.stale_profiles.entry_count()),
+            ("normalized_skins", self.normalized_skins.stats(), self.normalized_skins.entry_count()),
+            ("raw_faces", self.raw_faces.stats(), self.raw_faces.entry_count()),
+            ("faces", self.faces.stats(), self.faces.entry_count()),
+            ("texture_faces", self.texture_faces.stats(), self.texture_faces.entry_count()),
+            ("heads", self.heads.stats(), self.heads.entry_count()),
+            ("compasses", self.compasses.stats(), self.compasses.entry_count()),
+            ("views", self.views.stats(), self.views.entry_count()),
+            ("previews", self.previews.stats(), self.previews.entry_count()),
+            ("chibis", self.chibis.stats(), self.chibis.entry_count()),
+            ("diffs", self.diffs.stats(), self.diffs.entry_count()),
+            ("banners", self.banners.stats(), self.banners.entry_count()),
+            ("capes", self.capes.stats(), self.capes.entry_count()),
+            ("cards", self.cards.stats(), self.cards.entry_count()),
+            ("layouts", self.layouts.stats(), self.layouts.entry_count()),
+        ]
+    }
+
+    /// Every cache by name, for changing capacities at runtime, but for the stale caches, which
+    /// are tuned along with their time to live.
+    fn resizable(&self) -> Vec<(&'static str, &dyn Resize)> {
+        vec![
+            ("profiles", &self.profiles),
+            ("usernames", &self.usernames),
+            ("skins", &self.skins),
+            ("textures", &self.textures),
+            ("normalized_skins", &self.normalized_skins),
+            ("raw_faces", &self.raw_faces),
+            ("faces", &self.faces),
+            ("texture_faces", &self.texture_faces),
+            ("heads", &self.heads),
+            ("compasses", &self.compasses),
+            ("views", &self.views),
+            ("previews", &self.previews),
+            ("chibis", &self.chibis),
+            ("diffs", &self.diffs),
+            ("banners", &self.banners),
+            ("capes", &self.capes),
+            ("cards", &self.cards),
+            ("layouts", &self.layouts),
+        ]
+    }
+
+    /// Drops everything rendered from a player's skin, for when it has changed.
+    fn forget_skin(&self, uuid: Uuid) {
+        self.skins.invalidate_where(move |key| *key == uuid);
+        self.normalized_skins.invalidate_where(move |key| *key == uuid);
+        self.raw_faces.invalidate_where(move |key| *key == uuid);
+        self.faces.invalidate_where(move |(key, _, _)| *key == uuid);
+        self.heads.invalidate_where(move |(key, _, _)| *key == uuid);
+        self.compasses.invalidate_where(move |(key, _, _)| *key == uuid);
+        self.views.invalidate_where(move |(key, _, _)| *key == uuid);
+        self.previews.invalidate_where(move |(key, _, _)| *key == uuid);
+        self.chibis.invalidate_where(move |(key, _, _)| *key == uuid);
+        self.diffs.invalidate_where(move |(a, b, _)| *a == uuid || *b == uuid);
+        self.banners.invalidate_where(move |(key, _)| *key == uuid);
+        self.capes.invalidate_where(move |(key, _)| *key == uuid);
+        self.cards.invalidate_where(move |(key, _)| *key == uuid);
+        self.layouts.invalidate_where(move |(key, _)| *key == uuid);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            saved_at: Snapshot::now(),
+            profiles: self.profiles.entries().into_iter()
+                .map(|(uuid, profile)| (uuid, profile.map(|profile| PlayerProfile::clone(&profile))))
+                .collect(),
+            raw_faces: self.raw_faces.entries().into_iter()
+                .map(|(uuid, face)| (uuid, RawFace::from(&face.image)))
+                .collect(),
+        }
+    }
+
+    fn restore(&self, snapshot: Snapshot) {
+        log::info!("restoring {} profiles and {} faces from cache snapshot", snapshot.profiles.len(), snapshot.raw_faces.len());
+
+        for (uuid, profile) in snapshot.profiles {
+            self.profiles.insert(uuid, profile.map(Arc::new));
+        }
+        for (uuid, face) in snapshot.raw_faces {
+            let face = face.to_image().and_then(|image| PlayerFace::new(image).ok());
+            if let Some(face) = face {
+                self.raw_faces.insert(uuid, Arc::new(face));
+            }
+        }
+    }
+}
+
+/// Entries pushed out before expiring suggest that the cache is undersized for its traffic.
+fn log_capacity_eviction<K: fmt::Debug, V>(cache: &'static str) -> impl Fn(&K, &V, Eviction) + Send + Sync {
+    move |key, _, eviction| {
+        if eviction == Eviction::Capacity {
+            log::debug!("evicted {:?} from {} cache to stay within capacity", key, cache);
+        }
+    }
+}
+
+/// Moves expired skins that can be revalidated into `stale`, rather than dropping them outright.
+fn keep_stale_skin<K, V, F>(stale: Arc<Cache<(String, Model), Arc<PlayerSkin>>>, skin: F) -> impl Fn(&K, &V, Eviction) + Send + Sync
+    where F: Fn(&V) -> Option<&Arc<PlayerSkin>> + Send + Sync,
+{
+    move |_, value, eviction| {
+        if eviction != Eviction::Expired {
+            return;
+        }
+        if let Some(skin) = skin(value) {
+            if let Some(source) = &skin.source {
+                stale.insert((source.url.clone(), source.model), skin.clone());
+            }
+        }
+    }
+}
+
+/// Moves expired profiles into `stale`, rather than dropping them outright.
+fn keep_stale_profile(stale: Arc<Cache<Uuid, Arc<PlayerProfile>>>) -> impl Fn(&Uuid, &Option<Arc<PlayerProfile>>, Eviction) + Send + Sync {
+    move |uuid, profile, eviction| {
+        if eviction != Eviction::Expired {
+            return;
+        }
+        if let Some(profile) = profile {
+            stale.insert(*uuid, profile.clone());
+        }
+    }
+}
+
+/// Caches for requests that pick a single source, kept apart so that players never leak
+/// between sources.
+struct SelectedSource {
+    caches: Arc<Caches>,
+    sources: Arc<Vec<Source>>,
+}
+
+#[derive(Clone)]
+pub struct Api {
+    caches: Arc<Caches>,
+    sources: Arc<Vec<Source>>,
+    selectable_sources: Arc<HashMap<String, SelectedSource>>,
+    source_selection_ips: Arc<Vec<IpNet>>,
+    budget: Arc<Budget>,
+    reachability: Arc<Reachability>,
+    ip_filter: Arc<IpFilter>,
+    blocklist: Arc<RwLock<HashSet<Uuid>>>,
+    blocked_textures: Arc<RwLock<HashMap<String, Uuid>>>,
+    usage: Arc<Usage<IpAddr>>,
+    tenants: Arc<Tenants>,
+    tunables: Arc<RwLock<Tunables>>,
+    /// Rate limiting state shared with other replicas, used in place of `tunables.rate_limiter`
+    /// while it can be reached.
+    shared_rate_limiter: Option<Arc<SharedRateLimiter>>,
+    /// Held while tuning so that concurrent changes apply one after another.
+    tuning: Arc<Mutex<()>>,
+    max_quality: u32,
+    pixel_art_upscaling: bool,
+    badges: Arc<HashMap<String, Arc<RgbaImage>>>,
+    default_skins: Arc<HashMap<DefaultSkin, Arc<PlayerSkin>>>,
+    pinger: Arc<Pinger>,
+    status_server: Option<Arc<str>>,
+    watcher: Arc<Watcher>,
+    stats: Option<Arc<StatsClient>>,
+    max_texture_bytes: u64,
+    hot: Arc<HotPlayers>,
+    render_queue: Arc<RenderQueue>,
+    /// Renders queued or running on the blocking pool, across every request to this instance.
+    pending_renders: Arc<AtomicUsize>,
+    priority_ips: Arc<Vec<IpNet>>,
 }
 
-impl Caches {
-    fn new() -> Caches {
-        Caches {
-            raw_faces: Cache::new(512),
-            faces: Cache::new(128),
+/// The settings that can be changed at runtime, swapped out as a whole.
+#[derive(Clone)]
+struct Tunables {
+    requests_per_minute: u32,
+    rate_limit_burst: Option<u32>,
+    rate_limiter: Arc<KeyedRateLimiter>,
+    rate_limit_wait: Duration,
+    readiness: ReadinessConfig,
+}
+
+impl Tunables {
+    fn new(requests_per_minute: u32, rate_limit_burst: Option<u32>, rate_limit_wait_ms: u64, readiness: ReadinessConfig) -> Tunables {
+        let mut quota = governor::Quota::per_minute(NonZeroU32::new(requests_per_minute).unwrap());
+        if let Some(burst) = rate_limit_burst {
+            quota = quota.allow_burst(NonZeroU32::new(burst).unwrap());
+        }
+        Tunables {
+            requests_per_minute,
+            rate_limit_burst,
+            rate_limiter: Arc::new(RateLimiter::dashmap(quota)),
+            rate_limit_wait: Duration::from_millis(rate_limit_wait_ms),
+            readiness,
+        }
+    }
+}
+
+/// Runtime settings as exposed through the admin API.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Tuning {
+    pub requests_per_minute: u32,
+    pub rate_limit_burst: Option<u32>,
+    pub rate_limit_wait_ms: u64,
+    pub readiness: ReadinessConfig,
+    pub stale: StaleConfig,
+    /// In-memory capacity by cache name, in entries or in bytes for caches bounded by size.
+    pub cache_capacities: BTreeMap<String, u64>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("`{field}` {constraint}")]
+pub struct InvalidTuning {
+    field: String,
+    constraint: &'static str,
+}
+
+/// What the config names but couldn't be loaded, which stops the service from starting.
+#[derive(thiserror::Error, Debug)]
+pub enum StartupError {
+    #[error("failed to load badge `{name}` from {path:?}: {source}")]
+    Badge { name: String, path: PathBuf, source: image::ImageError },
+    #[error("failed to read default skin {skin:?} from {path:?}: {source}")]
+    ReadDefaultSkin { skin: DefaultSkin, path: PathBuf, source: std::io::Error },
+    #[error("default skin {skin:?} at {path:?} {problem}")]
+    InvalidDefaultSkin { skin: DefaultSkin, path: PathBuf, problem: InvalidDefaultSkin },
+    #[error("invalid redis url {url:?}: {source}")]
+    Redis { url: String, source: redis::RedisError },
+}
+
+impl Api {
+    pub fn new(config: Config) -> std::result::Result<Api, StartupError> {
+        if let Some(user_agent) = &config.user_agent {
+            minecraft::set_user_agent(user_agent.clone());
+        }
+
+        // Each selectable source caches faces apart from the default sources, all within the one
+        // configured bound.
+        let face_bytes = config.face_cache_bytes / (config.sources.len() as u64 + 1);
+        let caches = Arc::new(Caches::new(&config, config.disk_cache.as_deref(), face_bytes));
+
+        if let Some(snapshot) = config.cache_snapshot.as_deref().and_then(|path| Snapshot::load(path, CACHE_TTL)) {
+            caches.restore(snapshot);
+        }
+
+        let selectable_sources = config.sources.iter()
+            .map(|(name, source)| {
+                let selected = SelectedSource {
+                    caches: Arc::new(Caches::new(&config, None, face_bytes)),
+                    sources: Arc::new(vec![Source::new(name.clone(), source)]),
+                };
+                (name.clone(), selected)
+            })
+            .collect();
+
+        let ip_filter = IpFilter::new(config.allowed_ips, config.denied_ips);
+        let ip_filter = Arc::new(ip_filter);
+
+        let max_quality = config.max_quality.max(1);
+
+        let badges = config.badges.iter()
+            .map(|(name, path)| {
+                let badge = image::open(path)
+                    .map_err(|source| StartupError::Badge { name: name.clone(), path: path.clone(), source })?;
+                Ok((name.clone(), Arc::new(badge.into_rgba8())))
+            })
+            .collect::<std::result::Result<_, StartupError>>()?;
+
+        let replacement_skins = &config.default_skins;
+        let default_skins = DefaultSkin::ALL.iter()
+            .map(|&default| {
+                let skin = match replacement_skins.get(&default) {
+                    Some(path) => {
+                        let bytes = std::fs::read(path)
+                            .map_err(|source| StartupError::ReadDefaultSkin { skin: default, path: path.clone(), source })?;
+                        let skin = default.load_replacement(&bytes)
+                            .map_err(|problem| StartupError::InvalidDefaultSkin { skin: default, path: path.clone(), problem })?;
+                        PlayerSkin { skin, png: ImageBytes::from(Bytes::from(bytes)), source: None }
+                    }
+                    None => PlayerSkin {
+                        skin: default.as_skin().clone(),
+                        png: ImageBytes::from(Bytes::from_static(default.png_bytes())),
+                        source: None,
+                    },
+                };
+                Ok((default, Arc::new(skin)))
+            })
+            .collect::<std::result::Result<_, StartupError>>()?;
+
+        let budget = Arc::new(Budget::new(&config.upstream_budget));
+
+        let shared_rate_limiter = match config.rate_limit_redis.as_deref() {
+            Some(url) => {
+                let limiter = SharedRateLimiter::new(url)
+                    .map_err(|source| StartupError::Redis { url: url.to_owned(), source })?;
+                Some(Arc::new(limiter))
+            }
+            None => None,
+        };
+
+        let api = Api {
+            caches,
+            sources: Arc::new(Source::all(&config.sources)),
+            selectable_sources: Arc::new(selectable_sources),
+            source_selection_ips: Arc::new(config.source_selection_ips),
+            budget,
+            reachability: Arc::new(Reachability::default()),
+            ip_filter,
+            blocklist: Arc::new(RwLock::new(config.blocked_uuids.iter().copied().collect())),
+            blocked_textures: Arc::new(RwLock::new(HashMap::new())),
+            usage: Arc::new(Usage::new()),
+            tenants: Arc::new(Tenants::new(&config.tenants)),
+            tunables: Arc::new(RwLock::new(Tunables::new(config.requests_per_minute, config.rate_limit_burst, config.rate_limit_wait_ms, config.readiness))),
+            tuning: Arc::new(Mutex::new(())),
+            shared_rate_limiter,
+            max_quality,
+            pixel_art_upscaling: config.pixel_art_upscaling,
+            badges: Arc::new(badges),
+            default_skins: Arc::new(default_skins),
+            pinger: Arc::new(Pinger::new(&config.ping)),
+            status_server: config.ping.status_server.as_deref().map(Arc::from),
+            watcher: Arc::new(Watcher::new(Duration::from_secs(config.watch_interval_secs), MAX_WATCHED_PLAYERS)),
+            stats: config.nucleoid.as_ref().map(|nucleoid| Arc::new(StatsClient::new(nucleoid))),
+            max_texture_bytes: config.max_texture_bytes,
+            hot: Arc::new(HotPlayers::default()),
+            render_queue: Arc::new(RenderQueue::new(&config.render_queue)),
+            pending_renders: Arc::new(AtomicUsize::new(0)),
+            priority_ips: Arc::new(config.render_queue.priority_ips.clone()),
+        };
+
+        for &uuid in &config.blocked_uuids {
+            api.learn_blocked_texture(uuid);
+        }
+        Ok(api)
+    }
+
+    /// Renders the service's metrics in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        let stats = self.caches.stats();
+        let mut exposition = Exposition::default();
+
+        let counters: [(&str, &str, StatCounter); 4] = [
+            ("player_face_cache_hits_total", "Cache lookups served from the cache.", cache::Stats::hits),
+            ("player_face_cache_misses_total", "Cache lookups that had to load the value.", cache::Stats::misses),
+            ("player_face_cache_load_errors_total", "Cache loads that failed.", cache::Stats::load_errors),
+            ("player_face_cache_evictions_total", "Cache entries evicted for capacity or expiry.", cache::Stats::evictions),
+        ];
+
+        for (name, help, counter) in counters.iter() {
+            exposition.family(name, Kind::Counter, help);
+            for (cache, cache_stats, _) in &stats {
+                exposition.sample(name, &[("cache", cache)], counter(cache_stats));
+            }
+        }
+
+        exposition.family("player_face_cache_entries", Kind::Gauge, "Entries currently held in the cache.");
+        for (cache, _, entries) in &stats {
+            exposition.sample("player_face_cache_entries", &[("cache", cache)], entries);
+        }
+
+        exposition.family("player_face_upstream_requests_total", Kind::Counter, "Requests made to Mojang, by host.");
+        for upstream in Upstream::ALL.iter() {
+            exposition.sample("player_face_upstream_requests_total", &[("upstream", upstream.as_str())], self.budget.requests(*upstream));
+        }
+
+        exposition.family("player_face_upstream_throttled_total", Kind::Counter, "Requests to Mojang refused to stay within budget, by host.");
+        for upstream in Upstream::ALL.iter() {
+            exposition.sample("player_face_upstream_throttled_total", &[("upstream", upstream.as_str())], self.budget.throttled(*upstream));
+        }
+
+        exposition.family("player_face_upstream_player_throttled_total", Kind::Counter, "Profile requests refused for a player having had their share of the budget.");
+        exposition.sample("player_face_upstream_player_throttled_total", &[], self.budget.players_throttled());
+
+        exposition.family("player_face_upstream_window_requests", Kind::Gauge, "Requests made to Mojang within the budget window, by host.");
+        for upstream in Upstream::ALL.iter() {
+            exposition.sample("player_face_upstream_window_requests", &[("upstream", upstream.as_str())], self.budget.in_window(*upstream));
+        }
+
+        exposition.family("player_face_upstream_window_limit", Kind::Gauge, "Configured ceiling on requests to Mojang within the budget window, by host.");
+        for upstream in Upstream::ALL.iter() {
+            if let Some(limit) = self.budget.limit(*upstream) {
+                exposition.sample("player_face_upstream_window_limit", &[("upstream", upstream.as_str())], limit);
+            }
+        }
+
+        exposition.family("player_face_open_connections", Kind::Gauge, "Client connections currently open.");
+        exposition.sample("player_face_open_connections", &[], metrics::OPEN_CONNECTIONS.get());
+
+        exposition.family("player_face_in_flight_requests", Kind::Gauge, "Requests currently being handled.");
+        exposition.sample("player_face_in_flight_requests", &[], metrics::IN_FLIGHT_REQUESTS.get());
+
+        let (running, queued) = self.render_queue.occupancy();
+        exposition.family("player_face_render_slots", Kind::Gauge, "Renders that may run at once.");
+        exposition.sample("player_face_render_slots", &[], self.render_queue.slots());
+
+        exposition.family("player_face_render_slots_busy", Kind::Gauge, "Renders currently holding a render slot.");
+        exposition.sample("player_face_render_slots_busy", &[], running);
+
+        exposition.family("player_face_render_queued", Kind::Gauge, "Renders waiting for a render slot.");
+        exposition.sample("player_face_render_queued", &[], queued);
+
+        exposition.family("player_face_blocking_tasks", Kind::Gauge, "Renders and texture decodes running on the blocking thread pool.");
+        exposition.sample("player_face_blocking_tasks", &[], metrics::BLOCKING_TASKS.get());
+
+        exposition.finish()
+    }
+
+    /// Running totals and current entry count for every cache, by name.
+    pub fn cache_stats(&self) -> BTreeMap<&'static str, CacheStats> {
+        self.caches.stats().into_iter()
+            .map(|(cache, stats, entries)| {
+                let stats = CacheStats {
+                    hits: stats.hits(),
+                    misses: stats.misses(),
+                    load_errors: stats.load_errors(),
+                    evictions: stats.evictions(),
+                    entries,
+                };
+                (cache, stats)
+            })
+            .collect()
+    }
+
+    pub fn tuning(&self) -> Tuning {
+        let tunables = self.tunables.read().unwrap().clone();
+        Tuning {
+            requests_per_minute: tunables.requests_per_minute,
+            rate_limit_burst: tunables.rate_limit_burst,
+            rate_limit_wait_ms: tunables.rate_limit_wait.as_millis() as u64,
+            readiness: tunables.readiness,
+            stale: StaleConfig {
+                ttl_secs: self.caches.stale_skins.ttl().as_secs(),
+                skins: self.caches.stale_skins.capacity(),
+                profiles: self.caches.stale_profiles.capacity(),
+            },
+            cache_capacities: self.caches.resizable().into_iter()
+                .map(|(cache, resize)| (cache.to_owned(), resize.capacity()))
+                .collect(),
+        }
+    }
+
+    /// Applies new runtime settings once all of them are known to be valid. Rate limiting starts
+    /// afresh if the rate or burst changes, and resized caches keep as many entries as fit.
+    pub fn tune(&self, tuning: Tuning) -> std::result::Result<(), InvalidTuning> {
+        let invalid = |field: &str, constraint| Err(InvalidTuning { field: field.to_owned(), constraint });
+
+        if tuning.requests_per_minute == 0 {
+            return invalid("requests_per_minute", "must be at least 1");
+        }
+        if tuning.rate_limit_burst == Some(0) {
+            return invalid("rate_limit_burst", "must be at least 1");
+        }
+        if tuning.readiness.max_pending_renders == 0 {
+            return invalid("readiness.max_pending_renders", "must be at least 1");
+        }
+        if tuning.stale.ttl_secs == 0 {
+            return invalid("stale.ttl_secs", "must be at least 1");
+        }
+        if tuning.stale.skins == 0 || tuning.stale.profiles == 0 {
+            return invalid("stale", "must keep at least 1 skin and profile");
+        }
+
+        let caches = self.caches.resizable();
+        for (cache, &capacity) in &tuning.cache_capacities {
+            if !caches.iter().any(|(name, _)| name == cache) {
+                return invalid(&format!("cache_capacities.{}", cache), "does not name a cache");
+            }
+            if capacity == 0 {
+                return invalid(&format!("cache_capacities.{}", cache), "must be at least 1");
+            }
+        }
+
+        let _tuning = self.tuning.lock().unwrap();
+
+        let current = self.tunables.read().unwrap().clone();
+        let quota_changed = tuning.requests_per_minute != current.requests_per_minute
+            || tuning.rate_limit_burst != current.rate_limit_burst;
+        let tunables = if quota_changed {
+            Tunables::new(tuning.requests_per_minute, tuning.rate_limit_burst, tuning.rate_limit_wait_ms, tuning.readiness)
+        } else {
+            Tunables {
+                rate_limit_wait: Duration::from_millis(tuning.rate_limit_wait_ms),
+                readiness: tuning.readiness,
+                ..current
+            }
+        };
+        *self.tunables.write().unwrap() = tunables;
+
+        let selected = self.selectable_sources.values().map(|selected| &*selected.caches);
+        for caches in std::iter::once(&*self.caches).chain(selected) {
+            caches.tune_stale(tuning.stale);
+            for (cache, resize) in caches.resizable() {
+                match tuning.cache_capacities.get(cache) {
+                    Some(&capacity) if capacity != resize.capacity() => {
+                        log::info!("resizing {} cache to {}", cache, capacity);
+                        resize.set_capacity(capacity);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts a response of `bytes` towards the usage statistics of `client` and its tenant.
+    pub fn record_usage(&self, client: &Client, bytes: u64) {
+        if let Some(addr) = client.addr {
+            self.usage.record(addr.ip(), bytes);
+        }
+        if let Some(tenant) = &client.tenant {
+            self.tenants.record_usage(tenant, bytes);
+        }
+    }
+
+    /// The `limit` busiest clients over the last hour.
+    pub fn usage(&self, limit: usize) -> Vec<ClientUsage> {
+        self.usage.top(limit).into_iter()
+            .map(|(ip, counts)| ClientUsage { ip, counts })
+            .collect()
+    }
+
+    /// Every tenant that made requests over the last hour, busiest first.
+    pub fn tenant_usage(&self) -> Vec<TenantUsage> {
+        self.tenants.usage()
+    }
+
+    pub fn tenants(&self) -> Arc<Tenants> {
+        self.tenants.clone()
+    }
+
+    /// Every blocked player, in order.
+    pub fn blocked_uuids(&self) -> Vec<Uuid> {
+        let mut blocked: Vec<Uuid> = self.blocklist.read().unwrap().iter().copied().collect();
+        blocked.sort();
+        blocked
+    }
+
+    /// Stops serving `uuid` until restarted, returning whether it wasn't already blocked.
+    pub fn block(&self, uuid: Uuid) -> bool {
+        let blocked = self.blocklist.write().unwrap().insert(uuid);
+        if blocked {
+            self.learn_blocked_texture(uuid);
+        }
+        blocked
+    }
+
+    /// Returns whether `uuid` was blocked.
+    pub fn unblock(&self, uuid: Uuid) -> bool {
+        self.blocked_textures.write().unwrap().retain(|_, owner| *owner != uuid);
+        self.blocklist.write().unwrap().remove(&uuid)
+    }
+
+    /// Looks up the skin of a blocked player in the background, so that faces rendered straight
+    /// from its texture are refused too.
+    fn learn_blocked_texture(&self, uuid: Uuid) {
+        let api = self.access(self.caches.clone(), self.sources.clone(), None, Priority::Normal);
+        tokio::spawn(async move {
+            let cached = api.caches.profiles.get(&uuid).flatten()
+                .or_else(|| api.caches.stale_profiles.get(&uuid));
+            let profile = match cached {
+                Some(profile) => Some(profile),
+                None => match load_profile(api.clone(), uuid).await {
+                    Ok(profile) => profile,
+                    Err(err) => {
+                        log::warn!("failed to look up the skin of blocked player {}: {:?}", uuid, err);
+                        return;
+                    }
+                },
+            };
+
+            let hash = profile.and_then(|profile| PlayerInfo::new(uuid, &profile).texture_hash);
+            if let Some(hash) = hash {
+                if api.blocklist.read().unwrap().contains(&uuid) {
+                    api.blocked_textures.write().unwrap().insert(hash, uuid);
+                }
+            }
+        });
+    }
+
+    pub fn save_snapshot(&self, path: &Path) {
+        let snapshot = self.caches.snapshot();
+        log::info!("saving {} profiles and {} faces to cache snapshot", snapshot.profiles.len(), snapshot.raw_faces.len());
+
+        if let Err(err) = snapshot.save(path) {
+            log::error!("failed to save cache snapshot: {:?}", err);
+        }
+    }
+
+    /// The cached profiles and faces, for seeding the caches of another instance.
+    pub(crate) fn export_cache(&self) -> Snapshot {
+        self.caches.snapshot()
+    }
+
+    /// Fills the caches from a snapshot exported by another instance, unless it is older than
+    /// anything the caches would still hold. Returns whether it was restored.
+    pub(crate) fn import_cache(&self, snapshot: Snapshot) -> bool {
+        if snapshot.age() > CACHE_TTL.as_secs() {
+            return false;
+        }
+        self.caches.restore(snapshot);
+        true
+    }
+
+    /// Whether this instance can usefully serve traffic, for orchestrators' readiness probes.
+    pub fn readiness(&self) -> Readiness {
+        let readiness = self.tunables.read().unwrap().readiness.clone();
+        let window = Duration::from_secs(readiness.upstream_window_secs);
+        let pending_renders = self.pending_renders.load(Ordering::Relaxed);
+        Readiness {
+            upstream_reachable: self.reachability.is_reachable(window),
+            pending_renders,
+            render_pool_saturated: pending_renders >= readiness.max_pending_renders,
+        }
+    }
+
+    /// Checks `client` against the IP filter and rate limiter. Rate limited clients wait up to
+    /// the configured duration for a token before being turned away. Trusted clients may restrict
+    /// the request to a single named `source`.
+    pub async fn try_access(&self, client: &Client, source: Option<&str>) -> std::result::Result<ApiAccess, AccessDenied> {
+        let ip = client.addr.map(|addr| addr.ip());
+        if !self.ip_filter.permits(ip) {
+            return Err(AccessDenied::Forbidden);
+        }
+
+        let (caches, sources) = match source {
+            Some(source) => {
+                if !access::covers(&self.source_selection_ips, ip) {
+                    return Err(AccessDenied::Forbidden);
+                }
+                let selected = self.selectable_sources.get(source).ok_or(AccessDenied::UnknownSource)?;
+                (selected.caches.clone(), selected.sources.clone())
+            }
+            None => (self.caches.clone(), self.sources.clone()),
+        };
+
+        self.rate_limit(client).await?;
+
+        // Players are only refreshed in the shared caches, so only requests using them count.
+        let hot = source.is_none().then(|| self.hot.clone());
+        let priority = if access::covers(&self.priority_ips, ip) { Priority::High } else { Priority::Normal };
+        Ok(self.access(caches, sources, hot, priority))
+    }
+
+    /// Counts a request against the quota of its tenant, or else of the client at its address.
+    pub(crate) async fn rate_limit(&self, client: &Client) -> std::result::Result<(), AccessDenied> {
+        let Tunables { requests_per_minute, rate_limit_burst, rate_limiter, rate_limit_wait, .. } = self.tunables.read().unwrap().clone();
+        let (key, requests_per_minute, burst) = match (&client.tenant, client.addr) {
+            (Some(tenant), _) => (format!("tenant:{}", tenant.name()), tenant.requests_per_minute(), tenant.burst()),
+            (None, Some(addr)) => (addr.ip().to_string(), requests_per_minute, rate_limit_burst.unwrap_or(requests_per_minute)),
+            (None, None) => return Ok(()),
+        };
+
+        timing::enter("rate_limit");
+        if let Some(shared) = &self.shared_rate_limiter {
+            match shared.acquire(&key, requests_per_minute, burst, rate_limit_wait).await {
+                Ok(Some(wait)) => {
+                    tokio::time::sleep(wait).await;
+                    return Ok(());
+                }
+                Ok(None) => return Err(AccessDenied::RateLimited),
+                Err(err) => log::warn!("failed to rate limit through redis, limiting locally: {}", err),
+            }
+        }
+
+        match (&client.tenant, &client.addr) {
+            (Some(tenant), _) => tenant.acquire_locally(rate_limit_wait).await.then_some(()).ok_or(AccessDenied::RateLimited),
+            (None, Some(addr)) => rate_limit_locally(&rate_limiter, addr, rate_limit_wait).await,
+            (None, None) => Ok(()),
+        }
+    }
+
+    /// Revalidates the players requested most lately, so that they are never served cold and
+    /// changes to their skins show up long before their entries would expire.
+    pub async fn refresh_hot_players(&self, limit: usize) {
+        let api = self.access(self.caches.clone(), self.sources.clone(), None, Priority::Normal);
+        for uuid in self.hot.take_top(limit) {
+            let result = match api.revalidate(uuid).await {
+                Ok(Some(_)) => get_raw_face(api.clone(), uuid).await.map(|_| ()),
+                Ok(None) => Ok(()),
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) | Err(Error::Blocked) => (),
+                // What budget is left is better spent on requests.
+                Err(Error::UpstreamThrottled) | Err(Error::Overloaded) => break,
+                Err(err) => log::debug!("failed to refresh hot player {}: {:?}", uuid, err),
+            }
+        }
+    }
+
+    fn access(&self, caches: Arc<Caches>, sources: Arc<Vec<Source>>, hot: Option<Arc<HotPlayers>>, priority: Priority) -> ApiAccess {
+        ApiAccess {
+            caches,
+            sources,
+            blocklist: self.blocklist.clone(),
+            blocked_textures: self.blocked_textures.clone(),
+            budget: self.budget.clone(),
+            reachability: self.reachability.clone(),
+            max_quality: self.max_quality,
+            pixel_art_upscaling: self.pixel_art_upscaling,
+            badges: self.badges.clone(),
+            default_skins: self.default_skins.clone(),
+            pinger: self.pinger.clone(),
+            status_server: self.status_server.clone(),
+            watcher: self.watcher.clone(),
+            stats: self.stats.clone(),
+            max_texture_bytes: self.max_texture_bytes,
+            hot,
+            render_queue: self.render_queue.clone(),
+            pending_renders: self.pending_renders.clone(),
+            priority,
+        }
+    }
+}
+
+/// Waits up to `rate_limit_wait` for the client at `addr` to have a token to spend.
+async fn rate_limit_locally(rate_limiter: &KeyedRateLimiter, addr: &SocketAddr, rate_limit_wait: Duration) -> std::result::Result<(), AccessDenied> {
+    if rate_limiter.check_key(addr).is_ok() {
+        return Ok(());
+    }
+    if rate_limit_wait.is_zero() {
+        return Err(AccessDenied::RateLimited);
+    }
+
+    let ready = rate_limiter.until_key_ready_with_jitter(addr, Jitter::up_to(RATE_LIMIT_JITTER));
+    match tokio::time::timeout(rate_limit_wait, ready).await {
+        Ok(()) => Ok(()),
+        Err(_) => Err(AccessDenied::RateLimited),
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheStats {
+    hits: u64,
+    misses: u64,
+    load_errors: u64,
+    evictions: u64,
+    entries: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    upstream_reachable: bool,
+    pending_renders: usize,
+    render_pool_saturated: bool,
+}
+
+impl Readiness {
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.upstream_reachable && !self.render_pool_saturated
+    }
+}
+
+/// Who a request comes from, as far as access to the API is concerned.
+#[derive(Clone, Default)]
+pub struct Client {
+    pub addr: Option<SocketAddr>,
+    /// The tenant the request was made on behalf of, whose quota it draws from rather than the
+    /// client's own.
+    pub tenant: Option<Arc<Tenant>>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum AccessDenied {
+    Forbidden,
+    RateLimited,
+    UnknownSource,
+}
+
+#[derive(Clone)]
+pub struct ApiAccess {
+    caches: Arc<Caches>,
+    /// The sources to load players from, in the order to ask them.
+    sources: Arc<Vec<Source>>,
+    blocklist: Arc<RwLock<HashSet<Uuid>>>,
+    /// The skins of blocked players, by texture hash.
+    blocked_textures: Arc<RwLock<HashMap<String, Uuid>>>,
+    budget: Arc<Budget>,
+    reachability: Arc<Reachability>,
+    max_quality: u32,
+    pixel_art_upscaling: bool,
+    badges: Arc<HashMap<String, Arc<RgbaImage>>>,
+    /// What to show for players without a skin of their own, the built-in skins unless replaced.
+    default_skins: Arc<HashMap<DefaultSkin, Arc<PlayerSkin>>>,
+    pinger: Arc<Pinger>,
+    status_server: Option<Arc<str>>,
+    watcher: Arc<Watcher>,
+    stats: Option<Arc<StatsClient>>,
+    max_texture_bytes: u64,
+    /// Where requests for players are counted towards their popularity, unless they shouldn't
+    /// be, as with our own refreshes.
+    hot: Option<Arc<HotPlayers>>,
+    render_queue: Arc<RenderQueue>,
+    pending_renders: Arc<AtomicUsize>,
+    priority: Priority,
+}
+
+impl ApiAccess {
+    #[inline]
+    fn acquire(&self, upstream: Upstream) -> Result<()> {
+        timing::enter(upstream.as_str());
+        if self.budget.try_acquire(upstream) {
+            Ok(())
+        } else {
+            log::warn!("refusing {} request to stay within the upstream budget", upstream.as_str());
+            Err(Error::UpstreamThrottled)
+        }
+    }
+
+    /// Checked before any cache so that a blocked player's warm entries are never served.
+    #[inline]
+    fn check_blocked(&self, uuid: Uuid) -> Result<()> {
+        if self.blocklist.read().unwrap().contains(&uuid) {
+            Err(Error::Blocked)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The texture route identifies skins rather than players, so a blocked player's skin is
+    /// refused by its hash.
+    #[inline]
+    fn check_blocked_texture(&self, hash: &str) -> Result<()> {
+        if self.blocked_textures.read().unwrap().contains_key(hash) {
+            Err(Error::Blocked)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs image work off the async runtime once a render slot is free, recorded as the render
+    /// phase of the request.
+    async fn render<F, T>(&self, render: F) -> Result<T>
+        where F: FnOnce() -> Result<T> + Send + 'static,
+              T: Send + 'static,
+    {
+        timing::enter("render_queue");
+        let _pending = PendingRender::start(&self.pending_renders);
+        let _slot = self.render_queue.acquire(self.priority).await.map_err(|_| Error::Overloaded)?;
+
+        timing::enter("render");
+        let timeline = timing::current();
+        let render = move || {
+            let _blocking = metrics::BLOCKING_TASKS.enter();
+            timing::blocking(timeline, render)
+        };
+        match tokio::task::spawn_blocking(render).await {
+            Ok(result) => result,
+            // Carry the render's own panic up to the request, which turns it into a 500.
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    /// Called first on every request for a player.
+    #[inline]
+    fn enter_player(&self, uuid: Uuid) -> Result<()> {
+        timing::enter_player(uuid);
+        self.check_blocked(uuid)?;
+        if let Some(hot) = &self.hot {
+            hot.record(uuid);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn primary_source(&self) -> &Source {
+        &self.sources[0]
+    }
+
+    /// The source a texture URL from a profile belongs to, for its timeout.
+    fn texture_source(&self, url: &str) -> &Source {
+        self.sources.iter()
+            .find(|source| source.serves_texture(url))
+            .unwrap_or_else(|| self.primary_source())
+    }
+
+    /// Spends the budget for `upstream` on a request to `source`. Sources read from a local
+    /// directory cost nothing.
+    #[inline]
+    fn acquire_from(&self, source: &Source, upstream: Upstream) -> Result<()> {
+        match source.directory {
+            Some(_) => Ok(()),
+            None => self.acquire(upstream),
+        }
+    }
+
+    /// Like `observe`, but only for sources reached over HTTP, local directories saying
+    /// nothing about whether upstream is reachable.
+    #[inline]
+    fn observe_from<T>(&self, source: &Source, result: minecraft::Result<T>) -> minecraft::Result<T> {
+        match source.directory {
+            Some(_) => result,
+            None => self.observe(result),
+        }
+    }
+
+    /// Notes whether an upstream request got through, for the readiness probe.
+    fn observe<T>(&self, result: minecraft::Result<T>) -> minecraft::Result<T> {
+        match &result {
+            Ok(_) => self.reachability.record_success(),
+            Err(minecraft::Error::Http(_)) => self.reachability.record_failure(),
+            Err(_) => (),
+        }
+        result
+    }
+
+    /// Falls back to nearest-neighbour upscaling where pixel-art interpolation is disabled, and
+    /// rejects badges that aren't configured.
+    fn check_face_options(&self, options: FaceOptions) -> Result<FaceOptions> {
+        if options.badge.as_ref().is_some_and(|badge| !self.badges.contains_key(badge)) {
+            return Err(Error::UnknownBadge);
+        }
+        if options.status && self.status_server.is_none() {
+            return Err(Error::NoStatusServer);
+        }
+
+        if self.pixel_art_upscaling {
+            Ok(options)
+        } else {
+            Ok(FaceOptions { upscale: Upscale::Nearest, ..options })
+        }
+    }
+
+    /// Whether the player is seen on the status server, taking them to be offline if it can't
+    /// be reached.
+    async fn is_online(&self, uuid: Uuid) -> bool {
+        let server = match &self.status_server {
+            Some(server) => server,
+            None => return false,
+        };
+
+        timing::enter("ping");
+        match self.pinger.status(server).await {
+            Ok(status) => status.is_online(uuid),
+            Err(_) => false,
+        }
+    }
+
+    /// Whether player statistics, and so cards, are available.
+    #[inline]
+    pub fn has_stats(&self) -> bool {
+        self.stats.is_some()
+    }
+
+    #[inline]
+    fn badge(&self, options: &FaceOptions) -> Option<Arc<RgbaImage>> {
+        options.badge.as_ref().and_then(|badge| self.badges.get(badge).cloned())
+    }
+
+    #[inline]
+    pub async fn get_face(&self, uuid: Uuid, size: u32, options: FaceOptions) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let (size, options) = self.face_key(uuid, size, options).await?;
+        get_face(self.clone(), uuid, size, options).await
+    }
+
+    /// The ETag the face would be served with, if it has already been rendered. Nothing is
+    /// loaded or rendered to find out.
+    pub async fn cached_face_etag(&self, uuid: Uuid, size: u32, options: FaceOptions) -> Result<Option<String>> {
+        self.check_blocked(uuid)?;
+        let key = self.face_key(uuid, size, options).await?;
+        Ok(self.caches.faces.get(&(uuid, key.0, key.1)).map(|face| face.etag().to_owned()))
+    }
+
+    /// The size and options a face is cached under.
+    async fn face_key(&self, uuid: Uuid, size: u32, options: FaceOptions) -> Result<(u32, FaceOptions)> {
+        let mut options = self.check_face_options(options)?;
+        if options.status {
+            options.online = Some(self.is_online(uuid).await);
+        }
+        Ok((cache_size(size, &options), options))
+    }
+
+    /// Renders a face straight from the skin with the given texture hash, independent of any player.
+    /// Returns `None` if the texture is not a valid skin. Options showing the player's name or
+    /// status are refused, there being no player to show them for, as are the skins of blocked
+    /// players.
+    pub async fn get_texture_face(&self, hash: String, size: u32, options: FaceOptions) -> Result<Option<(ImageBytes, cache::Outcome)>> {
+        if options.label || options.status {
+            return Err(Error::NoPlayer);
+        }
+        self.check_blocked_texture(&hash)?;
+        let options = self.check_face_options(options)?;
+        let size = cache_size(size, &options);
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        let (face, outcome) = caches.texture_faces.try_get_outcome((hash, size, options), move |(hash, size, options)| load_texture_face(api, hash, size, options)).await?;
+        Ok(face.map(|face| (face, outcome)))
+    }
+
+    pub async fn get_head(&self, uuid: Uuid, size: u32, options: HeadOptions) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let options = HeadOptions { quality: options.quality.min(self.max_quality), ..options };
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.heads.try_get_outcome((uuid, size, options), move |(uuid, size, options)| load_head(api, uuid, size, options)).await
+    }
+
+    /// `size` is the pixel size of each head in the sheet.
+    pub async fn get_compass(&self, uuid: Uuid, size: u32, options: CompassOptions) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let options = CompassOptions { quality: options.quality.min(self.max_quality), ..options };
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.compasses.try_get_outcome((uuid, size, options), move |(uuid, size, options)| load_compass(api, uuid, size, options)).await
+    }
+
+    /// `size` is the pixel width of the head, as with faces; it must be at least 8.
+    pub async fn get_views(&self, uuid: Uuid, size: u32, options: BodyOptions) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.views.try_get_outcome((uuid, size, options), move |(uuid, size, options)| load_views(api, uuid, size, options)).await
+    }
+
+    /// `size` is the pixel width of the head, as with views; it must be at least 8.
+    pub async fn get_preview(&self, uuid: Uuid, size: u32, options: BodyOptions) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.previews.try_get_outcome((uuid, size, options), move |(uuid, size, options)| load_preview(api, uuid, size, options)).await
+    }
+
+    /// `size` is the pixel width of the head at its usual size, as with views; it must be at
+    /// least 8.
+    pub async fn get_chibi(&self, uuid: Uuid, size: u32, options: ChibiOptions) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.chibis.try_get_outcome((uuid, size, options), move |(uuid, size, options)| load_chibi(api, uuid, size, options)).await
+    }
+
+    /// The dominant and average colors of the player's face, for theming around it.
+    pub async fn get_face_colors(&self, uuid: Uuid) -> Result<FaceColors> {
+        self.enter_player(uuid)?;
+        let face = get_raw_face(self.clone(), uuid).await?;
+        Ok(render::face_colors(&face.image))
+    }
+
+    /// How alike the faces of players `a` and `b` are, for spotting impersonation.
+    pub async fn compare_faces(&self, a: Uuid, b: Uuid) -> Result<Similarity> {
+        self.enter_player(a)?;
+        self.enter_player(b)?;
+        let (a, b) = futures::try_join!(get_raw_face(self.clone(), a), get_raw_face(self.clone(), b))?;
+        Ok(render::compare(&a.image, &b.image))
+    }
+
+    /// Renders the face of player `a` with where it differs from that of player `b`
+    /// highlighted. `size` is the pixel width of the diff; it must be at least 8.
+    pub async fn get_diff(&self, a: Uuid, b: Uuid, size: u32) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(a)?;
+        self.enter_player(b)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.diffs.try_get_outcome((a, b, size), move |(a, b, size)| load_diff(api, a, b, size)).await
+    }
+
+    pub async fn get_banner(&self, uuid: Uuid, options: BannerOptions) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.banners.try_get_outcome((uuid, options), move |(uuid, options)| load_banner(api, uuid, options)).await
+    }
+
+    /// The front of the player's cape, or `None` if they have no cape or it lacks the frame
+    /// asked for.
+    pub async fn get_cape(&self, uuid: Uuid, options: CapeOptions) -> Result<Option<(ImageBytes, cache::Outcome)>> {
+        self.enter_player(uuid)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        let (cape, outcome) = caches.capes.try_get_outcome((uuid, options), move |(uuid, options)| load_cape(api, uuid, options)).await?;
+        Ok(cape.map(|cape| (cape, outcome)))
+    }
+
+    pub async fn get_card(&self, uuid: Uuid, options: CardOptions) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        if self.stats.is_none() {
+            return Err(Error::NoStatsApi);
+        }
+
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.cards.try_get_outcome((uuid, options), move |(uuid, options)| load_card(api, uuid, options)).await
+    }
+
+    /// The status of a server named by the client, which must be reachable from the internet.
+    pub async fn get_server_status(&self, address: &str) -> std::result::Result<Arc<ServerStatus>, ping::Error> {
+        timing::enter("ping");
+        self.pinger.public_status(address).await.map(|(status, _)| status)
+    }
+
+    /// The favicon of a server named by the client, or `None` if it has none.
+    pub async fn get_server_icon(&self, address: &str) -> std::result::Result<Option<(ImageBytes, cache::Outcome)>, ping::Error> {
+        timing::enter("ping");
+        let (status, outcome) = self.pinger.public_status(address).await?;
+        Ok(status.favicon_png().map(|png| (ImageBytes::new(Bytes::from(png), "image/png"), outcome)))
+    }
+
+    /// How long server statuses are reused for, and so how long clients may cache them.
+    #[inline]
+    pub fn server_cache_duration(&self) -> Duration {
+        self.pinger.cache_duration()
+    }
+
+    /// `scale` is the pixel size of each texel, from 1 to [`MAX_LAYOUT_SCALE`](crate::options::MAX_LAYOUT_SCALE).
+    pub async fn get_layout(&self, uuid: Uuid, scale: u32) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.layouts.try_get_outcome((uuid, scale), move |(uuid, scale)| load_layout(api, uuid, scale)).await
+    }
+
+    pub async fn get_skin_png(&self, uuid: Uuid) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        let (skin, outcome) = caches.skins.try_get_outcome(uuid, move |uuid| load_skin(api, uuid)).await?;
+        Ok((skin.png.clone(), outcome))
+    }
+
+    /// Resolves usernames to UUIDs, looking up uncached names in as few upstream requests as
+    /// possible. Results are in the order given, with `None` for names without an account.
+    /// Names must be valid usernames.
+    pub async fn resolve_usernames(&self, names: &[String]) -> Result<Vec<Option<Uuid>>> {
+        let keys: Vec<String> = names.iter().map(|name| name.to_ascii_lowercase()).collect();
+
+        let mut resolved = HashMap::new();
+        let mut missing = Vec::new();
+        for key in &keys {
+            if resolved.contains_key(key) || missing.contains(key) {
+                continue;
+            }
+            match self.caches.usernames.get(key) {
+                Some(uuid) => { resolved.insert(key.clone(), uuid); }
+                None => missing.push(key.clone()),
+            }
         }
+
+        for chunk in missing.chunks(minecraft::MAX_NAMES_PER_REQUEST) {
+            self.acquire(Upstream::Api)?;
+            let profiles = self.observe(minecraft::get_name_profiles(chunk).await)?;
+            for key in chunk {
+                let uuid = profiles.iter()
+                    .find(|profile| profile.name.eq_ignore_ascii_case(key))
+                    .map(|profile| profile.id);
+                self.caches.usernames.insert(key.clone(), uuid);
+                resolved.insert(key.clone(), uuid);
+            }
+        }
+
+        Ok(keys.iter().map(|key| resolved.get(key).copied().flatten()).collect())
+    }
+
+    pub async fn get_info(&self, uuid: Uuid) -> Result<Option<PlayerInfo>> {
+        self.enter_player(uuid)?;
+        let profile = get_profile(self.clone(), uuid).await?;
+        Ok(profile.map(|profile| PlayerInfo::new(uuid, &profile)))
+    }
+
+    /// Fetches the player's profile afresh in place of the cached one. If their skin changed,
+    /// everything rendered from the old one is dropped so that the next request sees the new.
+    pub async fn revalidate(&self, uuid: Uuid) -> Result<Option<PlayerInfo>> {
+        self.check_blocked(uuid)?;
+        let profile = load_profile(self.clone(), uuid).await?;
+        let info = profile.as_ref().map(|profile| PlayerInfo::new(uuid, profile));
+
+        let cached = self.caches.profiles.get(&uuid).flatten()
+            .map(|profile| PlayerInfo::new(uuid, &profile));
+        if cached.and_then(|cached| cached.texture_hash) != info.as_ref().and_then(|info| info.texture_hash.clone()) {
+            log::debug!("skin of {} changed", uuid);
+            self.caches.forget_skin(uuid);
+        }
+
+        self.caches.profiles.insert(uuid, profile);
+        Ok(info)
+    }
+
+    /// Subscribes to changes to the skin of the player `current` describes. Returns `None` if
+    /// too many players are being watched already.
+    #[inline]
+    pub fn watch(&self, current: &PlayerInfo) -> Option<broadcast::Receiver<PlayerInfo>> {
+        self.watcher.watch(self.clone(), current)
+    }
+
+    pub async fn get_profile(&self, uuid: Uuid) -> Result<(Option<ProfileView>, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        let (profile, outcome) = caches.profiles.try_get_outcome(uuid, move |uuid| load_profile(api, uuid)).await?;
+
+        let profile = profile.map(|profile| ProfileView {
+            id: profile.id,
+            name: profile.name.clone(),
+            textures: profile.property("textures"),
+        });
+        Ok((profile, outcome))
     }
 
-    async fn clear(&self) {
-        self.raw_faces.clear().await;
-        self.faces.clear().await;
+    pub async fn get_normalized_skin_png(&self, uuid: Uuid) -> Result<(ImageBytes, cache::Outcome)> {
+        self.enter_player(uuid)?;
+        let caches = self.caches.clone();
+        let api = self.clone();
+        timing::enter("cache");
+        caches.normalized_skins.try_get_outcome(uuid, move |uuid| load_normalized_skin(api, uuid)).await
     }
 }
 
-#[derive(Clone)]
-pub struct Api {
-    caches: Arc<Caches>,
-    rate_limiter: Arc<RateLimiter<SocketAddr, DashMapStateStore<SocketAddr>, DefaultClock>>,
+#[derive(Serialize, Debug, Clone)]
+pub struct PlayerInfo {
+    pub uuid: Uuid,
+    pub name: String,
+    pub model: Model,
+    pub custom_skin: bool,
+    pub cape: bool,
+    pub texture_hash: Option<String>,
+    pub timestamp: Option<u64>,
 }
 
-impl Api {
-    pub fn new(config: Config) -> Api {
-        let caches = Arc::new(Caches::new());
+impl PlayerInfo {
+    fn new(uuid: Uuid, profile: &PlayerProfile) -> PlayerInfo {
+        let textures = profile.textures();
+        let skin = textures.as_ref().and_then(|textures| textures.refs.skin.as_ref());
+        let cape = textures.as_ref().and_then(|textures| textures.refs.cape.as_ref());
 
-        let caches_weak = Arc::downgrade(&caches);
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(CACHE_CLEAR_INTERVAL);
-            loop {
-                interval.tick().await;
-                if let Some(caches) = caches_weak.upgrade() {
-                    caches.clear().await;
-                } else {
-                    break;
-                }
-            }
-        });
+        let model = match skin {
+            Some(skin) => Model::from_metadata(&skin.metadata),
+            None => skin::DefaultSkin::from(uuid).model(),
+        };
+
+        PlayerInfo {
+            uuid,
+            name: profile.name.clone(),
+            model,
+            custom_skin: skin.is_some(),
+            cape: cape.is_some(),
+            texture_hash: skin.and_then(|skin| skin.hash()).map(str::to_owned),
+            timestamp: textures.as_ref().map(|textures| textures.timestamp),
+        }
+    }
+}
+
+/// A player profile with its textures property decoded rather than base64-encoded.
+#[derive(Serialize, Debug, Clone)]
+pub struct ProfileView {
+    pub id: Uuid,
+    pub name: String,
+    pub textures: Option<serde_json::Value>,
+}
+
+/// A player's skin along with the PNG it was decoded from, falling back to the default skin.
+pub struct PlayerSkin {
+    pub skin: Skin,
+    pub png: ImageBytes,
+    /// Where the skin was downloaded from, if it can be revalidated there.
+    source: Option<SkinSource>,
+}
 
-        let quota = governor::Quota::per_minute(NonZeroU32::new(config.requests_per_minute).unwrap());
-        let rate_limiter = RateLimiter::dashmap(quota);
-        let rate_limiter = Arc::new(rate_limiter);
+struct SkinSource {
+    url: String,
+    model: Model,
+    validators: minecraft::Validators,
+}
 
-        Api { caches, rate_limiter }
+/// Favicons and vector faces don't depend on the requested size, so share one cache entry.
+#[inline]
+fn cache_size(size: u32, options: &FaceOptions) -> u32 {
+    match options.format {
+        OutputFormat::Ico | OutputFormat::Svg => 0,
+        OutputFormat::Png | OutputFormat::Tga | OutputFormat::Raw | OutputFormat::Ansi => size,
     }
+}
+
+async fn get_face(api: ApiAccess, uuid: Uuid, size: u32, options: FaceOptions) -> Result<(ImageBytes, cache::Outcome)> {
+    let caches = api.caches.clone();
+    timing::enter("cache");
+    caches.faces.try_get_outcome((uuid, size, options), move |(uuid, size, options)| load_face(api, uuid, size, options)).await
+}
 
-    pub fn try_access(&self, addr: Option<&SocketAddr>) -> Option<ApiAccess> {
-        if let Some(addr) = addr {
-            if let Err(_) = self.rate_limiter.check_key(addr) {
-                return None;
+async fn get_profile(api: ApiAccess, uuid: Uuid) -> Result<Option<Arc<PlayerProfile>>> {
+    let caches = api.caches.clone();
+    timing::enter("cache");
+    caches.profiles.try_get(uuid, move |uuid| load_profile(api, uuid)).await
+}
+
+/// Asks each source in turn for the profile, moving on when a source fails or doesn't know the
+/// player. Fails only if no source has the profile and at least one failed.
+async fn load_profile(api: ApiAccess, uuid: Uuid) -> Result<Option<Arc<PlayerProfile>>> {
+    let mut error = None;
+    let mut throttled = None;
+    let mut player_budget_spent = false;
+    for source in api.sources.iter() {
+        // The per-player budget is spent once, on the first source that goes upstream.
+        if source.directory.is_none() && !player_budget_spent {
+            if !api.budget.try_acquire_player(uuid) {
+                // Answer with what we last knew, if anything. Otherwise fail rather than have the
+                // player cached as having no profile, or the default skin cached in place of theirs.
+                log::warn!("refusing profile request for {} to stay within the per-player budget", uuid);
+                return match api.caches.stale_profiles.get(&uuid) {
+                    Some(profile) => Ok(Some(profile)),
+                    None => Err(Error::UpstreamThrottled),
+                };
+            }
+            player_budget_spent = true;
+        }
+
+        // A refusal only rules out this source. Those read from a directory cost nothing, so
+        // may yet answer.
+        if let Err(err) = api.acquire_from(source, Upstream::SessionServer) {
+            throttled = Some(err);
+            continue;
+        }
+        match api.observe_from(source, minecraft::get_profile(source, uuid).await) {
+            Ok(Some(profile)) => return Ok(Some(Arc::new(profile))),
+            Ok(None) => (),
+            Err(err) => {
+                log::warn!("failed to load profile for {} from {}: {:?}", uuid, source.name, err);
+                error = Some(err);
             }
         }
+    }
 
-        Some(ApiAccess { caches: self.caches.clone() })
+    // Not having found the player is only an answer if every source was asked.
+    match (error, throttled) {
+        (Some(err), _) => Err(err.into()),
+        (None, Some(err)) => Err(err),
+        (None, None) => Ok(None),
     }
 }
 
-#[derive(Clone)]
-pub struct ApiAccess {
-    caches: Arc<Caches>,
+async fn get_raw_face(api: ApiAccess, uuid: Uuid) -> Result<Arc<PlayerFace>> {
+    let caches = api.caches.clone();
+    timing::enter("cache");
+    caches.raw_faces.try_get(uuid, move |uuid| load_raw_face(api, uuid)).await
 }
 
-impl ApiAccess {
-    #[inline]
-    pub async fn get_face(&self, uuid: Uuid, scale: u32) -> Result<ImageBytes> {
-        get_face(self.clone(), uuid, scale).await
+async fn load_face(api: ApiAccess, uuid: Uuid, size: u32, options: FaceOptions) -> Result<ImageBytes> {
+    let label = match (&options.caption, options.label) {
+        (Some(caption), _) => Some(caption.clone()),
+        (None, true) => get_profile(api.clone(), uuid).await?.map(|profile| profile.name.clone()),
+        (None, false) => None,
+    };
+
+    let badge = api.badge(&options);
+    let raw_face = get_raw_face(api.clone(), uuid).await?;
+
+    // The face as the skin has it needs no work at all, having been encoded along with the skin.
+    let plain = FaceOptions { resample: options.resample, upscale: options.upscale, ..FaceOptions::default() } == options;
+    if plain && label.is_none() && size == raw_face.image.width() {
+        return Ok(raw_face.png.clone());
     }
+
+    api.render(move || render_face_bytes(&raw_face.image, size, &options, label, badge.as_deref())).await
 }
 
-async fn get_face(api: ApiAccess, uuid: Uuid, scale: u32) -> Result<ImageBytes> {
-    let caches = api.caches.clone();
-    caches.faces.try_get((uuid, scale), move |(uuid, scale)| load_face(api, uuid, scale)).await
+async fn load_texture_face(api: ApiAccess, hash: String, size: u32, options: FaceOptions) -> Result<Option<ImageBytes>> {
+    let texture = minecraft::PlayerTextureRef::from_hash(api.primary_source(), &hash);
+    let badge = api.badge(&options);
+    let skin = match get_texture_skin(api.clone(), texture).await? {
+        Some(skin) => skin,
+        None => return Ok(None),
+    };
+
+    api.render(move || {
+        let raw_face = render::render_face(&skin.skin);
+        render_face_bytes(&raw_face, size, &options, options.caption.clone(), badge.as_deref()).map(Some)
+    }).await
 }
 
-async fn get_raw_face(api: ApiAccess, uuid: Uuid) -> Result<Arc<RgbImage>> {
-    let caches = api.caches.clone();
-    caches.raw_faces.try_get(uuid, load_raw_face).await
+/// Counts a render as pending for as long as it's held.
+struct PendingRender<'a>(&'a AtomicUsize);
+
+impl<'a> PendingRender<'a> {
+    fn start(pending_renders: &'a AtomicUsize) -> PendingRender<'a> {
+        pending_renders.fetch_add(1, Ordering::Relaxed);
+        PendingRender(pending_renders)
+    }
+}
+
+impl Drop for PendingRender<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
-async fn load_face(api: ApiAccess, uuid: Uuid, scale: u32) -> Result<ImageBytes> {
-    let raw_face = get_raw_face(api, uuid).await?;
+fn render_face_bytes(raw_face: &RgbImage, size: u32, options: &FaceOptions, label: Option<String>, badge: Option<&RgbaImage>) -> Result<ImageBytes> {
+    let render = |size| face::render(raw_face, size, options, label.as_deref(), badge);
 
-    tokio::task::spawn_blocking(move || {
-        let face = if scale > 0 {
-            render::rescale(&*raw_face, scale)
-        } else {
-            (*raw_face).clone()
+    match options.format {
+        OutputFormat::Png => encode_image(&render(size)),
+        OutputFormat::Tga => encode_tga(&render(size)),
+        OutputFormat::Raw => Ok(encode_raw(&render(size))),
+        OutputFormat::Ico => {
+            let faces: Vec<_> = FAVICON_SIZES.iter().map(|&size| render(size)).collect();
+            encode_ico(&faces)
+        }
+        OutputFormat::Svg => {
+            let face = face::apply_pixel_options(raw_face, options);
+            let border = options.border.map(|border| (border.to_rgb(), options.border_width.unwrap_or(1)));
+            let svg = render::render_svg(&face, options.shape == Shape::Circle, border);
+            Ok(ImageBytes::new(Bytes::from(svg), "image/svg+xml"))
+        }
+        OutputFormat::Ansi => {
+            let ansi = render::render_ansi(&render(size).to_rgba8());
+            Ok(ImageBytes::new(Bytes::from(ansi), "text/plain; charset=utf-8"))
+        }
+    }
+}
+
+async fn load_head(api: ApiAccess, uuid: Uuid, size: u32, options: HeadOptions) -> Result<ImageBytes> {
+    let skin = get_skin(api.clone(), uuid).await?;
+
+    api.render(move || {
+        let mut head = render::render_head(&skin.skin, size, options.yaw.degrees(), options.pitch.degrees(), options.quality);
+        if let Some(Flip::Horizontal) = options.flip {
+            image::imageops::flip_horizontal_in_place(&mut head);
+        }
+        if options.trim {
+            head = render::trim(head);
+        }
+        encode_image(&DynamicImage::ImageRgba8(head))
+    }).await
+}
+
+async fn load_compass(api: ApiAccess, uuid: Uuid, size: u32, options: CompassOptions) -> Result<ImageBytes> {
+    let skin = get_skin(api.clone(), uuid).await?;
+
+    api.render(move || {
+        let sheet = render::render_head_compass(&skin.skin, size, options.pitch.degrees(), options.quality);
+        encode_image(&DynamicImage::ImageRgba8(sheet))
+    }).await
+}
+
+async fn load_views(api: ApiAccess, uuid: Uuid, size: u32, options: BodyOptions) -> Result<ImageBytes> {
+    let skin = get_skin(api.clone(), uuid).await?;
+
+    api.render(move || {
+        let views = render::render_views(&skin.skin, options.flip == Some(Flip::Horizontal));
+        let mut views = render::rescale(&views, size / 8);
+        if options.trim {
+            views = render::trim(views);
+        }
+        encode_image(&DynamicImage::ImageRgba8(views))
+    }).await
+}
+
+async fn load_preview(api: ApiAccess, uuid: Uuid, size: u32, options: BodyOptions) -> Result<ImageBytes> {
+    let skin = get_skin(api.clone(), uuid).await?;
+
+    api.render(move || {
+        let preview = render::render_preview(&skin.skin, options.flip == Some(Flip::Horizontal));
+        let mut preview = render::rescale(&preview, size / 8);
+        if options.trim {
+            preview = render::trim(preview);
+        }
+        encode_image(&DynamicImage::ImageRgba8(preview))
+    }).await
+}
+
+async fn load_chibi(api: ApiAccess, uuid: Uuid, size: u32, options: ChibiOptions) -> Result<ImageBytes> {
+    let skin = get_skin(api.clone(), uuid).await?;
+
+    api.render(move || {
+        let mut chibi = render::render_chibi(&skin.skin, render::View::Front, options.head_scale);
+        if let Some(Flip::Horizontal) = options.flip {
+            image::imageops::flip_horizontal_in_place(&mut chibi);
+        }
+        let mut chibi = render::rescale(&chibi, size / 8);
+        if options.trim {
+            chibi = render::trim(chibi);
+        }
+        encode_image(&DynamicImage::ImageRgba8(chibi))
+    }).await
+}
+
+async fn load_diff(api: ApiAccess, a: Uuid, b: Uuid, size: u32) -> Result<ImageBytes> {
+    let (a, b) = futures::try_join!(get_raw_face(api.clone(), a), get_raw_face(api.clone(), b))?;
+
+    api.render(move || {
+        let diff = render::render_diff(&a.image, &b.image);
+        let diff = render::rescale(&diff, size / 8);
+        encode_image(&DynamicImage::ImageRgb8(diff))
+    }).await
+}
+
+async fn load_banner(api: ApiAccess, uuid: Uuid, options: BannerOptions) -> Result<ImageBytes> {
+    let subtitle = match options.subtitle {
+        Some(subtitle) => subtitle,
+        None => get_profile(api.clone(), uuid).await?.map(|profile| profile.name.clone()).unwrap_or_default(),
+    };
+    let title = options.title.unwrap_or_else(|| DEFAULT_BANNER_TITLE.to_owned());
+    let scale = options.scale;
+
+    let raw_face = get_raw_face(api.clone(), uuid).await?;
+
+    api.render(move || {
+        let banner = render::render_banner(&raw_face.image, &title, &subtitle);
+        let banner = render::rescale(&banner, scale);
+        encode_image(&DynamicImage::ImageRgba8(banner))
+    }).await
+}
+
+async fn load_cape(api: ApiAccess, uuid: Uuid, options: CapeOptions) -> Result<Option<ImageBytes>> {
+    let cape = get_profile(api.clone(), uuid).await?
+        .and_then(|profile| profile.textures())
+        .and_then(|textures| textures.refs.cape);
+    let cape = match cape {
+        Some(cape) => cape,
+        None => return Ok(None),
+    };
+
+    let source = api.texture_source(&cape.url);
+    api.acquire_from(source, Upstream::Textures)?;
+    let texture = match api.observe_from(source, minecraft::get_texture(source, cape, &minecraft::Validators::default(), api.max_texture_bytes).await)? {
+        Some(texture) => texture,
+        None => return Ok(None),
+    };
+
+    api.render(move || {
+        let frames = match render::cape_frames(&texture.image) {
+            Some(frames) => frames,
+            None => return Err(Error::InvalidCape),
         };
+        let scale = |frame: &RgbaImage| render::rescale(frame, options.scale);
+
+        match options.frame {
+            Some(index) => match frames.get(index as usize) {
+                Some(frame) => encode_image(&DynamicImage::ImageRgba8(scale(frame))).map(Some),
+                None => Ok(None),
+            },
+            None if frames.len() == 1 => encode_image(&DynamicImage::ImageRgba8(scale(&frames[0]))).map(Some),
+            None => encode_gif(frames.iter().map(scale), options.fps).map(Some),
+        }
+    }).await
+}
+
+async fn load_card(api: ApiAccess, uuid: Uuid, options: CardOptions) -> Result<ImageBytes> {
+    let stats = match &api.stats {
+        Some(stats) => stats.get(uuid).await.map_err(|_| Error::StatsApi)?,
+        None => return Err(Error::NoStatsApi),
+    };
+
+    let name = match get_profile(api.clone(), uuid).await? {
+        Some(profile) => profile.name.clone(),
+        None => uuid.to_string(),
+    };
+    let lines = card_lines(&stats, options.game.as_deref());
+    let scale = options.scale;
+
+    let raw_face = get_raw_face(api.clone(), uuid).await?;
+
+    api.render(move || {
+        let card = render::render_card(&raw_face.image, &name, &lines);
+        let card = render::rescale(&card, scale);
+        encode_image(&DynamicImage::ImageRgba8(card))
+    }).await
+}
+
+/// The statistics of one game by name, or else the games played of each game, most first.
+fn card_lines(stats: &PlayerStats, game: Option<&str>) -> Vec<(String, String)> {
+    let mut lines: Vec<(String, String)> = match game {
+        Some(game) => stats.get(game).into_iter()
+            .flat_map(|stats| stats.iter())
+            .map(|(stat, value)| (stat_label(stat), format_stat(*value)))
+            .take(MAX_CARD_LINES)
+            .collect(),
+        None => {
+            let mut games: Vec<(&String, f64)> = stats.iter()
+                .filter_map(|(game, stats)| stats.get(GAMES_PLAYED_STAT).map(|&played| (game, played)))
+                .collect();
+            games.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+            games.into_iter()
+                .take(MAX_CARD_LINES)
+                .map(|(game, played)| (stat_label(game), format!("{} played", format_stat(played))))
+                .collect()
+        }
+    };
+
+    if lines.is_empty() {
+        lines.push(("No statistics yet".to_owned(), String::new()));
+    }
+    lines
+}
+
+/// Turns a key such as `minecraft:games_played` into `Games Played`.
+fn stat_label(key: &str) -> String {
+    let key = key.rsplit(':').next().unwrap_or(key);
+    key.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn format_stat(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+async fn load_layout(api: ApiAccess, uuid: Uuid, scale: u32) -> Result<ImageBytes> {
+    let skin = get_skin(api.clone(), uuid).await?;
+
+    api.render(move || {
+        let layout = render::render_layout(&skin.skin, scale);
+        encode_image(&DynamicImage::ImageRgba8(layout))
+    }).await
+}
+
+async fn load_raw_face(api: ApiAccess, uuid: Uuid) -> Result<Arc<PlayerFace>> {
+    let skin = get_skin(api.clone(), uuid).await?;
+
+    api.render(move || {
+        let image = render::render_face(&skin.skin);
+        PlayerFace::new(image).map(Arc::new)
+    }).await
+}
+
+/// A player's face at the resolution of their skin, kept along with its PNG encoding since that
+/// is what most requests ask for.
+struct PlayerFace {
+    image: RgbImage,
+    png: ImageBytes,
+}
+
+impl PlayerFace {
+    fn new(image: RgbImage) -> Result<PlayerFace> {
+        timing::enter("encode");
+        let png = ImageBytes::from(Bytes::from(player_face_core::encode_png_buffer(&image)?));
+        Ok(PlayerFace { image, png })
+    }
+}
 
-        Ok(encode_image(face)?)
-    }).await.unwrap()
+async fn get_skin(api: ApiAccess, uuid: Uuid) -> Result<Arc<PlayerSkin>> {
+    let caches = api.caches.clone();
+    timing::enter("cache");
+    caches.skins.try_get(uuid, move |uuid| load_skin(api, uuid)).await
 }
 
-async fn load_raw_face(uuid: Uuid) -> Result<Arc<RgbImage>> {
-    let skin = get_skin(uuid).await?.unwrap_or_else(|| {
-        let default = skin::DefaultSkin::from(uuid);
-        default.as_skin().clone()
-    });
+async fn load_normalized_skin(api: ApiAccess, uuid: Uuid) -> Result<ImageBytes> {
+    let skin = get_skin(api.clone(), uuid).await?;
+    if !skin.skin.is_legacy() {
+        return Ok(skin.png.clone());
+    }
 
-    Ok(tokio::task::spawn_blocking(move || {
-        let image = render::render_face(&skin);
-        Arc::new(image)
-    }).await.unwrap())
+    api.render(move || {
+        let normalized = skin.skin.normalize();
+        encode_image(&DynamicImage::ImageRgba8(normalized.image))
+    }).await
 }
 
-async fn get_skin(uuid: Uuid) -> Result<Option<Skin>> {
-    let skin = minecraft::get_profile(uuid).await?
+async fn load_skin(api: ApiAccess, uuid: Uuid) -> Result<Arc<PlayerSkin>> {
+    let skin = get_profile(api.clone(), uuid).await?
         .and_then(|profile| profile.textures())
         .and_then(|textures| textures.refs.skin);
 
     if let Some(skin) = skin {
-        let skin = minecraft::get_texture(skin).await?;
-        Ok(Skin::from(skin))
-    } else {
-        Ok(None)
+        if let Some(skin) = get_texture_skin(api.clone(), skin).await? {
+            return Ok(skin);
+        }
     }
+
+    Ok(api.default_skins[&DefaultSkin::from(uuid)].clone())
 }
 
-fn encode_image(face: RgbImage) -> Result<ImageBytes> {
-    let mut bytes = Vec::new();
+/// Looks up a skin in the content-addressed store, falling back to downloading it. Textures
+/// without a hash in their URL bypass the store.
+async fn get_texture_skin(api: ApiAccess, texture: minecraft::PlayerTextureRef) -> Result<Option<Arc<PlayerSkin>>> {
+    let hash = match texture.hash() {
+        Some(hash) => hash.to_owned(),
+        None => return load_texture_skin(api, texture).await,
+    };
+
+    let model = Model::from_metadata(&texture.metadata);
+    let caches = api.caches.clone();
+    timing::enter("cache");
+    caches.textures.try_get((hash, model), move |_| load_texture_skin(api, texture)).await
+}
+
+async fn load_texture_skin(api: ApiAccess, texture: minecraft::PlayerTextureRef) -> Result<Option<Arc<PlayerSkin>>> {
+    let (url, model) = (texture.url.clone(), Model::from_metadata(&texture.metadata));
+    let stale = api.caches.stale_skins.get(&(url.clone(), model));
+    let validators = stale.as_ref()
+        .and_then(|stale| stale.source.as_ref())
+        .map(|source| source.validators.clone())
+        .unwrap_or_default();
 
-    let encoder = PngEncoder::new(&mut bytes);
-    encoder.encode(face.as_bytes(), face.width(), face.height(), image::ColorType::Rgb8)?;
+    let source = api.texture_source(&url);
+    api.acquire_from(source, Upstream::Textures)?;
+    let texture = match api.observe_from(source, minecraft::get_texture(source, texture, &validators, api.max_texture_bytes).await)? {
+        Some(texture) => texture,
+        None => return Ok(stale),
+    };
 
+    let png = ImageBytes::from(texture.bytes.clone());
+    let source = Some(SkinSource { url, model, validators: texture.validators.clone() })
+        .filter(|source| !source.validators.is_empty());
+    Ok(Skin::new(texture.image, Model::from_metadata(&texture.metadata)).map(|skin| Arc::new(PlayerSkin { skin, png, source })))
+}
+
+fn encode_image(face: &DynamicImage) -> Result<ImageBytes> {
+    timing::enter("encode");
+    let bytes = player_face_core::encode_png(face)?;
     Ok(ImageBytes::from(Bytes::from(bytes)))
 }
 
+fn encode_tga(face: &DynamicImage) -> Result<ImageBytes> {
+    timing::enter("encode");
+    let mut bytes = Vec::new();
+
+    let encoder = TgaEncoder::new(&mut bytes);
+    encoder.encode(face.as_bytes(), face.width(), face.height(), face.color())?;
+
+    Ok(ImageBytes::new(Bytes::from(bytes), "image/x-tga"))
+}
+
+/// Encodes the frames as a looping animated GIF, shown at `fps` frames per second.
+fn encode_gif(frames: impl Iterator<Item = RgbaImage>, fps: u32) -> Result<ImageBytes> {
+    timing::enter("encode");
+    let mut bytes = Vec::new();
+
+    {
+        let mut encoder = GifEncoder::new(&mut bytes);
+        encoder.set_repeat(Repeat::Infinite)?;
+        let delay = Delay::from_numer_denom_ms(1000, fps);
+        encoder.encode_frames(frames.map(|frame| Frame::from_parts(frame, 0, 0, delay)))?;
+    }
+
+    Ok(ImageBytes::new(Bytes::from(bytes), "image/gif"))
+}
+
+/// Uncompressed 8-bit RGBA rows, top to bottom, with no header.
+fn encode_raw(face: &DynamicImage) -> ImageBytes {
+    timing::enter("encode");
+    let bytes = face.to_rgba8().into_raw();
+    ImageBytes::new(Bytes::from(bytes), "application/octet-stream")
+}
+
+/// Packs the given images into a single ICO file, storing each entry as an embedded PNG.
+fn encode_ico(images: &[DynamicImage]) -> Result<ImageBytes> {
+    const HEADER_LEN: usize = 6;
+    const ENTRY_LEN: usize = 16;
+
+    let mut pngs = Vec::with_capacity(images.len());
+    for image in images {
+        pngs.push((image.dimensions(), encode_image(image)?.bytes));
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&0u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&(pngs.len() as u16).to_le_bytes());
+
+    let mut offset = HEADER_LEN + ENTRY_LEN * pngs.len();
+    for ((width, height), png) in &pngs {
+        // a dimension of 0 stands for 256 pixels
+        bytes.push(*width as u8);
+        bytes.push(*height as u8);
+        bytes.push(0);
+        bytes.push(0);
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&32u16.to_le_bytes());
+        bytes.extend_from_slice(&(png.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += png.len();
+    }
+
+    for (_, png) in &pngs {
+        bytes.extend_from_slice(png);
+    }
+
+    Ok(ImageBytes::new(Bytes::from(bytes), "image/x-icon"))
+}
+
 #[derive(Clone)]
 pub struct ImageBytes {
     bytes: Bytes,
     etag: String,
+    content_type: &'static str,
 }
 
 impl ImageBytes {
+    #[inline]
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    #[inline]
+    pub fn content_type(&self) -> &'static str {
+        self.content_type
+    }
+
+    #[inline]
+    pub fn etag(&self) -> &str {
+        &self.etag
+    }
+
     #[inline]
     pub fn matches(&self, etag: Option<String>) -> bool {
         match etag {
@@ -166,25 +2001,64 @@ impl ImageBytes {
     }
 }
 
-impl From<Bytes> for ImageBytes {
-    fn from(bytes: Bytes) -> Self {
+impl ImageBytes {
+    pub fn new(bytes: Bytes, content_type: &'static str) -> ImageBytes {
         let mut sha1 = Sha1::new();
         sha1.update(bytes.as_ref());
         let sha1 = sha1.digest();
 
         let etag = base64::encode_config(sha1.bytes(), base64::URL_SAFE_NO_PAD);
-        ImageBytes { bytes, etag }
+        ImageBytes { bytes, etag, content_type }
+    }
+}
+
+impl cache::Weighted for ImageBytes {
+    #[inline]
+    fn weight(&self) -> u32 {
+        (self.bytes.len() + self.etag.len()) as u32
+    }
+}
+
+/// Stored as the content type, a newline, and then the image itself.
+impl Persist for ImageBytes {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.content_type.len() + 1 + self.bytes.len());
+        bytes.extend_from_slice(self.content_type.as_bytes());
+        bytes.push(b'\n');
+        bytes.extend_from_slice(&self.bytes);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let split = bytes.iter().position(|&b| b == b'\n')?;
+        let content_type = match &bytes[..split] {
+            b"image/png" => "image/png",
+            b"image/x-icon" => "image/x-icon",
+            b"image/svg+xml" => "image/svg+xml",
+            b"image/x-tga" => "image/x-tga",
+            b"application/octet-stream" => "application/octet-stream",
+            b"text/plain; charset=utf-8" => "text/plain; charset=utf-8",
+            _ => return None,
+        };
+        Some(ImageBytes::new(Bytes::copy_from_slice(&bytes[split + 1..]), content_type))
     }
 }
 
-const CACHE_MAX_AGE: usize = 60 * 60 * 24;
+impl From<Bytes> for ImageBytes {
+    #[inline]
+    fn from(bytes: Bytes) -> Self {
+        ImageBytes::new(bytes, "image/png")
+    }
+}
+
+pub const CACHE_MAX_AGE: usize = 60 * 60 * 24;
 
 impl warp::Reply for ImageBytes {
     fn into_response(self) -> warp::reply::Response {
         let mut response = warp::reply::Response::new(self.bytes.into());
 
         let headers = response.headers_mut();
-        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(self.content_type));
         headers.insert(header::ETAG, HeaderValue::from_str(&self.etag).unwrap());
         headers.insert(header::CACHE_CONTROL, HeaderValue::from_str(&format!("public, max-age={}, stale-while-revalidate", CACHE_MAX_AGE)).unwrap());
 
@@ -200,6 +2074,24 @@ pub enum Error {
     EncodeImage,
     #[error("minecraft api gave error")]
     MinecraftApi,
+    #[error("upstream request budget exhausted")]
+    UpstreamThrottled,
+    #[error("too many renders are queued")]
+    Overloaded,
+    #[error("player is blocked")]
+    Blocked,
+    #[error("cape texture has an unexpected size")]
+    InvalidCape,
+    #[error("option needs a player, which texture faces have none of")]
+    NoPlayer,
+    #[error("no badge is configured by that name")]
+    UnknownBadge,
+    #[error("no status server is configured")]
+    NoStatusServer,
+    #[error("no statistics api is configured")]
+    NoStatsApi,
+    #[error("statistics api gave error")]
+    StatsApi,
 }
 
 impl From<image::ImageError> for Error {
@@ -214,3 +2106,169 @@ impl From<minecraft::Error> for Error {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::source::SourceConfig;
+
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    #[test]
+    fn ico_embeds_each_image_as_png() {
+        let images: Vec<DynamicImage> = [16, 256].iter()
+            .map(|&size| DynamicImage::ImageRgb8(RgbImage::from_pixel(size, size, image::Rgb([10, 20, 30]))))
+            .collect();
+        let ico = encode_ico(&images).unwrap();
+        assert_eq!(ico.content_type, "image/x-icon");
+
+        let bytes = &ico.bytes[..];
+        assert_eq!((u16_at(bytes, 0), u16_at(bytes, 2), u16_at(bytes, 4)), (0, 1, 2));
+
+        for (entry, &size) in [16u32, 256].iter().enumerate() {
+            let entry = &bytes[6 + entry * 16..6 + (entry + 1) * 16];
+            // 256 pixels are written as 0.
+            assert_eq!(entry[0] as u32, size % 256);
+            assert_eq!(entry[1] as u32, size % 256);
+
+            let (len, offset) = (u32_at(entry, 8) as usize, u32_at(entry, 12) as usize);
+            let png = image::load_from_memory_with_format(&bytes[offset..offset + len], image::ImageFormat::Png).unwrap();
+            assert_eq!(png.dimensions(), (size, size));
+        }
+    }
+
+    #[test]
+    fn ico_entries_follow_one_another() {
+        let images = vec![DynamicImage::ImageRgb8(RgbImage::new(8, 8)); 3];
+        let ico = encode_ico(&images).unwrap();
+        let bytes = &ico.bytes[..];
+
+        let mut expected_offset = 6 + 16 * 3;
+        for entry in 0..3 {
+            let entry = &bytes[6 + entry * 16..];
+            assert_eq!(u32_at(entry, 12) as usize, expected_offset);
+            expected_offset += u32_at(entry, 8) as usize;
+        }
+        assert_eq!(expected_offset, bytes.len());
+    }
+
+    #[test]
+    fn every_cache_is_named() {
+        let caches = Caches::new(&Config::default(), None, 1024);
+        let names: Vec<_> = caches.stats().iter().map(|(name, _, _)| *name).collect();
+        assert_eq!(names, CACHE_NAMES);
+        let names: Vec<_> = caches.resizable().iter().map(|(name, _)| *name).collect();
+        let unstale: Vec<_> = CACHE_NAMES.iter().copied().filter(|name| !name.starts_with("stale_")).collect();
+        assert_eq!(names, unstale);
+    }
+
+    #[tokio::test]
+    async fn missing_files_stop_startup() {
+        let mut config = Config::default();
+        config.badges.insert("partner".to_owned(), PathBuf::from("/nonexistent/partner.png"));
+        assert!(matches!(Api::new(config), Err(StartupError::Badge { name, .. }) if name == "partner"));
+
+        let mut config = Config::default();
+        config.default_skins.insert(DefaultSkin::ALL[0], PathBuf::from("/nonexistent/steve.png"));
+        assert!(matches!(Api::new(config), Err(StartupError::ReadDefaultSkin { .. })));
+
+        let path = std::env::temp_dir().join(format!("player-face-api-skin-{}.png", std::process::id()));
+        std::fs::write(&path, b"not a png").unwrap();
+        let mut config = Config::default();
+        config.default_skins.insert(DefaultSkin::ALL[0], path.clone());
+        let result = Api::new(config);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(StartupError::InvalidDefaultSkin { .. })));
+
+        assert!(Api::new(Config::default()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn tuning_applies_stale_settings() {
+        let api = Api::new(Config::default()).unwrap();
+        let mut tuning = api.tuning();
+        assert_eq!((tuning.stale.skins, tuning.stale.profiles), (256, 512));
+        assert!(!tuning.cache_capacities.contains_key("stale_skins"));
+
+        tuning.stale = StaleConfig { ttl_secs: 60, skins: 8, profiles: 16 };
+        api.tune(tuning.clone()).unwrap();
+        let tuned = api.tuning().stale;
+        assert_eq!((tuned.ttl_secs, tuned.skins, tuned.profiles), (60, 8, 16));
+        assert_eq!(api.caches.stale_profiles.ttl(), Duration::from_secs(60));
+
+        tuning.stale.ttl_secs = 0;
+        assert!(api.tune(tuning.clone()).is_err());
+        tuning.stale = StaleConfig { ttl_secs: 60, skins: 0, profiles: 16 };
+        assert!(api.tune(tuning).is_err());
+        assert_eq!(api.tuning().stale.skins, 8);
+    }
+
+    #[tokio::test]
+    async fn sources_over_budget_fall_through_to_the_next() {
+        let dir = std::env::temp_dir().join(format!("player-face-api-profiles-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("profiles")).unwrap();
+        let (known, unknown) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let profile = serde_json::json!({ "id": known, "name": "Local", "properties": [] });
+        std::fs::write(dir.join("profiles").join(format!("{}.json", known)), profile.to_string()).unwrap();
+
+        let mut config = Config::default();
+        config.upstream_budget.session_server = Some(0);
+        config.sources.insert("local".to_owned(), SourceConfig { directory: Some(dir.clone()), priority: -1, ..SourceConfig::default() });
+        let api = Api::new(config).unwrap();
+        let access = api.try_access(&Client { addr: None, tenant: None }, None).await.unwrap();
+
+        let (profile, _) = access.get_profile(known).await.unwrap();
+        assert_eq!(profile.map(|profile| profile.name), Some("Local".to_owned()));
+        assert!(matches!(access.get_profile(unknown).await, Err(Error::UpstreamThrottled)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn pending_renders_are_counted_per_instance() {
+        let (api, other) = (Api::new(Config::default()).unwrap(), Api::new(Config::default()).unwrap());
+        let access = api.try_access(&Client { addr: None, tenant: None }, None).await.unwrap();
+
+        let (started, has_started) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let render = tokio::spawn(async move {
+            access.render(move || {
+                started.send(()).unwrap();
+                released.recv().unwrap();
+                Ok(())
+            }).await
+        });
+        tokio::task::spawn_blocking(move || has_started.recv().unwrap()).await.unwrap();
+
+        assert_eq!(api.readiness().pending_renders, 1);
+        assert_eq!(other.readiness().pending_renders, 0);
+        release.send(()).unwrap();
+        render.await.unwrap().unwrap();
+        assert_eq!(api.readiness().pending_renders, 0);
+    }
+
+    #[tokio::test]
+    async fn unreachable_redis_falls_back_to_local_limits() {
+        let config = Config { rate_limit_redis: Some("not a url".to_owned()), ..Config::default() };
+        assert!(matches!(Api::new(config), Err(StartupError::Redis { .. })));
+
+        // nothing listens on the port, so every request is limited locally instead
+        let config = Config {
+            rate_limit_redis: Some("redis://127.0.0.1:1".to_owned()),
+            requests_per_minute: 1,
+            rate_limit_wait_ms: 0,
+            ..Config::default()
+        };
+        let api = Api::new(config).unwrap();
+
+        let client = Client { addr: Some(([203, 0, 113, 1], 1234).into()), tenant: None };
+        assert!(api.rate_limit(&client).await.is_ok());
+        assert!(matches!(api.rate_limit(&client).await, Err(AccessDenied::RateLimited)));
+    }
+}
@@ -0,0 +1,53 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde_json::Value;
+use sha1::Sha1;
+
+const TARGET: &str = "audit";
+
+/// A record of changes made through the admin api, logged under the `audit` target and, when a
+/// path is configured, appended to that file as one JSON object per line.
+#[derive(Clone)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+}
+
+impl AuditLog {
+    pub fn new(path: Option<PathBuf>) -> AuditLog {
+        AuditLog { path }
+    }
+
+    pub fn record(&self, actor: &Actor, action: &str, params: Value) {
+        let entry = serde_json::json!({
+            "time": chrono::Utc::now().to_rfc3339(),
+            "actor": actor.0,
+            "action": action,
+            "params": params,
+        });
+        let entry = entry.to_string();
+
+        log::info!(target: TARGET, "{}", entry);
+
+        // Opened for every entry so that the file can be rotated away underneath us.
+        if let Some(path) = &self.path {
+            let result = OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut file| file.write_all(format!("{}\n", entry).as_bytes()));
+            if let Err(err) = result {
+                log::error!("failed to append to audit log at {:?}: {:?}", path, err);
+            }
+        }
+    }
+}
+
+/// Identifies the token an admin request was authorized with, without revealing it.
+#[derive(Clone, Debug)]
+pub struct Actor(String);
+
+impl Actor {
+    pub fn from_token(token: &str) -> Actor {
+        let digest = Sha1::from(token).digest().to_string();
+        Actor(format!("token:{}", &digest[..12]))
+    }
+}
@@ -0,0 +1,309 @@
+use std::fmt;
+use std::io::{self, BufRead};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
+
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha1::Sha1;
+
+use super::{CacheTier, Key, Matcher, Value};
+
+/// A value that can be written to and read back from a byte buffer, for tiers that store
+/// entries outside of memory.
+pub trait Persist: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+/// How often a disk tier removes expired files and holds itself to its size.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Stores one file per entry in a directory, named by a hash of the key. Each file starts with
+/// its key as a line of JSON, so that entries can be matched for removal by key. Files older than the
+/// time to live are treated as missing when looked up, and are removed by a sweep every
+/// [`SWEEP_INTERVAL`], which also removes the oldest files while there are more than
+/// `max_bytes` of them.
+pub struct DiskTier<K, V> {
+    root: PathBuf,
+    ttl: Duration,
+    /// Keeps the sweep going for as long as the tier is around.
+    _alive: Arc<()>,
+    _marker: PhantomData<fn(K) -> V>,
+}
+
+impl<K, V> DiskTier<K, V> {
+    /// Must be called within a tokio runtime, which the sweep is spawned onto.
+    pub fn new(root: PathBuf, ttl: Duration, max_bytes: u64) -> io::Result<DiskTier<K, V>> {
+        std::fs::create_dir_all(&root)?;
+
+        let alive = Arc::new(());
+        tokio::spawn(run_sweeps(root.clone(), ttl, max_bytes, Arc::downgrade(&alive)));
+
+        Ok(DiskTier { root, ttl, _alive: alive, _marker: PhantomData })
+    }
+}
+
+async fn run_sweeps(root: PathBuf, ttl: Duration, max_bytes: u64, alive: Weak<()>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if alive.upgrade().is_none() {
+            return;
+        }
+
+        let root = root.clone();
+        match tokio::task::spawn_blocking(move || sweep(&root, ttl, max_bytes)).await {
+            Ok(Ok(())) => (),
+            Ok(Err(err)) => log::warn!("failed to sweep disk cache: {:?}", err),
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+}
+
+/// Removes the files in `root` older than `ttl`, and then the oldest of the rest until they add
+/// up to at most `max_bytes`.
+fn sweep(root: &Path, ttl: Duration, max_bytes: u64) -> io::Result<()> {
+    let now = SystemTime::now();
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let modified = metadata.modified()?;
+        if now.duration_since(modified).unwrap_or_default() > ttl {
+            remove_file(&entry.path())?;
+        } else {
+            files.push((modified, metadata.len(), entry.path()));
+        }
+    }
+
+    let mut total: u64 = files.iter().map(|&(_, len, _)| len).sum();
+    if total > max_bytes {
+        files.sort();
+        for (_, len, path) in files {
+            if total <= max_bytes {
+                break;
+            }
+            remove_file(&path)?;
+            total -= len;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a file that may already have been removed by another sweep or lookup.
+fn remove_file(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Removes the files in `root` whose key matches. Files whose key can't be read are left for the
+/// sweep.
+fn remove_matching<K: DeserializeOwned>(root: &Path, matches: &Matcher<K>) -> io::Result<()> {
+    for entry in std::fs::read_dir(root)? {
+        let path = entry?.path();
+        if path.extension().is_some() {
+            continue;
+        }
+
+        let mut header = Vec::new();
+        match std::fs::File::open(&path) {
+            Ok(file) => io::BufReader::new(file).read_until(b'\n', &mut header)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        if serde_json::from_slice::<K>(&header).is_ok_and(|key| matches(&key)) {
+            remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+impl<K: Key + Serialize + fmt::Debug, V: Value + Persist> DiskTier<K, V> {
+    #[inline]
+    fn header(key: &K) -> Vec<u8> {
+        serde_json::to_vec(key).expect("cache keys serialize to json")
+    }
+
+    /// Keys are hashed through their JSON serialization, which stays the same across builds
+    /// for as long as the key's fields do.
+    fn path(&self, key: &K) -> PathBuf {
+        let name = Sha1::from(Self::header(key)).digest().to_string();
+        self.root.join(name)
+    }
+
+    async fn read(&self, key: &K) -> io::Result<Option<V>> {
+        let path = self.path(key);
+
+        let modified = tokio::fs::metadata(&path).await?.modified()?;
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+        if age > self.ttl {
+            return Ok(None);
+        }
+
+        let bytes = tokio::fs::read(&path).await?;
+        let header = Self::header(key);
+        match bytes.strip_prefix(header.as_slice()).and_then(|rest| rest.strip_prefix(b"\n")) {
+            Some(value) => Ok(V::from_bytes(value)),
+            None => Ok(None),
+        }
+    }
+
+    async fn write(&self, key: &K, value: &V) -> io::Result<()> {
+        let path = self.path(key);
+
+        // write to a temporary file first so that readers never observe a partial entry
+        let temp = path.with_extension("tmp");
+        let mut bytes = Self::header(key);
+        bytes.push(b'\n');
+        bytes.extend_from_slice(&value.to_bytes());
+        tokio::fs::write(&temp, bytes).await?;
+        tokio::fs::rename(&temp, &path).await
+    }
+}
+
+impl<K: Key + Serialize + DeserializeOwned + fmt::Debug, V: Value + Persist> CacheTier<K, V> for DiskTier<K, V> {
+    fn get<'a>(&'a self, key: &'a K) -> BoxFuture<'a, Option<V>> {
+        Box::pin(async move {
+            match self.read(key).await {
+                Ok(value) => value,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => {
+                    log::warn!("failed to read disk cache entry for {:?}: {:?}", key, err);
+                    None
+                }
+            }
+        })
+    }
+
+    fn insert<'a>(&'a self, key: &'a K, value: &'a V) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if let Err(err) = self.write(key, value).await {
+                log::warn!("failed to write disk cache entry for {:?}: {:?}", key, err);
+            }
+        })
+    }
+
+    fn remove_where(&self, matches: Matcher<K>) {
+        let root = self.root.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = remove_matching(&root, &matches) {
+                log::warn!("failed to remove disk cache entries: {:?}", err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    impl Persist for u32 {
+        fn to_bytes(&self) -> Vec<u8> {
+            self.to_be_bytes().to_vec()
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Option<Self> {
+            Some(u32::from_be_bytes(bytes.try_into().ok()?))
+        }
+    }
+
+    fn root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("player-face-api-disk-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    #[tokio::test]
+    async fn entries_round_trip() {
+        let root = root("round-trip");
+        let tier: DiskTier<u32, u32> = DiskTier::new(root.clone(), TTL, u64::MAX).unwrap();
+        tier.insert(&1, &10).await;
+
+        assert_eq!(tier.get(&1).await, Some(10));
+        assert_eq!(tier.get(&2).await, None);
+        // no temporary file is left behind once the entry is in place
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn entries_under_another_key_are_missing() {
+        let root = root("other-key");
+        let tier: DiskTier<u32, u32> = DiskTier::new(root.clone(), TTL, u64::MAX).unwrap();
+        tier.insert(&1, &10).await;
+        std::fs::rename(tier.path(&1), tier.path(&2)).unwrap();
+
+        assert_eq!(tier.get(&2).await, None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_missing() {
+        let root = root("expired");
+        let tier: DiskTier<u32, u32> = DiskTier::new(root.clone(), Duration::from_millis(10), u64::MAX).unwrap();
+        tier.insert(&1, &10).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(tier.get(&1).await, None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn removes_matching_entries() {
+        let root = root("remove");
+        let tier: DiskTier<u32, u32> = DiskTier::new(root.clone(), TTL, u64::MAX).unwrap();
+        for key in 0..4 {
+            tier.insert(&key, &key).await;
+        }
+        remove_matching(&root, &(Arc::new(|key: &u32| *key < 2) as Matcher<u32>)).unwrap();
+
+        for key in 0..4 {
+            assert_eq!(tier.get(&key).await, if key < 2 { None } else { Some(key) });
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sweeps_hold_the_tier_to_its_size() {
+        let root = root("sweep");
+        let tier: DiskTier<u32, u32> = DiskTier::new(root.clone(), TTL, u64::MAX).unwrap();
+        for key in 0..4 {
+            tier.insert(&key, &key).await;
+            // keep modification times apart so that the oldest is unambiguous
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let len = std::fs::metadata(tier.path(&0)).unwrap().len();
+        sweep(&root, TTL, len * 3).unwrap();
+
+        assert_eq!(tier.get(&0).await, None);
+        for key in 1..4 {
+            assert_eq!(tier.get(&key).await, Some(key));
+        }
+
+        sweep(&root, Duration::ZERO, u64::MAX).unwrap();
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
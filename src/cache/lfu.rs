@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{Eviction, Key, Matcher, Value};
+
+/// Called with each entry evicted for expiry or capacity, once the lock is released.
+pub(super) type Listener<K, V> = Box<dyn Fn(&K, &V, Eviction) + Send + Sync>;
+
+/// An in-memory store that evicts the least frequently used entry once full, breaking ties by
+/// evicting the one used least recently. Unlike TinyLFU, new entries are always admitted, and
+/// frequencies are only forgotten along with their entries.
+pub(super) struct LfuMemory<K, V> {
+    capacity: u64,
+    ttl: Duration,
+    weigher: Option<fn(&K, &V) -> u32>,
+    listener: Listener<K, V>,
+    state: Mutex<State<K, V>>,
+}
+
+struct State<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Every key by its use count and then by when it was last used, least used first.
+    by_use: BTreeMap<(u64, u64), K>,
+    weight: u64,
+    clock: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    weight: u32,
+    uses: u64,
+    last_used: u64,
+    expires_at: Instant,
+}
+
+impl<K: Key, V: Value> LfuMemory<K, V> {
+    pub(super) fn new(capacity: u64, ttl: Duration, weigher: Option<fn(&K, &V) -> u32>, listener: Listener<K, V>) -> Self {
+        let state = State { entries: HashMap::new(), by_use: BTreeMap::new(), weight: 0, clock: 0 };
+        LfuMemory { capacity, ttl, weigher, listener, state: Mutex::new(state) }
+    }
+
+    #[inline]
+    pub(super) fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub(super) fn get(&self, key: &K) -> Option<V> {
+        let mut state = self.state.lock().unwrap();
+        let (uses, last_used, expires_at) = state.entries.get(key)
+            .map(|entry| (entry.uses, entry.last_used, entry.expires_at))?;
+        if expires_at <= Instant::now() {
+            let (key, entry) = state.remove(key)?;
+            drop(state);
+            (self.listener)(&key, &entry.value, Eviction::Expired);
+            return None;
+        }
+
+        let now = state.tick();
+        let key = state.by_use.remove(&(uses, last_used))?;
+        state.by_use.insert((uses + 1, now), key.clone());
+
+        let entry = state.entries.get_mut(&key)?;
+        entry.uses += 1;
+        entry.last_used = now;
+        Some(entry.value.clone())
+    }
+
+    /// Replacing an entry counts as a use of it. Entries weighing more than the whole capacity are
+    /// never admitted.
+    pub(super) fn insert(&self, key: K, value: V) {
+        let weight = self.weigher.map_or(1, |weigher| weigher(&key, &value));
+        if u64::from(weight) > self.capacity {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let uses = state.remove(&key).map_or(0, |(_, entry)| entry.uses) + 1;
+
+        let mut evicted = Vec::new();
+        while state.weight + u64::from(weight) > self.capacity {
+            let least_used = match state.by_use.values().next() {
+                Some(least_used) => least_used.clone(),
+                None => break,
+            };
+            evicted.extend(state.remove(&least_used));
+        }
+
+        let last_used = state.tick();
+        state.by_use.insert((uses, last_used), key.clone());
+        state.weight += u64::from(weight);
+        state.entries.insert(key, Entry { value, weight, uses, last_used, expires_at: Instant::now() + self.ttl });
+        drop(state);
+
+        let now = Instant::now();
+        for (key, entry) in evicted {
+            let eviction = if entry.expires_at <= now { Eviction::Expired } else { Eviction::Capacity };
+            (self.listener)(&key, &entry.value, eviction);
+        }
+    }
+
+    pub(super) fn remove_where(&self, matches: Matcher<K>) {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<K> = state.entries.keys().filter(|key| matches(key)).cloned().collect();
+        for key in keys {
+            state.remove(&key);
+        }
+    }
+
+    /// Every entry that hasn't expired, without counting as a use of any of them.
+    pub(super) fn entries(&self) -> Vec<(K, V)> {
+        let now = Instant::now();
+        self.state.lock().unwrap().entries.iter()
+            .filter(|(_, entry)| entry.expires_at > now)
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect()
+    }
+
+    /// The number of entries held, after evicting those that have expired.
+    pub(super) fn entry_count(&self) -> u64 {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let expired: Vec<K> = state.entries.iter()
+            .filter(|(_, entry)| entry.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let expired: Vec<_> = expired.iter().filter_map(|key| state.remove(key)).collect();
+        let count = state.entries.len() as u64;
+        drop(state);
+
+        for (key, entry) in expired {
+            (self.listener)(&key, &entry.value, Eviction::Expired);
+        }
+        count
+    }
+}
+
+impl<K: Key, V> State<K, V> {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn remove(&mut self, key: &K) -> Option<(K, Entry<V>)> {
+        let (key, entry) = self.entries.remove_entry(key)?;
+        self.by_use.remove(&(entry.uses, entry.last_used));
+        self.weight -= u64::from(entry.weight);
+        Some((key, entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    type Evicted = Arc<Mutex<Vec<(u32, Eviction)>>>;
+
+    fn memory(capacity: u64) -> (LfuMemory<u32, u32>, Evicted) {
+        memory_with_ttl(capacity, TTL)
+    }
+
+    fn memory_with_ttl(capacity: u64, ttl: Duration) -> (LfuMemory<u32, u32>, Evicted) {
+        let evicted = Evicted::default();
+        let listener: Listener<u32, u32> = {
+            let evicted = evicted.clone();
+            Box::new(move |key, _, eviction| evicted.lock().unwrap().push((*key, eviction)))
+        };
+        (LfuMemory::new(capacity, ttl, None, listener), evicted)
+    }
+
+    #[test]
+    fn evicts_the_least_frequently_used() {
+        let (memory, evicted) = memory(2);
+        memory.insert(1, 10);
+        memory.insert(2, 20);
+        for _ in 0..3 {
+            memory.get(&1);
+        }
+        // Used more recently, but less often.
+        memory.get(&2);
+
+        memory.insert(3, 30);
+        assert_eq!(memory.get(&1), Some(10));
+        assert_eq!(memory.get(&2), None);
+        assert_eq!(memory.get(&3), Some(30));
+        assert_eq!(*evicted.lock().unwrap(), [(2, Eviction::Capacity)]);
+    }
+
+    #[test]
+    fn ties_evict_the_least_recently_used() {
+        let (memory, _) = memory(2);
+        memory.insert(1, 10);
+        memory.insert(2, 20);
+        memory.get(&2);
+        memory.get(&1);
+
+        memory.insert(3, 30);
+        assert_eq!(memory.get(&2), None);
+        assert_eq!(memory.get(&1), Some(10));
+    }
+
+    #[test]
+    fn replacing_keeps_the_use_count() {
+        let (memory, _) = memory(2);
+        memory.insert(1, 10);
+        memory.get(&1);
+        memory.insert(2, 20);
+        memory.get(&2);
+        memory.insert(1, 11);
+
+        memory.insert(3, 30);
+        assert_eq!(memory.get(&1), Some(11));
+        assert_eq!(memory.get(&2), None);
+        assert_eq!(memory.entry_count(), 2);
+    }
+
+    #[test]
+    fn weighted_entries_evict_until_they_fit() {
+        let listener: Listener<u32, u32> = Box::new(|_, _, _| ());
+        let memory = LfuMemory::new(10, TTL, Some(|_, value| *value), listener);
+        memory.insert(1, 4);
+        memory.insert(2, 4);
+        memory.get(&2);
+        memory.insert(3, 6);
+        assert_eq!(memory.get(&1), None);
+        assert_eq!(memory.get(&2), Some(4));
+        assert_eq!(memory.get(&3), Some(6));
+
+        memory.insert(4, 11);
+        assert_eq!(memory.get(&4), None);
+        assert_eq!(memory.entry_count(), 2);
+    }
+
+    #[test]
+    fn expired_entries_are_missing() {
+        let (memory, evicted) = memory_with_ttl(2, Duration::ZERO);
+        memory.insert(1, 10);
+        assert_eq!(memory.entries(), []);
+        assert_eq!(memory.get(&1), None);
+        assert_eq!(memory.entry_count(), 0);
+        assert_eq!(*evicted.lock().unwrap(), [(1, Eviction::Expired)]);
+    }
+
+    #[test]
+    fn removes_matching_entries() {
+        let (memory, evicted) = memory(4);
+        for key in 0..4 {
+            memory.insert(key, key);
+        }
+        memory.remove_where(Arc::new(|key| key % 2 == 0));
+
+        let mut entries = memory.entries();
+        entries.sort();
+        assert_eq!(entries, [(1, 1), (3, 3)]);
+        assert!(evicted.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,667 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture};
+use moka::notification::RemovalCause;
+use moka::policy::EvictionPolicy;
+use serde::{Deserialize, Serialize};
+
+pub use disk::{DiskTier, Persist};
+
+use lfu::LfuMemory;
+
+mod disk;
+mod lfu;
+
+pub trait Key: Eq + Hash + Clone + Send + Sync + 'static {}
+
+pub trait Value: Send + Sync + Clone + 'static {}
+
+impl<T: Eq + Hash + Clone + Send + Sync + 'static> Key for T {}
+
+impl<T: Send + Sync + Clone + 'static> Value for T {}
+
+/// A value with a known approximate memory footprint, for caches bounded by size.
+pub trait Weighted {
+    fn weight(&self) -> u32;
+}
+
+impl<T: Weighted> Weighted for Option<T> {
+    #[inline]
+    fn weight(&self) -> u32 {
+        self.as_ref().map_or(0, Weighted::weight)
+    }
+}
+
+/// A storage layer that a [`Cache`] can fall back to before loading a value, such as a disk or a
+/// remote store. Tiers may be slow and are free to drop entries; failures should be treated as
+/// misses.
+pub trait CacheTier<K, V>: Send + Sync {
+    fn get<'a>(&'a self, key: &'a K) -> BoxFuture<'a, Option<V>>;
+
+    fn insert<'a>(&'a self, key: &'a K, value: &'a V) -> BoxFuture<'a, ()>;
+
+    /// Drops every entry whose key matches, carrying on in the background if it takes a while.
+    fn remove_where(&self, matches: Matcher<K>);
+}
+
+/// Picks out the keys of entries to drop from every tier of a cache.
+pub type Matcher<K> = Arc<dyn Fn(&K) -> bool + Send + Sync>;
+
+impl<K: Key, V: Value> CacheTier<K, V> for moka::sync::Cache<K, V> {
+    fn get<'a>(&'a self, key: &'a K) -> BoxFuture<'a, Option<V>> {
+        Box::pin(future::ready(moka::sync::Cache::get(self, key)))
+    }
+
+    fn insert<'a>(&'a self, key: &'a K, value: &'a V) -> BoxFuture<'a, ()> {
+        moka::sync::Cache::insert(self, key.clone(), value.clone());
+        Box::pin(future::ready(()))
+    }
+
+    fn remove_where(&self, matches: Matcher<K>) {
+        for (key, _) in self.iter() {
+            if matches(&key) {
+                self.invalidate(&*key);
+            }
+        }
+    }
+}
+
+/// A cache whose in-memory capacity and time to live can be changed while running, whatever its
+/// key and value.
+pub trait Resize: Send + Sync {
+    fn capacity(&self) -> u64;
+
+    fn set_capacity(&self, capacity: u64);
+
+    fn ttl(&self) -> Duration;
+
+    fn set_ttl(&self, ttl: Duration);
+}
+
+impl<K: Key, V: Value> Resize for Cache<K, V> {
+    #[inline]
+    fn capacity(&self) -> u64 {
+        Cache::capacity(self)
+    }
+
+    #[inline]
+    fn set_capacity(&self, capacity: u64) {
+        Cache::set_capacity(self, capacity)
+    }
+
+    #[inline]
+    fn ttl(&self) -> Duration {
+        Cache::ttl(self)
+    }
+
+    #[inline]
+    fn set_ttl(&self, ttl: Duration) {
+        Cache::set_ttl(self, ttl)
+    }
+}
+
+/// Composes tiers from fastest to slowest. Lookups return the first hit and copy it into the
+/// faster tiers that missed; inserts are written through to every tier.
+pub struct TieredCache<K, V> {
+    tiers: Vec<Box<dyn CacheTier<K, V>>>,
+}
+
+impl<K, V> Default for TieredCache<K, V> {
+    fn default() -> Self {
+        TieredCache { tiers: Vec::new() }
+    }
+}
+
+impl<K, V> TieredCache<K, V> {
+    /// Adds a tier slower than every existing one.
+    pub fn push<T: CacheTier<K, V> + 'static>(&mut self, tier: T) {
+        self.tiers.push(Box::new(tier));
+    }
+}
+
+impl<K: Key, V: Value> CacheTier<K, V> for TieredCache<K, V> {
+    fn get<'a>(&'a self, key: &'a K) -> BoxFuture<'a, Option<V>> {
+        Box::pin(async move {
+            for (i, tier) in self.tiers.iter().enumerate() {
+                if let Some(value) = tier.get(key).await {
+                    for faster in &self.tiers[..i] {
+                        faster.insert(key, &value).await;
+                    }
+                    return Some(value);
+                }
+            }
+            None
+        })
+    }
+
+    fn insert<'a>(&'a self, key: &'a K, value: &'a V) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            for tier in &self.tiers {
+                tier.insert(key, value).await;
+            }
+        })
+    }
+
+    fn remove_where(&self, matches: Matcher<K>) {
+        for tier in &self.tiers {
+            tier.remove_where(matches.clone());
+        }
+    }
+}
+
+/// A concurrent cache that loads missing values on demand. Lookups don't block each other;
+/// concurrent loads of the same key are serialized through a per-key lock so that only one of
+/// them does the work, while other keys proceed independently. Entries are held in memory,
+/// backed by any slower tiers added with [`Cache::with_tier`].
+pub struct Cache<K: Key, V: Value> {
+    entries: RwLock<Memory<K, V>>,
+    settings: RwLock<Settings<K, V>>,
+    lower: TieredCache<K, V>,
+    loading: Mutex<HashMap<K, Arc<tokio::sync::Mutex<()>>>>,
+    stats: Arc<Stats>,
+    hooks: Arc<Hooks<K, V>>,
+}
+
+type EvictionHook<K, V> = Box<dyn Fn(&K, &V, Eviction) + Send + Sync>;
+type LoadErrorHook<K> = Box<dyn Fn(&K, &dyn fmt::Debug) + Send + Sync>;
+
+/// What the in-memory tier was built with, so that it can be rebuilt with another capacity or
+/// time to live.
+struct Settings<K, V> {
+    ttl: Duration,
+    policy: Policy,
+    weigher: Option<fn(&K, &V) -> u32>,
+}
+
+impl<K, V> Clone for Settings<K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V> Copy for Settings<K, V> {}
+
+struct Hooks<K, V> {
+    on_eviction: OnceLock<EvictionHook<K, V>>,
+    on_load_error: OnceLock<LoadErrorHook<K>>,
+}
+
+/// Which entries a cache discards once it reaches its capacity: `lru`, `lfu` or `tiny_lfu`.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// Evicts the least recently used entry.
+    #[default]
+    Lru,
+    /// Evicts the least frequently used entry, counting every use since it was inserted.
+    Lfu,
+    /// Only admits new entries that are requested more often than the entries they would
+    /// replace, which keeps popular entries resident under skewed traffic.
+    TinyLfu,
+}
+
+/// The in-memory tier. Moka holds entries for the policies it implements, and [`LfuMemory`] for
+/// plain LFU, which it lacks.
+#[derive(Clone)]
+enum Memory<K: Key, V: Value> {
+    Moka(moka::sync::Cache<K, V>),
+    Lfu(Arc<LfuMemory<K, V>>),
+}
+
+impl<K: Key, V: Value> Memory<K, V> {
+    fn capacity(&self) -> u64 {
+        match self {
+            Memory::Moka(entries) => entries.policy().max_capacity().unwrap_or(u64::MAX),
+            Memory::Lfu(entries) => entries.capacity(),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        match self {
+            Memory::Moka(entries) => entries.get(key),
+            Memory::Lfu(entries) => entries.get(key),
+        }
+    }
+
+    fn insert(&self, key: K, value: V) {
+        match self {
+            Memory::Moka(entries) => entries.insert(key, value),
+            Memory::Lfu(entries) => entries.insert(key, value),
+        }
+    }
+
+    fn remove_where(&self, matches: Matcher<K>) {
+        match self {
+            Memory::Moka(entries) => entries.remove_where(matches),
+            Memory::Lfu(entries) => entries.remove_where(matches),
+        }
+    }
+
+    fn entries(&self) -> Vec<(K, V)> {
+        match self {
+            Memory::Moka(entries) => entries.iter().map(|(key, value)| (K::clone(&key), value)).collect(),
+            Memory::Lfu(entries) => entries.entries(),
+        }
+    }
+
+    fn entry_count(&self) -> u64 {
+        match self {
+            Memory::Moka(entries) => {
+                entries.run_pending_tasks();
+                entries.entry_count()
+            }
+            Memory::Lfu(entries) => entries.entry_count(),
+        }
+    }
+}
+
+/// Why an entry was evicted from a cache.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Eviction {
+    /// The entry outlived the cache's time to live.
+    Expired,
+    /// The entry was pushed out to keep the cache within its capacity.
+    Capacity,
+}
+
+impl<K: Key, V: Value> Cache<K, V> {
+    /// Creates a cache holding up to `capacity` entries, each expiring once older than `ttl`.
+    pub fn new(capacity: u64, ttl: Duration, policy: Policy) -> Cache<K, V> {
+        Cache::build(capacity, Settings { ttl, policy, weigher: None })
+    }
+
+    /// Creates a cache holding up to `max_weight` in total [`Weighted::weight`] rather than a
+    /// fixed number of entries.
+    pub fn weighted(max_weight: u64, ttl: Duration, policy: Policy) -> Cache<K, V> where V: Weighted {
+        Cache::build(max_weight, Settings { ttl, policy, weigher: Some(|_, value| value.weight()) })
+    }
+
+    fn build(capacity: u64, settings: Settings<K, V>) -> Cache<K, V> {
+        let stats = Arc::new(Stats::default());
+        let hooks: Arc<Hooks<K, V>> = Arc::new(Hooks { on_eviction: OnceLock::new(), on_load_error: OnceLock::new() });
+
+        Cache {
+            entries: RwLock::new(build_entries(capacity, &settings, &stats, &hooks)),
+            settings: RwLock::new(settings),
+            lower: TieredCache::default(),
+            loading: Mutex::new(HashMap::new()),
+            stats,
+            hooks,
+        }
+    }
+
+    #[inline]
+    fn memory(&self) -> Memory<K, V> {
+        self.entries.read().unwrap().clone()
+    }
+
+    /// The most entries, or total weight for weighted caches, held in memory at once.
+    pub fn capacity(&self) -> u64 {
+        self.memory().capacity()
+    }
+
+    /// Rebuilds the in-memory tier with a new capacity, carrying over as many entries as fit.
+    /// Carried entries start their time to live afresh.
+    pub fn set_capacity(&self, capacity: u64) {
+        let settings = *self.settings.read().unwrap();
+        let resized = build_entries(capacity, &settings, &self.stats, &self.hooks);
+
+        let mut entries = self.entries.write().unwrap();
+        for (key, value) in entries.entries() {
+            resized.insert(key, value);
+        }
+        *entries = resized;
+    }
+
+    /// How long entries are held for once inserted.
+    pub fn ttl(&self) -> Duration {
+        self.settings.read().unwrap().ttl
+    }
+
+    /// Rebuilds the in-memory tier with a new time to live, carrying over every entry. Carried
+    /// entries start the new time to live afresh.
+    pub fn set_ttl(&self, ttl: Duration) {
+        self.settings.write().unwrap().ttl = ttl;
+        self.set_capacity(self.capacity());
+    }
+
+    /// Adds a tier to consult on memory misses before loading, slower than any added before it.
+    pub fn with_tier<T: CacheTier<K, V> + 'static>(mut self, tier: T) -> Cache<K, V> {
+        self.lower.push(tier);
+        self
+    }
+
+    /// Calls `hook` whenever an entry is evicted for expiry or capacity. Only one hook may be set.
+    pub fn on_eviction<F>(self, hook: F) -> Cache<K, V>
+        where F: Fn(&K, &V, Eviction) + Send + Sync + 'static,
+    {
+        if self.hooks.on_eviction.set(Box::new(hook)).is_err() {
+            panic!("eviction hook already set");
+        }
+        self
+    }
+
+    /// Calls `hook` whenever a loader fails. Only one hook may be set.
+    pub fn on_load_error<F>(self, hook: F) -> Cache<K, V>
+        where F: Fn(&K, &dyn fmt::Debug) + Send + Sync + 'static,
+    {
+        if self.hooks.on_load_error.set(Box::new(hook)).is_err() {
+            panic!("load error hook already set");
+        }
+        self
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// The number of entries currently held, after applying any pending evictions.
+    pub fn entry_count(&self) -> u64 {
+        self.memory().entry_count()
+    }
+
+    #[inline]
+    pub async fn try_get<'a, F, Fut, E>(&'a self, key: K, load: F) -> Result<V, E>
+        where F: FnOnce(K) -> Fut,
+              Fut: Future<Output = Result<V, E>> + 'a,
+              E: fmt::Debug,
+    {
+        self.try_get_outcome(key, load).await.map(|(value, _)| value)
+    }
+
+    pub async fn try_get_outcome<'a, F, Fut, E>(&'a self, key: K, load: F) -> Result<(V, Outcome), E>
+        where F: FnOnce(K) -> Fut,
+              Fut: Future<Output = Result<V, E>> + 'a,
+              E: fmt::Debug,
+    {
+        if let Some(value) = self.get(&key) {
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((value, Outcome::Hit));
+        }
+
+        let lock = self.loading.lock().unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(tokio::sync::Mutex::new(())))
+            .clone();
+        let _loading = LoadingGuard { cache: self, key: &key, lock: &lock };
+        let _guard = lock.lock().await;
+
+        // another request may have finished loading this key while we were waiting
+        if let Some(value) = self.get(&key) {
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((value, Outcome::Hit));
+        }
+
+        if let Some(value) = self.lower.get(&key).await {
+            self.memory().insert(key.clone(), value.clone());
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((value, Outcome::Hit));
+        }
+
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        // Boxed since loaders nest through other caches, and would otherwise make one future so
+        // large that polling it overflows the stack in debug builds.
+        let value = match Box::pin(load(key.clone())).await {
+            Ok(value) => value,
+            Err(err) => {
+                self.stats.load_errors.fetch_add(1, Ordering::Relaxed);
+                if let Some(hook) = self.hooks.on_load_error.get() {
+                    hook(&key, &err);
+                }
+                return Err(err);
+            }
+        };
+        self.memory().insert(key.clone(), value.clone());
+        self.lower.insert(&key, &value).await;
+
+        Ok((value, Outcome::Miss))
+    }
+
+    /// Inserts a value directly, bypassing any loader.
+    pub fn insert(&self, key: K, value: V) {
+        self.memory().insert(key, value);
+    }
+
+    /// Drops every entry whose key matches, such as every render of a player, from memory and
+    /// from every slower tier.
+    pub fn invalidate_where<F>(&self, matches: F)
+        where F: Fn(&K) -> bool + Send + Sync + 'static,
+    {
+        let matches: Matcher<K> = Arc::new(matches);
+        self.memory().remove_where(matches.clone());
+        self.lower.remove_where(matches);
+    }
+
+    /// Copies out every entry currently in the cache.
+    pub fn entries(&self) -> Vec<(K, V)> {
+        self.memory().entries()
+    }
+
+    /// Looks up a value without loading it if missing.
+    #[inline]
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.memory().get(key)
+    }
+}
+
+fn build_entries<K: Key, V: Value>(
+    capacity: u64,
+    settings: &Settings<K, V>,
+    stats: &Arc<Stats>,
+    hooks: &Arc<Hooks<K, V>>,
+) -> Memory<K, V> {
+    let on_eviction = {
+        let stats = stats.clone();
+        let hooks = hooks.clone();
+        move |key: &K, value: &V, eviction| {
+            stats.evictions.fetch_add(1, Ordering::Relaxed);
+            if let Some(hook) = hooks.on_eviction.get() {
+                hook(key, value, eviction);
+            }
+        }
+    };
+
+    let policy = match settings.policy {
+        Policy::Lru => EvictionPolicy::lru(),
+        Policy::TinyLfu => EvictionPolicy::tiny_lfu(),
+        Policy::Lfu => {
+            let entries = LfuMemory::new(capacity, settings.ttl, settings.weigher, Box::new(on_eviction));
+            return Memory::Lfu(Arc::new(entries));
+        }
+    };
+
+    let mut builder = moka::sync::Cache::builder()
+        .max_capacity(capacity)
+        .time_to_live(settings.ttl)
+        .eviction_policy(policy);
+    if let Some(weigher) = settings.weigher {
+        builder = builder.weigher(weigher);
+    }
+
+    let entries = builder
+        .eviction_listener(move |key, value, cause| {
+            let eviction = match cause {
+                RemovalCause::Expired => Eviction::Expired,
+                RemovalCause::Size => Eviction::Capacity,
+                RemovalCause::Explicit | RemovalCause::Replaced => return,
+            };
+            on_eviction(&key, &value, eviction);
+        })
+        .build();
+    Memory::Moka(entries)
+}
+
+/// Running totals for a single cache. Misses count loads attempted, including failed ones.
+#[derive(Default)]
+pub struct Stats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    load_errors: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl Stats {
+    #[inline]
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn load_errors(&self) -> u64 {
+        self.load_errors.load(Ordering::Relaxed)
+    }
+
+    /// Entries removed for capacity or expiry, as opposed to being replaced.
+    #[inline]
+    pub fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+}
+
+/// Drops the per-key load lock once no longer needed, even if the load is cancelled. Lookups
+/// still waiting on the lock keep it, so that one arriving later waits along with them rather
+/// than loading alongside whichever of them goes next.
+struct LoadingGuard<'a, K: Key, V: Value> {
+    cache: &'a Cache<K, V>,
+    key: &'a K,
+    lock: &'a Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<K: Key, V: Value> Drop for LoadingGuard<'_, K, V> {
+    fn drop(&mut self) {
+        // Held by the map and by us alone. Others can only take it from the map, which we hold.
+        let mut loading = self.cache.loading.lock().unwrap();
+        let unshared = Arc::strong_count(self.lock) == 2;
+        if unshared && loading.get(self.key).is_some_and(|current| Arc::ptr_eq(current, self.lock)) {
+            loading.remove(self.key);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Hit,
+    Miss,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Hit => "hit",
+            Outcome::Miss => "miss",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[tokio::test]
+    async fn loads_of_a_key_never_overlap() {
+        let cache: Arc<Cache<u32, u32>> = Arc::new(Cache::new(16, TTL, Policy::Lru));
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+
+        // Every load fails, so that each lookup loads in turn.
+        let lookup = || {
+            let (cache, running, most_running) = (cache.clone(), running.clone(), most_running.clone());
+            tokio::spawn(async move {
+                cache.try_get(1, |_| async move {
+                    most_running.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Err::<u32, ()>(())
+                }).await
+            })
+        };
+
+        let first = lookup();
+        let second = lookup();
+        // Arrives while the second lookup loads, after the first has given up the lock.
+        tokio::time::sleep(Duration::from_millis(75)).await;
+        let third = lookup();
+
+        for lookup in [first, second, third] {
+            assert!(lookup.await.unwrap().is_err());
+        }
+        assert_eq!(most_running.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.stats().load_errors(), 3);
+        assert!(cache.loading.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn waiting_lookups_hit_what_was_loaded() {
+        let cache: Arc<Cache<u32, u32>> = Arc::new(Cache::new(16, TTL, Policy::Lru));
+        let lookups: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    cache.try_get_outcome(1, |key| async move {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        Ok::<_, ()>(key * 10)
+                    }).await
+                })
+            })
+            .collect();
+
+        let mut outcomes = Vec::new();
+        for lookup in lookups {
+            let (value, outcome) = lookup.await.unwrap().unwrap();
+            assert_eq!(value, 10);
+            outcomes.push(outcome);
+        }
+        assert_eq!(outcomes.iter().filter(|&&outcome| outcome == Outcome::Miss).count(), 1);
+        assert_eq!((cache.stats().hits(), cache.stats().misses()), (3, 1));
+        assert!(cache.loading.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tiered_hits_are_copied_into_faster_tiers() {
+        let (fast, slow) = (moka::sync::Cache::new(16), moka::sync::Cache::new(16));
+        let mut tiered = TieredCache::default();
+        tiered.push(fast.clone());
+        tiered.push(slow.clone());
+
+        slow.insert(1, 10);
+        assert_eq!(tiered.get(&1).await, Some(10));
+        assert_eq!(fast.get(&1), Some(10));
+        assert_eq!(tiered.get(&2).await, None);
+
+        tiered.insert(&3, &30).await;
+        assert_eq!((fast.get(&3), slow.get(&3)), (Some(30), Some(30)));
+
+        tiered.remove_where(Arc::new(|key| *key == 1));
+        assert_eq!((fast.get(&1), slow.get(&1)), (None, None));
+    }
+
+    #[tokio::test]
+    async fn lookups_fall_back_to_lower_tiers_before_loading() {
+        let tier = moka::sync::Cache::new(16);
+        tier.insert(1, 10);
+        let cache: Cache<u32, u32> = Cache::new(16, TTL, Policy::Lru).with_tier(tier.clone());
+
+        let value = cache.try_get(1, |_| async { Err::<u32, ()>(()) }).await;
+        assert_eq!(value, Ok(10));
+
+        let value = cache.try_get(2, |key| async move { Ok::<_, ()>(key * 10) }).await;
+        assert_eq!(value, Ok(20));
+        assert_eq!(tier.get(&2), Some(20));
+    }
+}
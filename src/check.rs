@@ -0,0 +1,47 @@
+use std::net::TcpListener;
+
+use image::{GenericImageView, ImageFormat};
+
+use crate::skin::DefaultSkin;
+use crate::Config;
+
+/// Checks what the config alone can't tell about whether we could start with it, returning a
+/// description of each problem found. The config itself has already been validated in loading.
+pub fn run(config: &Config, bind: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for skin in DefaultSkin::ALL {
+        match image::load_from_memory_with_format(skin.png_bytes(), ImageFormat::Png) {
+            Ok(image) if image.width() == 64 && image.height() == 64 => (),
+            Ok(_) => problems.push(format!("default skin {:?} is not 64x64", skin)),
+            Err(err) => problems.push(format!("default skin {:?} does not decode: {}", skin, err)),
+        }
+    }
+
+    for (skin, path) in &config.default_skins {
+        match std::fs::read(path) {
+            Ok(bytes) => {
+                if let Err(err) = skin.load_replacement(&bytes) {
+                    problems.push(format!("default skin {:?} at {:?} {}", skin, path, err));
+                }
+            }
+            Err(err) => problems.push(format!("default skin {:?} at {:?} does not load: {}", skin, path, err)),
+        }
+    }
+
+    for (name, path) in &config.badges {
+        if let Err(err) = image::open(path) {
+            problems.push(format!("badge `{}` at {:?} does not load: {}", name, path, err));
+        }
+    }
+
+    if bind {
+        for addr in config.listen_addrs().into_iter().chain(config.grpc_listen) {
+            if let Err(err) = TcpListener::bind(addr) {
+                problems.push(format!("cannot bind to {}: {}", addr, err));
+            }
+        }
+    }
+
+    problems
+}
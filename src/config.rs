@@ -1,34 +1,813 @@
-use std::fs::File;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::{env, fmt, fs, io};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
+use ipnet::IpNet;
+use redis::IntoConnectionInfo;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
+use crate::access_log::AccessLogFormat;
+use crate::api::CACHE_NAMES;
+use crate::cache::Policy;
+use crate::logging::LogFormat;
+use crate::nucleoid::NucleoidConfig;
+use crate::options::MAX_QUALITY;
+use crate::ping::{self, PingConfig};
+use crate::render_queue::RenderQueueConfig;
+use crate::skin::DefaultSkin;
+use crate::source::{self, SourceConfig, HASH_PLACEHOLDER, UUID_PLACEHOLDER};
+use crate::tenant::{self, TenantConfig};
+use crate::trace::TracingConfig;
+use crate::upstream::BudgetConfig;
+
+const DEFAULT_PATH: &str = "config.json";
+
+/// Secrets may instead be given as a path to a file holding their value under this suffix.
+const SECRET_FILE_SUFFIX: &str = "_file";
+
+/// The fields that are secrets, which may be given as files.
+const SECRET_FIELDS: [&str; 3] = ["admin_token", "url_signing_key", "signing_key"];
+
+/// The field of each tenant that is a secret, given as a file of one key per line.
+const TENANT_SECRET_FIELD: &str = "api_keys";
+
+const CONFIG_PATH_VAR: &str = "CONFIG_PATH";
+
+/// Environment variables under this prefix override the matching config field.
+const ENV_PREFIX: &str = "PFA_";
+
+/// Fields left out of the file take their values from [`Config::default`], so a config only
+/// needs to mention what it changes.
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct Config {
     pub requests_per_minute: u32,
+    /// How many requests a client may make at once, such as a page embedding many faces, before
+    /// being held to `requests_per_minute`. Defaults to `requests_per_minute`.
+    pub rate_limit_burst: Option<u32>,
+    /// A Redis server to keep rate limiting state in, such as `redis://127.0.0.1/`, so that
+    /// replicas pointed at the same server share each client's quota. Limiting falls back to
+    /// each replica's own state while it can't be reached.
+    pub rate_limit_redis: Option<String>,
+    /// How long a rate limited request may wait for its turn before being rejected, in
+    /// milliseconds. Zero rejects immediately.
+    pub rate_limit_wait_ms: u64,
     pub port: u16,
+    /// Addresses to listen on. When empty, we listen on `port` on localhost alone.
+    pub listen: Vec<SocketAddr>,
+    /// The address to serve the gRPC API on, which is disabled when unset.
+    pub grpc_listen: Option<SocketAddr>,
+    pub allowed_ips: Vec<IpNet>,
+    pub denied_ips: Vec<IpNet>,
+    pub access_log: Option<AccessLogFormat>,
+    /// How every logged event is written: `text` as env_logger's usual lines, or `json` as one
+    /// object per line with the level, target, and the ID, player and running time of the
+    /// request it was logged for.
+    pub log_format: LogFormat,
+    pub http: HttpConfig,
+    /// How long open connections may take to finish once shutting down, in seconds.
+    pub drain_timeout_secs: u64,
+    /// How long a request may take before it is abandoned with a 504, in seconds.
+    pub request_timeout_secs: u64,
+    /// Upper bound on requests being handled at once across every client. Requests past it are
+    /// turned away with a 503 rather than queued.
+    pub max_in_flight_requests: Option<usize>,
+    /// Requests taking longer than this, in milliseconds, are logged with where the time went.
+    pub slow_request_ms: Option<u64>,
+    /// Upper bound on the supersampling quality of 3D renders; higher requests are clamped.
+    pub max_quality: u32,
+    /// Whether faces may be scaled up with `upscale=hq2x` or `upscale=xbr`, which cost far more
+    /// to render than nearest-neighbour. Requests for them are scaled with nearest-neighbour
+    /// otherwise.
+    pub pixel_art_upscaling: bool,
+    /// Images that may be composited onto the corner of faces with `?badge=<name>`, by name.
+    pub badges: BTreeMap<String, PathBuf>,
+    /// PNGs to show in place of the built-in default skins, by name (`steve` or `alex`), for
+    /// players without a skin of their own. Each keeps the model of the skin it replaces.
+    pub default_skins: BTreeMap<DefaultSkin, PathBuf>,
+    pub ping: PingConfig,
+    /// How often players watched through `/watch` are checked for skin changes, in seconds.
+    pub watch_interval_secs: u64,
+    /// How many of the players requested most lately to revalidate in the background, so that
+    /// they stay warm. Zero disables refreshing.
+    pub hot_players: usize,
+    /// How often the hot players are revalidated, in seconds.
+    pub hot_refresh_interval_secs: u64,
+    /// The Nucleoid backend to show player statistics from on `/card`, which is disabled when
+    /// unset.
+    pub nucleoid: Option<NucleoidConfig>,
+    /// Upper bound on the total size of cached face renders, in bytes. It is split evenly between
+    /// player and texture faces, and between the default sources and each of `sources`.
+    pub face_cache_bytes: u64,
+    /// Upper bound on the size of a texture download, in bytes.
+    pub max_texture_bytes: u64,
+    /// The User-Agent sent to Mojang, in place of one naming this service and its version.
+    pub user_agent: Option<String>,
+    /// Where to persist the profile and raw face caches across restarts, if anywhere.
+    pub cache_snapshot: Option<PathBuf>,
+    /// A directory to keep rendered faces in, backing the in-memory cache, if anywhere.
+    pub disk_cache: Option<PathBuf>,
+    /// Upper bound on the total size of `disk_cache`, in bytes, past which the oldest faces are
+    /// removed.
+    pub disk_cache_bytes: u64,
+    /// Whether to accept version 3 UUIDs, which offline-mode servers give players.
+    pub allow_offline_uuids: bool,
+    pub upstream_budget: BudgetConfig,
+    /// Where to load profiles and skins from, by name. A player missing from one source is looked
+    /// up in the next.
+    pub sources: BTreeMap<String, SourceConfig>,
+    /// Where to export request traces over OTLP, if anywhere.
+    pub tracing: Option<TracingConfig>,
+    /// Players whose faces and skins must not be served, answered with 410 Gone instead. More can
+    /// be added at runtime through `/admin/blocklist`.
+    pub blocked_uuids: Vec<Uuid>,
+    /// Whether to serve `/graphql`, which answers questions about many players in one request.
+    pub graphql: bool,
+    /// A secret every request for a player must be signed with, so that only URLs handed out by
+    /// the operator are served rather than hotlinks elsewhere. Signed URLs carry
+    /// `exp=<unix seconds>` and `sig=<hex HMAC-SHA256 of "<path>?<query>">` in their query, and
+    /// stop working at `exp`. The signed query holds every parameter but `sig`, including `exp`,
+    /// sorted by name and then value and form-urlencoded.
+    pub url_signing_key: Option<String>,
+    /// A secret to sign response bodies with, sent as `X-Signature: sha256=<hex HMAC-SHA256>` so
+    /// that services re-serving our images can check they came from us unmodified.
+    pub signing_key: Option<String>,
+    /// The bearer token required by the `/admin` routes, which are disabled when unset.
+    pub admin_token: Option<String>,
+    /// A file to append changes made through the admin api to, one JSON object per line.
+    pub audit_log: Option<PathBuf>,
+    /// Clients allowed to pick a single source for their request with `?source=<name>`.
+    pub source_selection_ips: Vec<IpNet>,
+    /// Sites and services given quotas and features of their own by name, recognized by API key
+    /// or by the origin of their requests.
+    pub tenants: BTreeMap<String, TenantConfig>,
+    pub readiness: ReadinessConfig,
+    pub stale: StaleConfig,
+    pub render_queue: RenderQueueConfig,
+    /// Eviction policy overrides by cache name, such as `faces` or `profiles`, each `lru`, `lfu`
+    /// or `tiny_lfu`. Caches left out use `lru`.
+    pub cache_policies: HashMap<String, Policy>,
+}
+
+/// Connection settings for the server, which speaks both HTTP/1.1 and cleartext HTTP/2.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Whether to keep HTTP/1.1 connections open between requests.
+    pub keep_alive: bool,
+    /// Interval between TCP keep-alive probes on idle connections, if any.
+    pub tcp_keep_alive_secs: Option<u64>,
+    /// Interval between HTTP/2 pings on idle connections, if any.
+    pub http2_keep_alive_interval_secs: Option<u64>,
+    /// How long to wait for a ping to be acknowledged before closing the connection.
+    pub http2_keep_alive_timeout_secs: u64,
+    pub http2_max_concurrent_streams: Option<u32>,
+}
+
+/// Thresholds past which the readiness probe reports that we can't usefully serve traffic.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    /// How long upstream requests may fail continuously before we are no longer ready.
+    pub upstream_window_secs: u64,
+    /// How many renders may be queued or running at once before we are no longer ready.
+    pub max_pending_renders: usize,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        ReadinessConfig {
+            upstream_window_secs: 300,
+            max_pending_renders: 256,
+        }
+    }
+}
+
+/// How long skins and profiles are kept once expired, to fall back on while Mojang fails, and
+/// how many of each.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[serde(default)]
+pub struct StaleConfig {
+    pub ttl_secs: u64,
+    pub skins: u64,
+    pub profiles: u64,
+}
+
+impl Default for StaleConfig {
+    fn default() -> Self {
+        StaleConfig {
+            ttl_secs: 60 * 60 * 24 * 7,
+            skins: 256,
+            profiles: 512,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            keep_alive: true,
+            tcp_keep_alive_secs: None,
+            http2_keep_alive_interval_secs: None,
+            http2_keep_alive_timeout_secs: 20,
+            http2_max_concurrent_streams: None,
+        }
+    }
+}
+
+impl Config {
+    /// Checks constraints that the types alone don't capture, returning the offending field and
+    /// the constraint it breaks.
+    fn validate(&self) -> Result<(), (&'static str, String)> {
+        fn check(valid: bool, field: &'static str, constraint: &str) -> Result<(), (&'static str, String)> {
+            if valid { Ok(()) } else { Err((field, constraint.to_owned())) }
+        }
+
+        let budget = &self.upstream_budget;
+        check(self.requests_per_minute > 0, "requests_per_minute", "must be at least 1")?;
+        check(self.rate_limit_burst != Some(0), "rate_limit_burst", "must be at least 1")?;
+        check(
+            self.rate_limit_redis.as_deref().is_none_or(|url| url.into_connection_info().is_ok()), "rate_limit_redis",
+            "must be a redis:// or rediss:// url",
+        )?;
+        check(
+            (1..=MAX_QUALITY).contains(&self.max_quality), "max_quality",
+            &format!("must be between 1 and {}", MAX_QUALITY),
+        )?;
+        check(self.face_cache_bytes > 0, "face_cache_bytes", "must be at least 1")?;
+        check(self.disk_cache_bytes > 0, "disk_cache_bytes", "must be at least 1")?;
+        check(self.max_texture_bytes > 0, "max_texture_bytes", "must be at least 1")?;
+        check(self.request_timeout_secs > 0, "request_timeout_secs", "must be at least 1")?;
+        check(self.max_in_flight_requests != Some(0), "max_in_flight_requests", "must be at least 1")?;
+        check(self.url_signing_key.as_ref().is_none_or(|key| !key.is_empty()), "url_signing_key", "must not be empty")?;
+        check(self.signing_key.as_ref().is_none_or(|key| !key.is_empty()), "signing_key", "must not be empty")?;
+        check(self.watch_interval_secs > 0, "watch_interval_secs", "must be at least 1")?;
+        check(self.hot_refresh_interval_secs > 0, "hot_refresh_interval_secs", "must be at least 1")?;
+        check(budget.window_secs > 0, "upstream_budget.window_secs", "must be at least 1")?;
+        check(
+            [budget.session_server, budget.textures, budget.api, budget.per_player_per_minute].iter().all(|limit| *limit != Some(0)), "upstream_budget",
+            "limits must be at least 1, or left out for no limit",
+        )?;
+        check(
+            self.tracing.as_ref().is_none_or(|tracing| (0.0..=1.0).contains(&tracing.sample_ratio)), "tracing.sample_ratio",
+            "must be between 0 and 1",
+        )?;
+        check(self.admin_token.as_ref().is_none_or(|token| !token.is_empty()), "admin_token", "must not be empty")?;
+        check(!self.sources.is_empty(), "sources", "must name at least one source")?;
+        for (name, source) in &self.sources {
+            check(
+                source.profile_endpoint.contains(UUID_PLACEHOLDER) && source.texture_endpoint.contains(HASH_PLACEHOLDER), "sources",
+                &format!("`{}` must have `{}` in its profile endpoint and `{}` in its texture endpoint", name, UUID_PLACEHOLDER, HASH_PLACEHOLDER),
+            )?;
+            check(source.timeout_secs > 0, "sources", &format!("`{}` must have a timeout of at least 1", name))?;
+        }
+        for (name, tenant) in &self.tenants {
+            check(tenant.requests_per_minute > 0, "tenants", &format!("`{}` must have a quota of at least 1 request per minute", name))?;
+            check(tenant.rate_limit_burst != Some(0), "tenants", &format!("`{}` must have a burst of at least 1", name))?;
+            check(
+                tenant.api_keys.iter().chain(&tenant.origins).all(|value| !value.is_empty()), "tenants",
+                &format!("`{}` must not have empty api keys or origins", name),
+            )?;
+            check(
+                tenant.features.iter().flatten().all(|feature| tenant::FEATURES.contains(&feature.as_str())), "tenants",
+                &format!("`{}` must only have features out of {}", name, tenant::FEATURES.join(", ")),
+            )?;
+        }
+        check(self.ping.timeout_secs > 0, "ping.timeout_secs", "must be at least 1")?;
+        check(
+            self.ping.status_server.as_deref().is_none_or(|server| ping::split_address(server).is_some()), "ping.status_server",
+            "must be a host, optionally followed by `:<port>`",
+        )?;
+        if let Some(nucleoid) = &self.nucleoid {
+            check(nucleoid.timeout_secs > 0, "nucleoid.timeout_secs", "must be at least 1")?;
+            check(nucleoid.cache_secs > 0, "nucleoid.cache_secs", "must be at least 1")?;
+        }
+        for name in self.cache_policies.keys() {
+            check(
+                CACHE_NAMES.contains(&name.as_str()), "cache_policies",
+                &format!("`{}` must be one of {}", name, CACHE_NAMES.join(", ")),
+            )?;
+        }
+        check(self.render_queue.slots != Some(0), "render_queue.slots", "must be at least 1")?;
+        check(self.readiness.max_pending_renders > 0, "readiness.max_pending_renders", "must be at least 1")?;
+        check(self.stale.ttl_secs > 0, "stale.ttl_secs", "must be at least 1")?;
+        check(self.stale.skins > 0 && self.stale.profiles > 0, "stale", "must keep at least 1 skin and profile")?;
+        check(self.http.http2_max_concurrent_streams != Some(0), "http.http2_max_concurrent_streams", "must be at least 1")?;
+
+        Ok(())
+    }
+
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        if self.listen.is_empty() {
+            vec![SocketAddr::from(([127, 0, 0, 1], self.port))]
+        } else {
+            self.listen.clone()
+        }
+    }
+
+    #[inline]
+    pub fn cache_policy(&self, cache: &str) -> Policy {
+        self.cache_policies.get(cache).copied().unwrap_or_default()
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             requests_per_minute: 100,
+            rate_limit_burst: None,
+            rate_limit_redis: None,
+            rate_limit_wait_ms: 0,
             port: 1111,
+            listen: Vec::new(),
+            grpc_listen: None,
+            allowed_ips: Vec::new(),
+            denied_ips: Vec::new(),
+            access_log: None,
+            log_format: LogFormat::Text,
+            http: HttpConfig::default(),
+            drain_timeout_secs: 30,
+            request_timeout_secs: 15,
+            max_in_flight_requests: None,
+            slow_request_ms: None,
+            max_quality: MAX_QUALITY,
+            pixel_art_upscaling: true,
+            badges: BTreeMap::new(),
+            default_skins: BTreeMap::new(),
+            ping: PingConfig::default(),
+            watch_interval_secs: 60,
+            hot_players: 64,
+            hot_refresh_interval_secs: 600,
+            nucleoid: None,
+            face_cache_bytes: 32 * 1024 * 1024,
+            max_texture_bytes: 256 * 1024,
+            user_agent: None,
+            cache_snapshot: None,
+            disk_cache: None,
+            disk_cache_bytes: 1024 * 1024 * 1024,
+            allow_offline_uuids: true,
+            upstream_budget: BudgetConfig::default(),
+            sources: source::default_sources(),
+            source_selection_ips: Vec::new(),
+            tenants: BTreeMap::new(),
+            graphql: false,
+            url_signing_key: None,
+            signing_key: None,
+            admin_token: None,
+            audit_log: None,
+            blocked_uuids: Vec::new(),
+            tracing: None,
+            readiness: ReadinessConfig::default(),
+            stale: StaleConfig::default(),
+            render_queue: RenderQueueConfig::default(),
+            cache_policies: HashMap::new(),
         }
     }
 }
 
-pub(super) fn load() -> Config {
-    let path = Path::new("config.json");
-    if path.exists() {
-        let mut file = File::open(path).unwrap();
-        serde_json::from_reader(&mut file).unwrap()
+/// Loads the config in layers, each overriding fields of the one before:
+///
+/// 1. the defaults,
+/// 2. the file given by `--config` or `CONFIG_PATH`, or else `config.json` in the working
+///    directory if it exists,
+/// 3. `PFA_<FIELD>` environment variables, such as `PFA_PORT=8080`, with nested fields separated
+///    by a double underscore, as in `PFA_HTTP__KEEP_ALIVE=false`,
+/// 4. `--set <field>=<value>` arguments, with nested fields separated by a dot, as in
+///    `--set http.keep_alive=false`.
+///
+/// Override values are read as JSON where the field takes what they parse as, and as strings
+/// otherwise, so that `PFA_ADMIN_TOKEN=12345` sets a token rather than failing as a number.
+/// Environment variables under the prefix that name no field are ignored with a warning.
+pub fn load() -> Result<Config, Error> {
+    load_with_mode().map(|(config, _)| config)
+}
+
+/// Like [`load`], also returning what the command line asked us to do with the config.
+pub fn load_with_mode() -> Result<(Config, Mode), Error> {
+    let args = parse_args()?;
+    let mode = args.mode;
+
+    let path = args.config
+        .or_else(|| env::var_os(CONFIG_PATH_VAR).map(PathBuf::from))
+        .or_else(|| Some(PathBuf::from(DEFAULT_PATH)).filter(|path| path.exists()));
+
+    let mut overrides = env_overrides();
+    overrides.extend(args.overrides);
+
+    load_layered(path.as_deref(), &overrides).map(|config| (config, mode))
+}
+
+/// Loads the config from a file at `path`, which must exist.
+pub fn load_from(path: &Path) -> Result<Config, Error> {
+    load_layered(Some(path), &[])
+}
+
+fn load_layered(path: Option<&Path>, overrides: &[Override]) -> Result<Config, Error> {
+    let origin = || Origin::new(path, !overrides.is_empty());
+    let malformed = |source| Error::Malformed { origin: origin(), source };
+
+    let source = match path {
+        Some(path) => Some(fs::read_to_string(path).map_err(|source| Error::Io { path: path.to_owned(), source })?),
+        None => None,
+    };
+
+    let mut value = match &source {
+        Some(source) => serde_json::from_str(source).map_err(malformed)?,
+        None => serde_json::Value::Object(serde_json::Map::new()),
+    };
+
+    for r#override in overrides {
+        r#override.apply(&mut value)?;
+    }
+
+    let config = if read_secret_files(&mut value)? || !overrides.is_empty() {
+        serde_json::from_value(value).map_err(malformed)?
+    } else if let Some(source) = source {
+        // Deserializing the source directly keeps line and column information in errors.
+        serde_json::from_str(&source).map_err(malformed)?
     } else {
-        let config = Config::default();
+        Config::default()
+    };
+
+    validate(&config, origin())?;
+    Ok(config)
+}
+
+fn validate(config: &Config, origin: Origin) -> Result<(), Error> {
+    config.validate().map_err(|(field, constraint)| Error::Invalid { origin, field, constraint })
+}
+
+/// What to do once the config is loaded.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Serve,
+    /// Check that we could start with the config and exit, given by `--check`. With
+    /// `--check-bind`, also check that the listen addresses can be bound, which fails while
+    /// another instance holds them.
+    Check { bind: bool },
+}
+
+#[derive(Default)]
+struct Args {
+    config: Option<PathBuf>,
+    overrides: Vec<Override>,
+    mode: Mode,
+}
+
+fn parse_args() -> Result<Args, Error> {
+    let mut parsed = Args::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+            _ => (arg.as_str(), None),
+        };
+
+        let mut value = || inline.clone().or_else(|| args.next())
+            .ok_or_else(|| Error::Usage(format!("`{}` requires a value", flag)));
+
+        match flag {
+            "--config" => parsed.config = Some(PathBuf::from(value()?)),
+            "--check" if inline.is_none() => parsed.mode = Mode::Check { bind: false },
+            "--check-bind" if inline.is_none() => parsed.mode = Mode::Check { bind: true },
+            "--set" => {
+                let assignment = value()?;
+                let (field, value) = assignment.split_once('=')
+                    .ok_or_else(|| Error::Usage(format!("`--set {}` is missing `=<value>`", assignment)))?;
+                parsed.overrides.push(Override::new(format!("--set {}", field), field.split('.'), value));
+            }
+            _ => return Err(Error::Usage(format!("unrecognized argument `{}`", arg))),
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn env_overrides() -> Vec<Override> {
+    let mut overrides: Vec<Override> = env::vars()
+        .filter_map(|(name, value)| {
+            let field = name.strip_prefix(ENV_PREFIX)?.to_lowercase();
+            Some(Override::new(name.clone(), field.split("__"), &value))
+        })
+        // Logging isn't set up until the config is loaded.
+        .filter(|r#override| r#override.names_field() || {
+            eprintln!("warning: ignoring `{}`, which does not name a config field", r#override.source);
+            false
+        })
+        .collect();
+
+    // Sorting applies a parent such as `PFA_HTTP` before the nested fields that refine it.
+    overrides.sort_by(|a, b| a.source.cmp(&b.source));
+    overrides
+}
+
+/// A single field set from outside the config file.
+struct Override {
+    /// Where the override came from, for error messages.
+    source: String,
+    path: Vec<String>,
+    value: String,
+}
+
+impl Override {
+    fn new<'a>(source: String, path: impl Iterator<Item = &'a str>, value: &str) -> Override {
+        Override {
+            source,
+            path: path.map(str::to_owned).collect(),
+            value: value.to_owned(),
+        }
+    }
+
+    /// Whether the override's path starts at a field of the config.
+    fn names_field(&self) -> bool {
+        let (field, parents) = match self.path.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        if field.is_empty() || parents.iter().any(String::is_empty) {
+            return false;
+        }
+
+        let top = match (parents.first(), field.strip_suffix(SECRET_FILE_SUFFIX)) {
+            (Some(top), _) => top,
+            (None, Some(secret)) => return SECRET_FIELDS.contains(&secret),
+            (None, None) => field,
+        };
+        let defaults = serde_json::to_value(Config::default()).expect("config is always serializable");
+        defaults.get(top).is_some()
+    }
+
+    /// The value as JSON if the field can take it as such, and as a string otherwise. Whether
+    /// it can is told by setting it on the defaults, there being no other record of field types.
+    fn value(&self) -> serde_json::Value {
+        let string = serde_json::Value::String(self.value.clone());
+        let parsed = match serde_json::from_str(&self.value) {
+            Ok(parsed @ serde_json::Value::String(_)) => return parsed,
+            Ok(parsed) => parsed,
+            Err(_) => return string,
+        };
+        if self.path.last().is_some_and(|field| field.ends_with(SECRET_FILE_SUFFIX)) {
+            return string;
+        }
+
+        let fits = |value: &serde_json::Value| {
+            let mut defaults = serde_json::to_value(Config::default()).expect("config is always serializable");
+            self.set(&mut defaults, value.clone());
+            serde_json::from_value::<Config>(defaults).is_ok()
+        };
+        if !fits(&parsed) && fits(&string) {
+            string
+        } else {
+            parsed
+        }
+    }
+
+    fn apply(&self, root: &mut serde_json::Value) -> Result<(), Error> {
+        if !self.names_field() {
+            return Err(Error::Usage(format!("`{}` does not name a config field", self.source)));
+        }
+        self.set(root, self.value());
+        Ok(())
+    }
+
+    fn set(&self, root: &mut serde_json::Value, value: serde_json::Value) {
+        let (field, parents) = self.path.split_last().expect("override paths are checked first");
+
+        let mut object = root;
+        for parent in parents {
+            if !object.is_object() {
+                *object = serde_json::Value::Object(serde_json::Map::new());
+            }
+            object = object.as_object_mut().unwrap()
+                .entry(parent.clone())
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        }
+
+        if !object.is_object() {
+            *object = serde_json::Value::Object(serde_json::Map::new());
+        }
+        let object = object.as_object_mut().unwrap();
+
+        // Whichever of `<name>` and `<name>_file` is overridden must win over the other.
+        match field.strip_suffix(SECRET_FILE_SUFFIX) {
+            Some(plain) => object.remove(plain),
+            None => object.remove(&format!("{}{}", field, SECRET_FILE_SUFFIX)),
+        };
+        object.insert(field.clone(), value);
+    }
+}
+
+/// Where a config was loaded from, for error messages.
+#[derive(Debug)]
+pub struct Origin {
+    pub file: Option<PathBuf>,
+    /// Whether fields were overridden from the environment or command line.
+    pub overridden: bool,
+}
+
+impl Origin {
+    fn new(file: Option<&Path>, overridden: bool) -> Origin {
+        Origin { file: file.map(Path::to_owned), overridden }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(path) => write!(f, "{:?}", path)?,
+            None => f.write_str("the defaults")?,
+        }
+        if self.overridden {
+            f.write_str(" with overrides")?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces each `<name>_file` field of a secret with a `<name>` field holding the contents of
+/// that file, so that secrets can be mounted as files rather than written inline. Returns
+/// whether any were read.
+fn read_secret_files(value: &mut serde_json::Value) -> Result<bool, Error> {
+    let object = match value {
+        serde_json::Value::Object(object) => object,
+        _ => return Ok(false),
+    };
+
+    let mut read = false;
+    for field in SECRET_FIELDS {
+        read |= read_secret_file(object, "", field, false)?;
+    }
+
+    if let Some(serde_json::Value::Object(tenants)) = object.get_mut("tenants") {
+        for (name, tenant) in tenants.iter_mut() {
+            if let serde_json::Value::Object(tenant) = tenant {
+                read |= read_secret_file(tenant, &format!("tenants.{}.", name), TENANT_SECRET_FIELD, true)?;
+            }
+        }
+    }
+
+    Ok(read)
+}
+
+/// Reads `<field>_file` of `object` into `field`, as a list of its non-empty lines if `lines`
+/// and as a single string otherwise.
+fn read_secret_file(object: &mut serde_json::Map<String, serde_json::Value>, parent: &str, field: &str, lines: bool) -> Result<bool, Error> {
+    let file_field = format!("{}{}", field, SECRET_FILE_SUFFIX);
+    let path = match object.remove(&file_field) {
+        Some(serde_json::Value::String(path)) => PathBuf::from(path),
+        _ => return Ok(false),
+    };
+
+    let secret = fs::read_to_string(&path)
+        .map_err(|source| Error::Secret { field: format!("{}{}", parent, file_field), path, source })?;
+    let value = if lines {
+        secret.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::Value::String(line.to_owned()))
+            .collect()
+    } else {
+        serde_json::Value::String(secret.trim_end_matches(&['\r', '\n'][..]).to_owned())
+    };
+    object.insert(field.to_owned(), value);
+    Ok(true)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed to access config at {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("malformed config from {origin}: {source}")]
+    Malformed { origin: Origin, source: serde_json::Error },
+    #[error("failed to read `{field}` from {path:?}: {source}")]
+    Secret { field: String, path: PathBuf, source: io::Error },
+    #[error("invalid config from {origin}: `{field}` {constraint}")]
+    Invalid { origin: Origin, field: &'static str, constraint: String },
+    #[error("{0}")]
+    Usage(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(field: &str, value: &str) -> Override {
+        Override::new(format!("--set {}", field), field.split('.'), value)
+    }
+
+    fn invalid_field(overrides: &[Override]) -> Option<&'static str> {
+        match load_layered(None, overrides) {
+            Err(Error::Invalid { field, .. }) => Some(field),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_fields_are_named() {
+        assert_eq!(invalid_field(&[set("requests_per_minute", "0")]), Some("requests_per_minute"));
+        assert_eq!(invalid_field(&[set("max_quality", "0")]), Some("max_quality"));
+        assert_eq!(invalid_field(&[set("admin_token", "\"\"")]), Some("admin_token"));
+        assert_eq!(invalid_field(&[set("sources", "{}")]), Some("sources"));
+        assert_eq!(invalid_field(&[set("sources.mojang.profile_endpoint", "https://example.com")]), Some("sources"));
+        assert_eq!(invalid_field(&[set("tenants", r#"{"t":{"features":["nope"]}}"#)]), Some("tenants"));
+        assert_eq!(invalid_field(&[set("ping.status_server", ":25565")]), Some("ping.status_server"));
+        assert_eq!(invalid_field(&[set("tracing", r#"{"endpoint":"http://localhost","sample_ratio":2}"#)]), Some("tracing.sample_ratio"));
+    }
+
+    #[test]
+    fn secrets_are_read_from_files() {
+        let dir = env::temp_dir().join(format!("player-face-api-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (token, keys) = (dir.join("token"), dir.join("keys"));
+        fs::write(&token, "sekret\n").unwrap();
+        fs::write(&keys, "k1\n\n  k2 \n").unwrap();
+        let token = token.to_str().unwrap();
+        let tenants = serde_json::json!({ "t": { "api_keys_file": keys } }).to_string();
+
+        let config = load_layered(None, &[set("admin_token_file", token), set("tenants", &tenants)]).unwrap();
+        assert_eq!(config.admin_token.as_deref(), Some("sekret"));
+        assert_eq!(config.tenants["t"].api_keys, ["k1", "k2"]);
+
+        // Whichever of a secret and its file comes last wins.
+        let config = load_layered(None, &[set("admin_token", "inline"), set("admin_token_file", token)]).unwrap();
+        assert_eq!(config.admin_token.as_deref(), Some("sekret"));
+        let config = load_layered(None, &[set("admin_token_file", token), set("admin_token", "inline")]).unwrap();
+        assert_eq!(config.admin_token.as_deref(), Some("inline"));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(load_layered(None, &[set("admin_token_file", token)]), Err(Error::Secret { .. })));
+    }
+
+    #[test]
+    fn only_secrets_are_read_from_files() {
+        let mut value = serde_json::json!({
+            "audit_log_file": "/nonexistent",
+            "ping": { "status_server_file": "/nonexistent" },
+            "tenants": { "t": { "origins_file": "/nonexistent" } },
+        });
+        let original = value.clone();
+        assert!(!read_secret_files(&mut value).unwrap());
+        assert_eq!(value, original);
+    }
+
+    #[test]
+    fn overrides_are_json_where_the_field_takes_it() {
+        let config = load_layered(None, &[
+            set("requests_per_minute", "77"),
+            set("rate_limit_burst", "5"),
+            set("graphql", "true"),
+            set("http.keep_alive", "false"),
+            set("source_selection_ips", r#"["10.0.0.0/8"]"#),
+        ]).unwrap();
+        assert_eq!(config.requests_per_minute, 77);
+        assert_eq!(config.rate_limit_burst, Some(5));
+        assert!(config.graphql);
+        assert!(!config.http.keep_alive);
+        assert_eq!(config.source_selection_ips, vec!["10.0.0.0/8".parse::<IpNet>().unwrap()]);
+    }
+
+    #[test]
+    fn overrides_are_strings_where_the_field_takes_them() {
+        let config = load_layered(None, &[set("admin_token", "12345"), set("signing_key", "true")]).unwrap();
+        assert_eq!(config.admin_token.as_deref(), Some("12345"));
+        assert_eq!(config.signing_key.as_deref(), Some("true"));
+
+        let config = load_layered(None, &[set("admin_token", "\"quoted\"")]).unwrap();
+        assert_eq!(config.admin_token.as_deref(), Some("quoted"));
+
+        assert!(matches!(load_layered(None, &[set("requests_per_minute", "many")]), Err(Error::Malformed { .. })));
+    }
+
+    #[test]
+    fn overrides_must_name_fields() {
+        assert!(set("requests_per_minute", "1").names_field());
+        assert!(set("http.keep_alive", "1").names_field());
+        assert!(set("admin_token_file", "/run/token").names_field());
+        assert!(set("tenants.t.api_keys_file", "/run/keys").names_field());
+
+        assert!(!set("nope", "1").names_field());
+        assert!(!set("requests_per_minute_file", "/run/rpm").names_field());
+        assert!(!set("http..keep_alive", "1").names_field());
+        assert!(matches!(load_layered(None, &[set("nope", "1")]), Err(Error::Usage(_))));
+    }
 
-        let mut file = File::create(path).unwrap();
-        serde_json::to_writer_pretty(&mut file, &config).unwrap();
+    #[test]
+    fn cache_policies_name_real_caches() {
+        let config = load_layered(None, &[set("cache_policies", r#"{"faces":"lfu","profiles":"tiny_lfu"}"#)]).unwrap();
+        assert_eq!(config.cache_policy("faces"), Policy::Lfu);
+        assert_eq!(config.cache_policy("profiles"), Policy::TinyLfu);
+        assert_eq!(config.cache_policy("skins"), Policy::Lru);
 
-        config
+        assert_eq!(invalid_field(&[set("cache_policies", r#"{"face":"lfu"}"#)]), Some("cache_policies"));
+        assert!(matches!(load_layered(None, &[set("cache_policies", r#"{"faces":"mru"}"#)]), Err(Error::Malformed { .. })));
     }
 }
@@ -0,0 +1,234 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Enum, Object, Schema, SimpleObject};
+use uuid::Uuid;
+use warp::{Filter, Rejection, Reply};
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+
+use crate::api::{AccessDenied, Api, ApiAccess, Client, PlayerInfo};
+use crate::minecraft;
+use crate::options;
+use crate::skin::Model;
+use crate::web;
+
+const MAX_BODY_BYTES: u64 = 16 * 1024;
+const MAX_PLAYERS_PER_QUERY: usize = 100;
+const MAX_QUERY_DEPTH: usize = 8;
+/// Enough for every field of `MAX_PLAYERS_PER_QUERY` players, with room to spare.
+const MAX_QUERY_COMPLEXITY: usize = 2048;
+
+const DEFAULT_LAYOUT_SCALE: u32 = 4;
+
+type PlayerSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// `POST /graphql`, answering queries about any number of players in one round trip. Not
+/// mounted at all unless enabled.
+pub fn routes(api: Api, enabled: bool, allow_offline_uuids: bool) -> BoxedFilter<(Box<dyn Reply>,)> {
+    let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(AllowOfflineUuids(allow_offline_uuids))
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish();
+
+    warp::path("graphql")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and_then(move || async move {
+            if enabled { Ok(()) } else { Err(warp::reject::not_found()) }
+        })
+        .untuple_one()
+        .and(web::client(&api.tenants(), "graphql"))
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and_then(move |client, request| execute(api.clone(), schema.clone(), client, request))
+        // Boxed since executing a query is a large future, which would otherwise be carried by
+        // every request and overflow the stack in debug builds.
+        .boxed()
+}
+
+async fn execute(api: Api, schema: PlayerSchema, client: Client, request: async_graphql::Request) -> Result<Box<dyn Reply>, Rejection> {
+    log::debug!("receiving graphql request from {:?}", client.addr);
+
+    let access = match api.try_access(&client, None).await {
+        Ok(access) => access,
+        Err(AccessDenied::Forbidden) => return Ok(Box::new(StatusCode::FORBIDDEN)),
+        Err(AccessDenied::RateLimited) => return Ok(Box::new(StatusCode::TOO_MANY_REQUESTS)),
+        Err(AccessDenied::UnknownSource) => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+
+    let quota = Quota { api, client, loaded: AtomicUsize::new(0) };
+    let response = schema.execute(request.data(access).data(quota)).await;
+    Ok(Box::new(warp::reply::json(&response)))
+}
+
+struct AllowOfflineUuids(bool);
+
+/// The players a query has loaded so far, each but the first drawing another request from the
+/// client's quota, the request itself having paid for one.
+struct Quota {
+    api: Api,
+    client: Client,
+    loaded: AtomicUsize,
+}
+
+impl Quota {
+    async fn charge(&self) -> async_graphql::Result<()> {
+        let loaded = self.loaded.fetch_add(1, Ordering::Relaxed);
+        if loaded >= MAX_PLAYERS_PER_QUERY {
+            return Err(format!("at most {} players may be asked for in one query", MAX_PLAYERS_PER_QUERY).into());
+        }
+        if loaded > 0 && self.api.rate_limit(&self.client).await.is_err() {
+            return Err("rate limited".into());
+        }
+        Ok(())
+    }
+}
+
+struct Query;
+
+#[Object]
+impl Query {
+    /// A player by UUID, or null if there is no such player.
+    async fn player(&self, ctx: &Context<'_>, uuid: String) -> async_graphql::Result<Option<Player>> {
+        let uuid = parse_uuid(ctx, &uuid)?;
+        load_player(ctx, uuid).await
+    }
+
+    /// Players by UUID in the order given, each null if there is no such player. A player that
+    /// fails to load doesn't fail the others.
+    #[graphql(complexity = "uuids.len() * child_complexity")]
+    async fn players(&self, ctx: &Context<'_>, uuids: Vec<String>) -> async_graphql::Result<Vec<Option<async_graphql::Result<Player>>>> {
+        if uuids.len() > MAX_PLAYERS_PER_QUERY {
+            return Err(format!("at most {} players may be asked for at once", MAX_PLAYERS_PER_QUERY).into());
+        }
+
+        let uuids = uuids.iter()
+            .map(|uuid| parse_uuid(ctx, uuid))
+            .collect::<async_graphql::Result<Vec<_>>>()?;
+
+        let players = futures::future::join_all(uuids.into_iter().map(|uuid| load_player(ctx, uuid))).await;
+        // Errors within an optional value null it alone rather than the whole list.
+        Ok(players.into_iter().map(Result::transpose).collect())
+    }
+
+    /// The sizes that faces, heads and the other square renders may be asked for in.
+    async fn sizes(&self) -> Vec<u32> {
+        options::allowed_sizes().collect()
+    }
+}
+
+fn parse_uuid(ctx: &Context<'_>, uuid: &str) -> async_graphql::Result<Uuid> {
+    let allow_offline = ctx.data_unchecked::<AllowOfflineUuids>().0;
+    match Uuid::parse_str(uuid) {
+        Ok(uuid) if minecraft::is_valid_player_uuid(&uuid, allow_offline) => Ok(uuid),
+        _ => Err(format!("invalid player uuid `{}`", uuid).into()),
+    }
+}
+
+async fn load_player(ctx: &Context<'_>, uuid: Uuid) -> async_graphql::Result<Option<Player>> {
+    ctx.data_unchecked::<Quota>().charge().await?;
+    let api = ctx.data_unchecked::<ApiAccess>();
+    let info = api.get_info(uuid).await?;
+    Ok(info.map(|info| Player { info, stats: api.has_stats() }))
+}
+
+struct Player {
+    info: PlayerInfo,
+    /// Whether cards can be rendered, which needs the statistics api.
+    stats: bool,
+}
+
+#[Object]
+impl Player {
+    async fn uuid(&self) -> String {
+        self.info.uuid.to_hyphenated().to_string()
+    }
+
+    async fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    async fn model(&self) -> SkinModel {
+        match self.info.model {
+            Model::Wide => SkinModel::Wide,
+            Model::Slim => SkinModel::Slim,
+        }
+    }
+
+    /// Whether the player has a skin of their own rather than a default one.
+    async fn custom_skin(&self) -> bool {
+        self.info.custom_skin
+    }
+
+    async fn cape(&self) -> bool {
+        self.info.cape
+    }
+
+    /// The hash of the skin texture, which identifies it across players.
+    async fn texture_hash(&self) -> Option<&str> {
+        self.info.texture_hash.as_deref()
+    }
+
+    /// When the textures were last changed, in milliseconds since the epoch.
+    async fn texture_timestamp(&self) -> Option<u64> {
+        self.info.timestamp
+    }
+
+    /// Every render available for the player, with its URL relative to this server. Square
+    /// renders are given at `size`.
+    async fn renders(&self, #[graphql(default = 64)] size: u32) -> async_graphql::Result<Vec<Render>> {
+        if options::parse_size(size).is_none_or(|size| size < 8) {
+            return Err("invalid size".into());
+        }
+
+        let uuid = self.info.uuid;
+        let mut renders = vec![
+            Render::sized("face", "face", size, uuid),
+            Render::sized("head", "head", size, uuid),
+            Render::sized("compass", "compass", size, uuid),
+            Render::sized("views", "views", size, uuid),
+            Render::sized("preview", "preview", size, uuid),
+            Render::sized("chibi", "chibi", size, uuid),
+            Render::sized("layout", "layout", DEFAULT_LAYOUT_SCALE, uuid),
+            Render::fixed("banner", "banner", uuid),
+            Render::fixed("skin", "skin", uuid),
+            Render::fixed("normalized_skin", "skin/normalized", uuid),
+        ];
+        if self.info.cape {
+            renders.push(Render::fixed("cape", "cape", uuid));
+        }
+        if self.stats {
+            renders.push(Render::fixed("card", "card", uuid));
+        }
+        if let Some(hash) = &self.info.texture_hash {
+            renders.push(Render {
+                name: "texture_face",
+                url: format!("/face/{}/texture/{}", size, hash),
+            });
+        }
+        Ok(renders)
+    }
+}
+
+#[derive(Enum, Copy, Clone, PartialEq, Eq)]
+enum SkinModel {
+    Wide,
+    Slim,
+}
+
+#[derive(SimpleObject)]
+struct Render {
+    name: &'static str,
+    url: String,
+}
+
+impl Render {
+    fn sized(name: &'static str, path: &str, size: u32, uuid: Uuid) -> Render {
+        Render { name, url: format!("/{}/{}/{}", path, size, uuid) }
+    }
+
+    fn fixed(name: &'static str, path: &str, uuid: Uuid) -> Render {
+        Render { name, url: format!("/{}/{}", path, uuid) }
+    }
+}
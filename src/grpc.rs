@@ -0,0 +1,150 @@
+// Handlers must fail with tonic's `Status`, however large it is.
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use serde::de::DeserializeOwned;
+use tonic::{Code, Request, Response, Status};
+use uuid::Uuid;
+
+use crate::api::{self, AccessDenied, Api, ApiAccess, Client, ImageBytes};
+use crate::cache;
+use crate::minecraft;
+use crate::options::{self, FaceOptions, HeadOptions};
+use crate::skin::Model;
+
+mod proto {
+    tonic::include_proto!("player_face");
+}
+
+use proto::player_face_server::{self, PlayerFaceServer};
+use proto::{FaceRequest, HeadRequest, ImageReply, ProfileReply, ProfileRequest};
+
+/// Serves the gRPC API on `addr` until the process exits. Requests go through the same access
+/// checks and caches as their HTTP counterparts.
+pub async fn run(api: Api, addr: SocketAddr, allow_offline_uuids: bool) {
+    log::info!("serving grpc on {}", addr);
+
+    let service = PlayerFaceServer::new(PlayerFaceService { api, allow_offline_uuids });
+    if let Err(err) = tonic::transport::Server::builder().add_service(service).serve(addr).await {
+        log::error!("grpc server failed: {}", err);
+    }
+}
+
+struct PlayerFaceService {
+    api: Api,
+    allow_offline_uuids: bool,
+}
+
+impl PlayerFaceService {
+    async fn access<T>(&self, request: &Request<T>) -> Result<ApiAccess, Status> {
+        // Tenants are recognized over HTTP alone, so gRPC clients are held to their own quotas.
+        let client = Client { addr: request.remote_addr(), tenant: None };
+        self.api.try_access(&client, None).await.map_err(denied_status)
+    }
+
+    fn parse_uuid(&self, uuid: &str) -> Result<Uuid, Status> {
+        match Uuid::parse_str(uuid) {
+            Ok(uuid) if minecraft::is_valid_player_uuid(&uuid, self.allow_offline_uuids) => Ok(uuid),
+            _ => Err(Status::invalid_argument("invalid player uuid")),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl player_face_server::PlayerFace for PlayerFaceService {
+    async fn get_face(&self, request: Request<FaceRequest>) -> Result<Response<ImageReply>, Status> {
+        let api = self.access(&request).await?;
+        let request = request.into_inner();
+        log::debug!("receiving grpc face request for {0} ({1}x{1})", request.uuid, request.size);
+
+        let uuid = self.parse_uuid(&request.uuid)?;
+        let options: FaceOptions = parse_options(&request.options)?;
+        let size = match options::parse_size(request.size) {
+            Some(size) if options.is_valid() => size,
+            _ => return Err(Status::invalid_argument("invalid size or options")),
+        };
+
+        image_response(api.get_face(uuid, size, options).await)
+    }
+
+    async fn get_head(&self, request: Request<HeadRequest>) -> Result<Response<ImageReply>, Status> {
+        let api = self.access(&request).await?;
+        let request = request.into_inner();
+        log::debug!("receiving grpc head request for {0} ({1}x{1})", request.uuid, request.size);
+
+        let uuid = self.parse_uuid(&request.uuid)?;
+        let options: HeadOptions = parse_options(&request.options)?;
+        let size = match options::parse_size(request.size) {
+            Some(size) if options.is_valid() => size,
+            _ => return Err(Status::invalid_argument("invalid size or options")),
+        };
+
+        image_response(api.get_head(uuid, size, options).await)
+    }
+
+    async fn get_profile(&self, request: Request<ProfileRequest>) -> Result<Response<ProfileReply>, Status> {
+        let api = self.access(&request).await?;
+        let uuid = self.parse_uuid(&request.get_ref().uuid)?;
+        log::debug!("receiving grpc profile request for {}", uuid);
+
+        let info = api.get_info(uuid).await
+            .map_err(error_status)?
+            .ok_or_else(|| Status::not_found("no such player"))?;
+
+        Ok(Response::new(ProfileReply {
+            uuid: info.uuid.to_hyphenated().to_string(),
+            name: info.name,
+            model: match info.model {
+                Model::Wide => "wide",
+                Model::Slim => "slim",
+            }.to_owned(),
+            custom_skin: info.custom_skin,
+            cape: info.cape,
+            texture_hash: info.texture_hash,
+            timestamp: info.timestamp,
+        }))
+    }
+}
+
+/// Reads render options from the same fields the HTTP routes take in their query string.
+fn parse_options<T: DeserializeOwned>(options: &HashMap<String, String>) -> Result<T, Status> {
+    serde_urlencoded::to_string(options).ok()
+        .and_then(|query| serde_urlencoded::from_str(&query).ok())
+        .ok_or_else(|| Status::invalid_argument("invalid options"))
+}
+
+fn image_response(result: api::Result<(ImageBytes, cache::Outcome)>) -> Result<Response<ImageReply>, Status> {
+    let (image, outcome) = result.map_err(error_status)?;
+    Ok(Response::new(ImageReply {
+        image: image.bytes().to_vec(),
+        content_type: image.content_type().to_owned(),
+        etag: image.etag().to_owned(),
+        cached: outcome == cache::Outcome::Hit,
+    }))
+}
+
+/// The gRPC counterpart of each HTTP status given for an error.
+fn error_status(err: api::Error) -> Status {
+    let code = match err {
+        api::Error::UpstreamThrottled | api::Error::Overloaded => Code::Unavailable,
+        api::Error::Blocked => Code::PermissionDenied,
+        api::Error::UnknownBadge | api::Error::NoStatusServer | api::Error::NoPlayer => Code::InvalidArgument,
+        api::Error::NoStatsApi => Code::NotFound,
+        api::Error::StatsApi | api::Error::InvalidCape => Code::Unavailable,
+        err => {
+            log::error!("internal server error: {:?}", err);
+            Code::Internal
+        }
+    };
+    Status::new(code, err.to_string())
+}
+
+fn denied_status(denied: AccessDenied) -> Status {
+    match denied {
+        AccessDenied::Forbidden => Status::permission_denied("forbidden"),
+        AccessDenied::RateLimited => Status::resource_exhausted("rate limited"),
+        AccessDenied::UnknownSource => Status::invalid_argument("unknown source"),
+    }
+}
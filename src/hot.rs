@@ -0,0 +1,57 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::{Instant, MissedTickBehavior};
+use uuid::Uuid;
+
+use crate::api::Api;
+
+/// Players tracked at once, so that requests for many distinct players can't exhaust memory.
+const MAX_PLAYERS: usize = 65536;
+
+/// Requests per player, decayed with each refresh so that the players requested most are those
+/// popular lately rather than ever.
+#[derive(Default)]
+pub struct HotPlayers {
+    counts: Mutex<HashMap<Uuid, u64>>,
+}
+
+impl HotPlayers {
+    pub fn record(&self, uuid: Uuid) {
+        let mut counts = self.counts.lock().unwrap();
+        if counts.len() >= MAX_PLAYERS && !counts.contains_key(&uuid) {
+            return;
+        }
+        *counts.entry(uuid).or_insert(0) += 1;
+    }
+
+    /// The `limit` most requested players, most requested first. Every count is halved in
+    /// taking them, and players no longer requested are eventually forgotten.
+    pub fn take_top(&self, limit: usize) -> Vec<Uuid> {
+        let mut counts = self.counts.lock().unwrap();
+
+        let mut top: Vec<(Uuid, u64)> = counts.iter().map(|(&uuid, &count)| (uuid, count)).collect();
+        top.sort_unstable_by_key(|&(_, count)| Reverse(count));
+        top.truncate(limit);
+
+        counts.retain(|_, count| {
+            *count /= 2;
+            *count > 0
+        });
+
+        top.into_iter().map(|(uuid, _)| uuid).collect()
+    }
+}
+
+/// Revalidates the `players` most requested players every `interval`, forever.
+pub async fn run(api: Api, players: usize, interval: Duration) {
+    let mut ticks = tokio::time::interval_at(Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticks.tick().await;
+        api.refresh_hot_players(players).await;
+    }
+}
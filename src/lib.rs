@@ -0,0 +1,32 @@
+pub use config::*;
+
+use player_face_core::{face, options, render, skin};
+
+mod access;
+mod admin;
+mod audit;
+mod activation;
+mod access_log;
+pub mod api;
+mod cache;
+pub mod check;
+pub mod config;
+mod graphql;
+pub mod grpc;
+pub mod hot;
+pub mod logging;
+mod metrics;
+mod minecraft;
+mod nucleoid;
+mod ping;
+mod render_queue;
+mod shared_limit;
+mod snapshot;
+mod source;
+mod tenant;
+mod timing;
+mod trace;
+mod upstream;
+mod usage;
+mod watch;
+pub mod web;
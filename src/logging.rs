@@ -0,0 +1,33 @@
+use std::io::{self, Write};
+
+use env_logger::fmt::Formatter;
+use serde::{Deserialize, Serialize};
+
+use crate::timing;
+
+/// How each logged event is written to stderr.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// env_logger's usual lines, for reading by eye.
+    #[default]
+    Text,
+    /// One JSON object per line, for log aggregators.
+    Json,
+}
+
+/// Writes `record` as a single JSON object, along with the request it was logged while handling
+/// if any: its ID, the player it was for, and how long it had been running.
+pub fn write_json(buf: &mut Formatter, record: &log::Record) -> io::Result<()> {
+    let request = timing::context();
+    let entry = serde_json::json!({
+        "time": chrono::Utc::now().to_rfc3339(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+        "request_id": request.as_ref().map(|request| request.request_id()),
+        "uuid": request.as_ref().and_then(|request| request.player()),
+        "duration_ms": request.as_ref().map(|request| request.elapsed().as_secs_f64() * 1000.0),
+    });
+    writeln!(buf, "{}", entry)
+}
@@ -1,20 +1,63 @@
-pub use config::*;
+use std::time::Duration;
 
-mod api;
-mod cache;
-mod config;
-mod minecraft;
-mod render;
-mod skin;
-mod web;
+use player_face_api::{api, check, config, grpc, hot, web};
+use player_face_api::logging::{self, LogFormat};
+use player_face_api::config::Mode;
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    let (config, mode) = match config::load_with_mode() {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
-    let config = config::load();
+    if let Mode::Check { bind } = mode {
+        let problems = check::run(&config, bind);
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        if !problems.is_empty() {
+            std::process::exit(1);
+        }
+        println!("config ok");
+        return;
+    }
 
-    let api = api::Api::new(config.clone());
+    let mut logger = env_logger::Builder::new();
+    if config.access_log.is_some() {
+        logger.filter_module("access", log::LevelFilter::Info);
+    }
+    if config.admin_token.is_some() {
+        logger.filter_module("audit", log::LevelFilter::Info);
+    }
+    if config.log_format == LogFormat::Json {
+        logger.format(logging::write_json);
+    }
+    logger.parse_default_env().init();
 
-    web::run(api, config).await;
+    let api = match api::Api::new(config.clone()) {
+        Ok(api) => api,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(addr) = config.grpc_listen {
+        tokio::spawn(grpc::run(api.clone(), addr, config.allow_offline_uuids));
+    }
+
+    if config.hot_players > 0 {
+        let interval = Duration::from_secs(config.hot_refresh_interval_secs);
+        tokio::spawn(hot::run(api.clone(), config.hot_players, interval));
+    }
+
+    web::run(api.clone(), config.clone()).await;
+
+    if let Some(path) = &config.cache_snapshot {
+        api.save_snapshot(path);
+    }
 }
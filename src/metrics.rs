@@ -0,0 +1,96 @@
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The Prometheus text exposition format's content type.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Connections accepted by our own listeners and not yet closed.
+pub static OPEN_CONNECTIONS: Gauge = Gauge::new();
+/// Requests received on our own listeners and not yet answered.
+pub static IN_FLIGHT_REQUESTS: Gauge = Gauge::new();
+/// Renders and texture decodes running on tokio's blocking pool, as opposed to waiting for it.
+pub static BLOCKING_TASKS: Gauge = Gauge::new();
+
+#[derive(Copy, Clone, Debug)]
+pub enum Kind {
+    Counter,
+    Gauge,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+        }
+    }
+}
+
+/// A count of things in progress, raised for as long as each [`Entered`] handed out lives.
+pub struct Gauge(AtomicUsize);
+
+impl Gauge {
+    pub const fn new() -> Gauge {
+        Gauge(AtomicUsize::new(0))
+    }
+
+    #[inline]
+    pub fn enter(&'static self) -> Entered {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Entered(self)
+    }
+
+    #[inline]
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// One thing counted by a [`Gauge`], until dropped.
+pub struct Entered(&'static Gauge);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        (self.0).0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Writes metrics in the Prometheus text exposition format. Each metric family is declared once
+/// with [`Exposition::family`] and followed by its samples.
+#[derive(Default)]
+pub struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    pub fn family(&mut self, name: &str, kind: Kind, help: &str) {
+        writeln!(self.out, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.out, "# TYPE {} {}", name, kind.as_str()).unwrap();
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.out.push_str(name);
+
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (i, (label, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                write!(self.out, "{}=\"{}\"", label, escape(value)).unwrap();
+            }
+            self.out.push('}');
+        }
+
+        writeln!(self.out, " {}", value).unwrap();
+    }
+
+    #[inline]
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
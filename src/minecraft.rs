@@ -1,23 +1,50 @@
 use std::collections::HashMap;
 use std::io;
 use std::io::Read;
+use std::path::Path;
+use std::sync::OnceLock;
 
-use bytes::Bytes;
-use image::{DynamicImage, ImageFormat};
+use bytes::{Bytes, BytesMut};
+use image::ImageFormat;
 use serde::de::DeserializeOwned;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::time::Duration;
 use uuid::Uuid;
-use warp::hyper::http::StatusCode;
+use warp::hyper::http::{header, HeaderMap, HeaderValue, StatusCode};
 
-const PROFILE_ENDPOINT: &'static str = "https://sessionserver.mojang.com/session/minecraft/profile";
+use crate::source::Source;
+
+const PROFILES_ENDPOINT: &str = "https://api.mojang.com/profiles/minecraft";
+
+/// The most names the bulk profile endpoint accepts in a single request.
+pub const MAX_NAMES_PER_REQUEST: usize = 10;
+/// The widest or tallest texture we will decode; larger images are rejected from their header.
+const MAX_TEXTURE_DIMENSION: u32 = 512;
 const TIMEOUT: Duration = Duration::from_secs(10);
 
-pub async fn get_profile(uuid: Uuid) -> Result<Option<PlayerProfile>> {
-    log::debug!("getting player profile for {}", uuid);
+const DEFAULT_USER_AGENT: &str = concat!(
+    "player-face-api/", env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/NucleoidMC/player-face-api)"
+);
+
+static USER_AGENT: OnceLock<String> = OnceLock::new();
+
+/// Overrides the User-Agent sent with every upstream request. Only the first call has an effect.
+pub fn set_user_agent(user_agent: String) {
+    if USER_AGENT.set(user_agent).is_err() {
+        log::warn!("user agent already set");
+    }
+}
+
+pub async fn get_profile(source: &Source, uuid: Uuid) -> Result<Option<PlayerProfile>> {
+    log::debug!("getting player profile for {} from {}", uuid, source.name);
+
+    if let Some(directory) = &source.directory {
+        return read_profile(directory, uuid).await;
+    }
 
-    let client = client()?;
-    let url = format!("{}/{}", PROFILE_ENDPOINT, uuid);
+    let client = client(source.timeout)?;
+    let url = source.profile_url(uuid);
 
     let response = client.get(url).send().await?;
     match response.status() {
@@ -26,38 +53,150 @@ pub async fn get_profile(uuid: Uuid) -> Result<Option<PlayerProfile>> {
     }
 }
 
-pub async fn get_texture(texture: PlayerTextureRef) -> Result<PlayerTexture> {
+async fn read_profile(directory: &Path, uuid: Uuid) -> Result<Option<PlayerProfile>> {
+    let path = directory.join("profiles").join(format!("{}.json", uuid));
+    match tokio::fs::read(path).await {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Resolves up to [`MAX_NAMES_PER_REQUEST`] usernames in one request. Names without an account
+/// are left out of the result.
+pub async fn get_name_profiles(names: &[String]) -> Result<Vec<NameProfile>> {
+    assert!(names.len() <= MAX_NAMES_PER_REQUEST, "too many names for one request");
+    log::debug!("resolving {} usernames", names.len());
+
+    let client = client(TIMEOUT)?;
+    let response = client.post(PROFILES_ENDPOINT).json(names).send().await?;
+    Ok(response.error_for_status()?.json().await?)
+}
+
+/// Usernames are 1 to 16 ASCII letters, digits, or underscores; Mojang rejects a whole batch
+/// if any name is malformed.
+pub fn is_valid_username(name: &str) -> bool {
+    (1..=16).contains(&name.len()) && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Whether a UUID could identify a player. Offline-mode servers derive version 3 UUIDs from
+/// usernames, which never have a Mojang profile.
+pub fn is_valid_player_uuid(uuid: &Uuid, allow_offline: bool) -> bool {
+    !uuid.is_nil() && (allow_offline || uuid.get_version_num() != 3)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NameProfile {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// Downloads a texture of at most `max_bytes` from `source`, passing along `validators` from a
+/// copy we already hold. Returns `None` if the texture host reports that copy is still current.
+pub async fn get_texture(source: &Source, texture: PlayerTextureRef, validators: &Validators, max_bytes: u64) -> Result<Option<PlayerTexture>> {
     log::debug!("requesting player skin at {}", texture.url);
 
-    let client = client()?;
-    let response = client.get(&texture.url).send().await?;
-    let response = response.bytes().await?;
+    if let Some(directory) = &source.directory {
+        return read_texture(directory, texture, max_bytes).await.map(Some);
+    }
+
+    let client = client(source.timeout)?;
+    let mut request = client.get(&texture.url);
+    if let Some(etag) = &validators.etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        log::debug!("player skin at {} not modified", texture.url);
+        return Ok(None);
+    }
+
+    let validators = Validators::from_headers(response.headers());
+    let response = read_body(response, max_bytes).await?;
+
+    crate::timing::enter("decode");
 
-    tokio::task::spawn_blocking(move || resolve_texture(texture, response)).await.unwrap()
+    match tokio::task::spawn_blocking(move || {
+        let _blocking = crate::metrics::BLOCKING_TASKS.enter();
+        resolve_texture(texture, response, validators).map(Some)
+    }).await {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
 }
 
-fn resolve_texture(texture: PlayerTextureRef, bytes: Bytes) -> Result<PlayerTexture> {
+/// Reads the texture dumped under its hash, which never needs revalidating since the hash names
+/// its content.
+async fn read_texture(directory: &Path, texture: PlayerTextureRef, max_bytes: u64) -> Result<PlayerTexture> {
+    let hash = texture.hash().filter(|hash| PlayerTextureRef::is_valid_hash(hash))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "texture url has no hash"))?;
+    let path = directory.join("textures").join(format!("{}.png", hash));
+
+    if tokio::fs::metadata(&path).await?.len() > max_bytes {
+        return Err(Error::TooLarge);
+    }
+    let bytes = Bytes::from(tokio::fs::read(&path).await?);
+
+    crate::timing::enter("decode");
+
+    match tokio::task::spawn_blocking(move || {
+        let _blocking = crate::metrics::BLOCKING_TASKS.enter();
+        resolve_texture(texture, bytes, Validators::default())
+    }).await {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// Reads a response body chunk by chunk, giving up as soon as it exceeds `max_bytes`.
+async fn read_body(mut response: reqwest::Response, max_bytes: u64) -> Result<Bytes> {
+    if response.content_length().is_some_and(|length| length > max_bytes) {
+        return Err(Error::TooLarge);
+    }
+
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > max_bytes {
+            return Err(Error::TooLarge);
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body.freeze())
+}
+
+fn resolve_texture(texture: PlayerTextureRef, bytes: Bytes, validators: Validators) -> Result<PlayerTexture> {
     let cursor = io::Cursor::new(bytes.as_ref());
+    let reader = image::io::Reader::with_format(cursor.clone(), ImageFormat::Png);
+    let (width, height) = reader.into_dimensions()?;
+    if width > MAX_TEXTURE_DIMENSION || height > MAX_TEXTURE_DIMENSION {
+        return Err(Error::TooLarge);
+    }
+
     let reader = image::io::Reader::with_format(cursor, ImageFormat::Png);
     let image = reader.decode()?;
-    match image {
-        DynamicImage::ImageRgba8(image) => Ok(PlayerTexture {
-            image,
-            metadata: texture.metadata,
-        }),
-        _ => Err(Error::InvalidImageFormat),
-    }
+    Ok(PlayerTexture {
+        image: image.into_rgba8(),
+        metadata: texture.metadata,
+        bytes,
+        validators,
+    })
 }
 
-fn client() -> reqwest::Result<reqwest::Client> {
+pub fn client(timeout: Duration) -> reqwest::Result<reqwest::Client> {
     reqwest::Client::builder()
         .gzip(true)
-        .timeout(TIMEOUT)
+        .timeout(timeout)
+        .user_agent(USER_AGENT.get().map(String::as_str).unwrap_or(DEFAULT_USER_AGENT))
         .use_rustls_tls()
         .build()
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerProfile {
     pub id: Uuid,
     pub name: String,
@@ -95,7 +234,7 @@ fn parse_base64<T: DeserializeOwned>(input: &str) -> Result<T> {
     Ok(serde_json::from_slice(&bytes)?)
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProfileProperty {
     pub name: String,
     pub value: String,
@@ -105,8 +244,6 @@ pub struct ProfileProperty {
 #[serde(rename_all = "camelCase")]
 pub struct PlayerTextures {
     pub timestamp: u64,
-    pub profile_id: Uuid,
-    pub profile_name: String,
     #[serde(rename = "textures")]
     pub refs: PlayerTextureUrls,
 }
@@ -126,9 +263,58 @@ pub struct PlayerTextureRef {
     pub metadata: HashMap<String, String>,
 }
 
+impl PlayerTextureRef {
+    /// References a texture on `source` by its content hash alone, without any model metadata.
+    pub fn from_hash(source: &Source, hash: &str) -> PlayerTextureRef {
+        PlayerTextureRef {
+            url: source.texture_url(hash),
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Texture hashes are lowercase hex digests.
+    pub fn is_valid_hash(hash: &str) -> bool {
+        !hash.is_empty() && hash.len() <= 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    }
+
+    /// The content hash, which texture hosts use as the final path segment.
+    #[inline]
+    pub fn hash(&self) -> Option<&str> {
+        self.url.rsplit('/').next().filter(|hash| !hash.is_empty())
+    }
+}
+
 pub struct PlayerTexture {
     pub image: image::RgbaImage,
     pub metadata: HashMap<String, String>,
+    /// The texture exactly as served by the texture host.
+    pub bytes: Bytes,
+    pub validators: Validators,
+}
+
+/// The `ETag` and `Last-Modified` headers a texture was served with, for revalidating it later.
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Validators {
+        let get = |name| headers.get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(str::to_owned);
+
+        Validators {
+            etag: get(header::ETAG),
+            last_modified: get(header::LAST_MODIFIED),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -143,6 +329,6 @@ pub enum Error {
     Json(#[from] serde_json::Error),
     #[error("parse image")]
     Image(#[from] image::ImageError),
-    #[error("invalid image format")]
-    InvalidImageFormat,
+    #[error("texture too large")]
+    TooLarge,
 }
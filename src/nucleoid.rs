@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::http::StatusCode;
+
+use crate::cache::{Cache, Policy};
+use crate::minecraft;
+
+const STATS_CAPACITY: u64 = 512;
+
+/// Where to find the Nucleoid backend, whose player statistics are shown on `/card`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NucleoidConfig {
+    pub api_url: String,
+    /// How long to reuse a player's statistics, and the cards showing them, in seconds.
+    pub cache_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for NucleoidConfig {
+    fn default() -> Self {
+        NucleoidConfig {
+            api_url: "https://api.nucleoid.xyz".to_owned(),
+            cache_secs: 300,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// A player's statistics by game namespace and then by statistic. Values that aren't numbers
+/// are left out.
+pub type PlayerStats = BTreeMap<String, BTreeMap<String, f64>>;
+
+/// Fetches player statistics from the Nucleoid backend, reusing each player's for a while.
+pub struct StatsClient {
+    api_url: String,
+    timeout: Duration,
+    stats: Cache<Uuid, Arc<PlayerStats>>,
+}
+
+impl StatsClient {
+    pub fn new(config: &NucleoidConfig) -> StatsClient {
+        StatsClient {
+            api_url: config.api_url.trim_end_matches('/').to_owned(),
+            timeout: Duration::from_secs(config.timeout_secs),
+            stats: Cache::new(STATS_CAPACITY, Duration::from_secs(config.cache_secs), Policy::default())
+                .on_load_error(|uuid, err| log::warn!("failed to load statistics for {}: {:?}", uuid, err)),
+        }
+    }
+
+    /// The player's statistics, empty if they have never played.
+    pub async fn get(&self, uuid: Uuid) -> minecraft::Result<Arc<PlayerStats>> {
+        self.stats.try_get(uuid, |uuid| async move { self.load(uuid).await.map(Arc::new) }).await
+    }
+
+    async fn load(&self, uuid: Uuid) -> minecraft::Result<PlayerStats> {
+        log::debug!("getting statistics for {}", uuid);
+
+        let client = minecraft::client(self.timeout)?;
+        let url = format!("{}/stats/player/{}", self.api_url, uuid.to_hyphenated());
+
+        let response = client.get(url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(PlayerStats::new());
+        }
+
+        let namespaces: BTreeMap<String, BTreeMap<String, serde_json::Value>> = response.error_for_status()?.json().await?;
+        Ok(namespaces.into_iter()
+            .map(|(namespace, stats)| {
+                let stats = stats.into_iter()
+                    .filter_map(|(stat, value)| value.as_f64().map(|value| (stat, value)))
+                    .collect();
+                (namespace, stats)
+            })
+            .collect())
+    }
+}
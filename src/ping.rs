@@ -0,0 +1,453 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+use crate::cache::{Cache, Outcome, Policy};
+
+const DEFAULT_PORT: u16 = 25565;
+
+/// Servers answer the status request whatever protocol version we claim, so we claim none.
+const PROTOCOL_VERSION: i32 = -1;
+
+/// Responses are a single JSON string, which the protocol caps at 32767 characters of up to
+/// three bytes each.
+const MAX_RESPONSE_BYTES: usize = 32767 * 3 + 8;
+
+const STATUS_CAPACITY: u64 = 64;
+
+const FAVICON_PREFIX: &str = "data:image/png;base64,";
+
+/// Settings for pinging Minecraft servers with the Server List Ping protocol.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PingConfig {
+    /// The server, as `host` or `host:port`, whose online players are shown on faces requested
+    /// with `?status=true`.
+    pub status_server: Option<String>,
+    /// How long a server may take to answer a ping, in seconds.
+    pub timeout_secs: u64,
+    /// How long to reuse a server's status before pinging it again, in seconds.
+    pub cache_secs: u64,
+    /// Ports besides the default that clients may name servers on, so that they can't have us
+    /// probe other services on public hosts.
+    pub public_ports: Vec<u16>,
+}
+
+impl Default for PingConfig {
+    fn default() -> Self {
+        PingConfig {
+            status_server: None,
+            timeout_secs: 5,
+            cache_secs: 30,
+            public_ports: Vec::new(),
+        }
+    }
+}
+
+/// A server's answer to a status request, as much of it as we use.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ServerStatus {
+    pub version: Option<ServerVersion>,
+    pub players: Option<ServerPlayers>,
+    /// The message of the day, as a chat component or plain string.
+    #[serde(default)]
+    pub description: serde_json::Value,
+    /// A PNG as a `data:image/png;base64,` URL.
+    pub favicon: Option<String>,
+}
+
+impl ServerStatus {
+    /// Whether the player is in the sample of online players. Servers only sample a dozen or
+    /// so players, and may hide them entirely.
+    pub fn is_online(&self, uuid: Uuid) -> bool {
+        self.players.as_ref().is_some_and(|players| {
+            players.sample.iter().any(|player| Uuid::parse_str(&player.id).ok() == Some(uuid))
+        })
+    }
+
+    /// The favicon decoded to PNG bytes, if the server has a well-formed one.
+    pub fn favicon_png(&self) -> Option<Vec<u8>> {
+        let favicon = self.favicon.as_ref()?.strip_prefix(FAVICON_PREFIX)?;
+        // Older servers break the base64 across lines.
+        let favicon: String = favicon.chars().filter(|c| !c.is_whitespace()).collect();
+        base64::decode(favicon).ok()
+    }
+
+    /// The message of the day without any formatting.
+    pub fn motd(&self) -> String {
+        let mut motd = String::new();
+        flatten_component(&self.description, &mut motd);
+        strip_formatting_codes(&motd)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ServerVersion {
+    pub name: String,
+    pub protocol: i32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ServerPlayers {
+    pub max: u32,
+    pub online: u32,
+    #[serde(default)]
+    pub sample: Vec<SamplePlayer>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SamplePlayer {
+    pub name: String,
+    pub id: String,
+}
+
+/// Appends the text of a chat component and its children.
+fn flatten_component(component: &serde_json::Value, text: &mut String) {
+    match component {
+        serde_json::Value::String(string) => text.push_str(string),
+        serde_json::Value::Array(components) => {
+            for component in components {
+                flatten_component(component, text);
+            }
+        }
+        serde_json::Value::Object(object) => {
+            if let Some(serde_json::Value::String(string)) = object.get("text") {
+                text.push_str(string);
+            }
+            if let Some(extra) = object.get("extra") {
+                flatten_component(extra, text);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Removes legacy `§` formatting codes along with the character following each.
+fn strip_formatting_codes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Pings servers on demand, reusing each server's status for a while.
+pub struct Pinger {
+    /// Keyed by address and whether the address had to be public, so that a status fetched
+    /// from a private server is never handed out for a public request.
+    statuses: Cache<(String, bool), Arc<ServerStatus>>,
+    timeout: Duration,
+    cache_duration: Duration,
+    public_ports: Vec<u16>,
+}
+
+impl Pinger {
+    pub fn new(config: &PingConfig) -> Pinger {
+        let statuses = Cache::new(STATUS_CAPACITY, Duration::from_secs(config.cache_secs), Policy::Lru)
+            .on_load_error(|(address, _), err| log::warn!("failed to ping {}: {:?}", address, err));
+        Pinger {
+            statuses,
+            timeout: Duration::from_secs(config.timeout_secs),
+            cache_duration: Duration::from_secs(config.cache_secs),
+            public_ports: config.public_ports.clone(),
+        }
+    }
+
+    /// The status of a server we were configured with, wherever it may be.
+    pub async fn status(&self, address: &str) -> Result<Arc<ServerStatus>, Error> {
+        self.status_of(address, false).await.map(|(status, _)| status)
+    }
+
+    /// The status of a server named by a client, refusing any that resolve to loopback or
+    /// private addresses so that clients can't probe the network we sit in, and any on ports
+    /// other than the default or those allowed.
+    pub async fn public_status(&self, address: &str) -> Result<(Arc<ServerStatus>, Outcome), Error> {
+        let (_, port) = split_address(address).ok_or(Error::InvalidAddress)?;
+        if port != DEFAULT_PORT && !self.public_ports.contains(&port) {
+            return Err(Error::PortNotAllowed);
+        }
+        self.status_of(address, true).await
+    }
+
+    #[inline]
+    pub fn cache_duration(&self) -> Duration {
+        self.cache_duration
+    }
+
+    async fn status_of(&self, address: &str, public_only: bool) -> Result<(Arc<ServerStatus>, Outcome), Error> {
+        let timeout = self.timeout;
+        self.statuses.try_get_outcome((address.to_owned(), public_only), move |(address, public_only)| async move {
+            match tokio::time::timeout(timeout, ping(&address, public_only)).await {
+                Ok(result) => result.map(Arc::new),
+                Err(_) => Err(Error::Timeout),
+            }
+        }).await
+    }
+}
+
+/// Asks the server at `address` for its status over a fresh connection.
+pub async fn ping(address: &str, public_only: bool) -> Result<ServerStatus, Error> {
+    let (host, port) = split_address(address).ok_or(Error::InvalidAddress)?;
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+    let addr = addrs.iter()
+        .find(|addr| !public_only || is_public(addr.ip()))
+        .ok_or(if addrs.is_empty() { Error::InvalidAddress } else { Error::NotPublic })?;
+    let mut stream = TcpStream::connect(addr).await?;
+
+    let mut handshake = Vec::new();
+    write_var_int(&mut handshake, 0x00);
+    write_var_int(&mut handshake, PROTOCOL_VERSION);
+    write_string(&mut handshake, host);
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_var_int(&mut handshake, 1);
+
+    let mut request = Vec::new();
+    write_packet(&mut request, &handshake);
+    write_packet(&mut request, &[0x00]);
+    stream.write_all(&request).await?;
+
+    let mut stream = BufReader::new(stream);
+    let length = read_var_int(&mut stream).await? as usize;
+    if length > MAX_RESPONSE_BYTES {
+        return Err(Error::Malformed("response too long"));
+    }
+
+    let mut packet = vec![0; length];
+    stream.read_exact(&mut packet).await?;
+
+    let mut packet = &packet[..];
+    if read_var_int(&mut packet).await? != 0x00 {
+        return Err(Error::Malformed("unexpected packet"));
+    }
+
+    let json_length = read_var_int(&mut packet).await? as usize;
+    if json_length != packet.len() {
+        return Err(Error::Malformed("string length mismatch"));
+    }
+
+    Ok(serde_json::from_slice(packet)?)
+}
+
+/// Whether the address is reachable from the internet at large.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            let this_network = octets[0] == 0;
+            let shared = octets[0] == 100 && (octets[1] & 0xC0) == 64;
+            let benchmarking = octets[0] == 198 && (octets[1] & 0xFE) == 18;
+            // Also covers the broadcast address.
+            let reserved = octets[0] >= 240;
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_multicast()
+                || ip.is_documentation() || this_network || shared || benchmarking || reserved)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            // NAT64 reaches the IPv4 address held in the last 32 bits.
+            let segments = ip.segments();
+            if segments[..6] == [0x64, 0xFF9B, 0, 0, 0, 0] {
+                let [.., a, b, c, d] = ip.octets();
+                return is_public(IpAddr::V4([a, b, c, d].into()));
+            }
+            let unique_local = (segments[0] & 0xFE00) == 0xFC00;
+            let link_local = (segments[0] & 0xFFC0) == 0xFE80;
+            !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+        }
+    }
+}
+
+/// Splits `host:port` or `[ipv6]:port`, taking the default port where none is given.
+pub fn split_address(address: &str) -> Option<(&str, u16)> {
+    let (host, port) = match address.strip_prefix('[') {
+        // An IPv6 address, bracketed so that its colons aren't taken for the port.
+        Some(bracketed) => match bracketed.split_once(']')? {
+            (host, "") => (host, DEFAULT_PORT),
+            (host, port) => (host, port.strip_prefix(':')?.parse().ok()?),
+        },
+        None => match address.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (address, DEFAULT_PORT),
+        },
+    };
+
+    if host.is_empty() {
+        None
+    } else {
+        Some((host, port))
+    }
+}
+
+fn write_packet(buf: &mut Vec<u8>, packet: &[u8]) {
+    write_var_int(buf, packet.len() as i32);
+    buf.extend_from_slice(packet);
+}
+
+fn write_string(buf: &mut Vec<u8>, string: &str) {
+    write_var_int(buf, string.len() as i32);
+    buf.extend_from_slice(string.as_bytes());
+}
+
+fn write_var_int(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+async fn read_var_int<R: AsyncRead + Unpin>(reader: &mut R) -> Result<i32, Error> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let byte = reader.read_u8().await?;
+        value |= ((byte & 0x7F) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(Error::Malformed("varint too long"))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid server address")]
+    InvalidAddress,
+    #[error("server address is not public")]
+    NotPublic,
+    #[error("server port is not allowed")]
+    PortNotAllowed,
+    #[error("server did not answer in time")]
+    Timeout,
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("malformed response: {0}")]
+    Malformed(&'static str),
+    #[error("malformed status: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn splits_addresses() {
+        assert_eq!(split_address("example.com"), Some(("example.com", DEFAULT_PORT)));
+        assert_eq!(split_address("example.com:25566"), Some(("example.com", 25566)));
+        assert_eq!(split_address("[::1]"), Some(("::1", DEFAULT_PORT)));
+        assert_eq!(split_address("[::1]:25566"), Some(("::1", 25566)));
+        assert_eq!(split_address("example.com:port"), None);
+        assert_eq!(split_address("[::1]25566"), None);
+        assert_eq!(split_address(":25566"), None);
+        assert_eq!(split_address(""), None);
+    }
+
+    #[tokio::test]
+    async fn var_ints_round_trip() {
+        for value in [0, 1, 127, 128, 25565, i32::MAX, -1, i32::MIN] {
+            let mut buf = Vec::new();
+            write_var_int(&mut buf, value);
+            assert!(buf.len() <= 5);
+            assert_eq!(read_var_int(&mut &buf[..]).await.unwrap(), value);
+        }
+
+        let too_long = [0x80; 6];
+        assert!(matches!(read_var_int(&mut &too_long[..]).await, Err(Error::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn pings_a_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            // the handshake and then the status request
+            for _ in 0..2 {
+                let length = read_var_int(&mut stream).await.unwrap() as usize;
+                stream.read_exact(&mut vec![0; length]).await.unwrap();
+            }
+
+            let status = serde_json::json!({
+                "version": { "name": "1.20.1", "protocol": 763 },
+                "players": {
+                    "max": 20,
+                    "online": 1,
+                    "sample": [{ "name": "Player", "id": "00000000-0000-0000-0000-000000000001" }],
+                },
+                "description": "A server",
+            });
+            let mut response = vec![0x00];
+            write_string(&mut response, &status.to_string());
+            let mut packet = Vec::new();
+            write_packet(&mut packet, &response);
+            stream.write_all(&packet).await.unwrap();
+        });
+
+        let status = ping(&address, false).await.unwrap();
+        assert!(status.is_online(Uuid::from_u128(1)));
+        assert!(!status.is_online(Uuid::from_u128(2)));
+        assert_eq!(status.description, "A server");
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in ["1.1.1.1", "2606:4700:4700::1111", "::ffff:1.1.1.1", "64:ff9b::101:101"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1", "10.0.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "198.18.0.1",
+            "0.0.0.0", "255.255.255.255", "::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn client_named_servers_must_be_public() {
+        let pinger = Pinger::new(&PingConfig { public_ports: vec![25566], ..PingConfig::default() });
+        assert!(matches!(pinger.public_status("example.com:22").await, Err(Error::PortNotAllowed)));
+        assert!(matches!(pinger.public_status("127.0.0.1").await, Err(Error::NotPublic)));
+        assert!(matches!(pinger.public_status("127.0.0.1:25566").await, Err(Error::NotPublic)));
+    }
+
+    #[test]
+    fn motd_drops_formatting() {
+        let status: ServerStatus = serde_json::from_value(serde_json::json!({
+            "description": { "text": "\u{a7}aA ", "extra": [{ "text": "server" }, "\u{a7}l!"] },
+        })).unwrap();
+        assert_eq!(status.motd(), "A server!");
+    }
+
+    #[test]
+    fn favicons_decode_across_lines() {
+        let status: ServerStatus = serde_json::from_value(serde_json::json!({
+            "favicon": "data:image/png;base64,iVBO\nRw==",
+        })).unwrap();
+        assert_eq!(status.favicon_png(), Some(vec![0x89, b'P', b'N', b'G']));
+
+        let status: ServerStatus = serde_json::from_value(serde_json::json!({ "favicon": "iVBORw==" })).unwrap();
+        assert_eq!(status.favicon_png(), None);
+    }
+}
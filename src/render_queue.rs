@@ -0,0 +1,250 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+/// Whose renders go first when every slot is taken.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Priority {
+    Normal,
+    /// Clients the operator vouches for, such as their own game servers.
+    High,
+}
+
+impl Priority {
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RenderQueueConfig {
+    /// How many renders may run at once, defaulting to one per CPU.
+    pub slots: Option<usize>,
+    /// How many renders may wait for a slot before more are turned away with a 503.
+    pub max_queued: usize,
+    /// Clients whose renders go ahead of everyone else's, and which may push others out of a
+    /// full queue.
+    pub priority_ips: Vec<IpNet>,
+}
+
+impl Default for RenderQueueConfig {
+    fn default() -> Self {
+        RenderQueueConfig {
+            slots: None,
+            max_queued: 256,
+            priority_ips: Vec::new(),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("render queue is full")]
+pub struct QueueFull;
+
+/// Hands out a fixed number of render slots, queueing renders for them by priority and then in
+/// order of arrival. Cache hits never render, so never queue.
+pub struct RenderQueue {
+    slots: usize,
+    max_queued: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    /// Waiting renders by priority, each told through their sender once a slot is theirs.
+    waiting: [VecDeque<oneshot::Sender<()>>; 2],
+}
+
+impl State {
+    fn queued(&self) -> usize {
+        self.waiting.iter().map(VecDeque::len).sum()
+    }
+}
+
+impl RenderQueue {
+    pub fn new(config: &RenderQueueConfig) -> RenderQueue {
+        let slots = config.slots
+            .or_else(|| std::thread::available_parallelism().ok().map(usize::from))
+            .unwrap_or(1);
+        RenderQueue {
+            slots,
+            max_queued: config.max_queued,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    #[inline]
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+
+    /// How many renders hold a slot and how many are waiting for one.
+    pub fn occupancy(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.running, state.queued())
+    }
+
+    /// Waits for a slot to render in, or fails right away if the queue is full. A render of high
+    /// priority facing a full queue pushes out the latest render of normal priority instead.
+    pub async fn acquire(&self, priority: Priority) -> Result<RenderSlot<'_>, QueueFull> {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < self.slots && state.queued() == 0 {
+                state.running += 1;
+                return Ok(RenderSlot { queue: self });
+            }
+
+            if state.queued() >= self.max_queued {
+                // Renders abandoned while waiting hold no place in the queue.
+                for waiting in &mut state.waiting {
+                    waiting.retain(|sender| !sender.is_closed());
+                }
+            }
+            if state.queued() >= self.max_queued {
+                let pushed_out = priority == Priority::High && state.waiting[Priority::Normal.index()].pop_back().is_some();
+                if !pushed_out {
+                    return Err(QueueFull);
+                }
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            state.waiting[priority.index()].push_back(sender);
+            receiver
+        };
+
+        let mut waiting = Waiting { queue: self, receiver: Some(receiver) };
+        let granted = waiting.receiver.as_mut().unwrap().await;
+        waiting.receiver = None;
+        match granted {
+            Ok(()) => Ok(RenderSlot { queue: self }),
+            Err(_) => Err(QueueFull),
+        }
+    }
+
+    /// Passes a freed slot on to the first render still waiting for one.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        for priority in [Priority::High, Priority::Normal] {
+            while let Some(sender) = state.waiting[priority.index()].pop_front() {
+                if sender.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        state.running -= 1;
+    }
+}
+
+/// A slot to render in, given up on drop.
+pub struct RenderSlot<'a> {
+    queue: &'a RenderQueue,
+}
+
+impl Drop for RenderSlot<'_> {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+/// A render waiting in the queue, which gives up its slot if it is abandoned just as one is
+/// handed to it.
+struct Waiting<'a> {
+    queue: &'a RenderQueue,
+    receiver: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Some(mut receiver) = self.receiver.take() {
+            receiver.close();
+            if receiver.try_recv().is_ok() {
+                self.queue.release();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::task::JoinHandle;
+
+    use super::*;
+
+    fn queue(slots: usize, max_queued: usize) -> Arc<RenderQueue> {
+        Arc::new(RenderQueue::new(&RenderQueueConfig { slots: Some(slots), max_queued, ..RenderQueueConfig::default() }))
+    }
+
+    /// Starts a render that notes its priority once it has a slot, giving it time to be queued.
+    async fn enqueue(queue: &Arc<RenderQueue>, priority: Priority, done: Arc<Mutex<Vec<Priority>>>) -> JoinHandle<Result<(), QueueFull>> {
+        let render = {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let _slot = queue.acquire(priority).await?;
+                done.lock().unwrap().push(priority);
+                Ok(())
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        render
+    }
+
+    #[tokio::test]
+    async fn renders_wait_for_a_slot_by_priority() {
+        let queue = queue(1, 4);
+        let done = Arc::new(Mutex::new(Vec::new()));
+        let slot = queue.acquire(Priority::Normal).await.unwrap();
+        assert_eq!(queue.occupancy(), (1, 0));
+
+        let normal = enqueue(&queue, Priority::Normal, done.clone()).await;
+        let high = enqueue(&queue, Priority::High, done.clone()).await;
+        assert_eq!(queue.occupancy(), (1, 2));
+
+        drop(slot);
+        for render in [normal, high] {
+            render.await.unwrap().unwrap();
+        }
+        assert_eq!(*done.lock().unwrap(), [Priority::High, Priority::Normal]);
+        assert_eq!(queue.occupancy(), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn high_priority_pushes_normal_out_of_a_full_queue() {
+        let queue = queue(1, 1);
+        let done = Arc::new(Mutex::new(Vec::new()));
+        let slot = queue.acquire(Priority::Normal).await.unwrap();
+
+        let normal = enqueue(&queue, Priority::Normal, done.clone()).await;
+        assert!(queue.acquire(Priority::Normal).await.is_err());
+
+        let high = enqueue(&queue, Priority::High, done.clone()).await;
+        assert!(normal.await.unwrap().is_err());
+
+        drop(slot);
+        high.await.unwrap().unwrap();
+        assert_eq!(*done.lock().unwrap(), [Priority::High]);
+    }
+
+    #[tokio::test]
+    async fn abandoned_renders_give_up_their_place() {
+        let queue = queue(1, 1);
+        let slot = queue.acquire(Priority::Normal).await.unwrap();
+
+        let abandoned = tokio::time::timeout(Duration::from_millis(10), queue.acquire(Priority::Normal)).await;
+        assert!(abandoned.is_err());
+        // the abandoned render no longer counts against the queue
+        let waiting = enqueue(&queue, Priority::Normal, Arc::default()).await;
+
+        drop(slot);
+        waiting.await.unwrap().unwrap();
+        assert_eq!(queue.occupancy(), (0, 0));
+    }
+}
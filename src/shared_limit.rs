@@ -0,0 +1,66 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use redis::aio::ConnectionManager;
+use redis::{Client, RedisResult, Script};
+use tokio::sync::OnceCell;
+
+const KEY_PREFIX: &str = "player-face-api:rate-limit:";
+
+/// Connecting is tried once per request rather than retried with backoff, so that requests fall
+/// back to local limits straight away while Redis is unreachable.
+const CONNECTION_RETRIES: usize = 0;
+
+/// GCRA over the theoretical arrival time kept at `KEYS[1]`, in microseconds. A request that
+/// would have to wait longer than `max_wait` is refused with -1 and not counted. Otherwise it is
+/// counted, and how long it must wait returned.
+const GCRA: &str = r"
+local interval = tonumber(ARGV[1])
+local burst = tonumber(ARGV[2])
+local max_wait = tonumber(ARGV[3])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000000 + tonumber(time[2])
+local tat = math.max(tonumber(redis.call('GET', KEYS[1])) or now, now)
+local wait = tat + interval - now - burst * interval
+if wait > max_wait then
+    return -1
+end
+redis.call('SET', KEYS[1], tat + interval, 'PX', math.ceil((tat + interval - now) / 1000))
+return math.max(wait, 0)
+";
+
+/// A rate limiter keeping its state in Redis, so that every replica pointed at the same server
+/// shares each client's quota rather than granting it once per replica.
+pub struct SharedRateLimiter {
+    client: Client,
+    connection: OnceCell<ConnectionManager>,
+    script: Script,
+}
+
+impl SharedRateLimiter {
+    pub fn new(url: &str) -> RedisResult<SharedRateLimiter> {
+        Ok(SharedRateLimiter {
+            client: Client::open(url)?,
+            connection: OnceCell::new(),
+            script: Script::new(GCRA),
+        })
+    }
+
+    /// Counts a request from `client`, such as its address, against a quota of `requests_per_minute` with bursts of up to
+    /// `burst`, returning how long it must wait for its turn, or `None` if that would be longer
+    /// than `max_wait` and the request should be refused.
+    pub async fn acquire(&self, client: &str, requests_per_minute: u32, burst: u32, max_wait: Duration) -> RedisResult<Option<Duration>> {
+        let connect = || self.client.get_connection_manager_with_backoff(2, 100, CONNECTION_RETRIES);
+        let mut connection = self.connection.get_or_try_init(connect).await?.clone();
+
+        let interval = 60_000_000 / u64::from(requests_per_minute);
+        let wait: i64 = self.script.key(format!("{}{}", KEY_PREFIX, client))
+            .arg(interval)
+            .arg(burst)
+            .arg(max_wait.as_micros() as u64)
+            .invoke_async(&mut connection)
+            .await?;
+
+        Ok(u64::try_from(wait).ok().map(Duration::from_micros))
+    }
+}
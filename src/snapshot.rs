@@ -0,0 +1,84 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::minecraft::PlayerProfile;
+
+/// Cache contents persisted across restarts, so that a fresh deploy doesn't have to look every
+/// player up again.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Snapshot {
+    /// Seconds since the Unix epoch at which the snapshot was taken.
+    pub saved_at: u64,
+    pub profiles: Vec<(Uuid, Option<PlayerProfile>)>,
+    pub raw_faces: Vec<(Uuid, RawFace)>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RawFace {
+    width: u32,
+    height: u32,
+    /// Base64-encoded RGB pixels.
+    pixels: String,
+}
+
+impl From<&RgbImage> for RawFace {
+    fn from(image: &RgbImage) -> Self {
+        RawFace {
+            width: image.width(),
+            height: image.height(),
+            pixels: base64::encode(image.as_raw()),
+        }
+    }
+}
+
+impl RawFace {
+    pub fn to_image(&self) -> Option<RgbImage> {
+        let pixels = base64::decode(&self.pixels).ok()?;
+        RgbImage::from_raw(self.width, self.height, pixels)
+    }
+}
+
+impl Snapshot {
+    #[inline]
+    pub fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+
+    /// Seconds since the snapshot was taken.
+    #[inline]
+    pub fn age(&self) -> u64 {
+        Snapshot::now().saturating_sub(self.saved_at)
+    }
+
+    /// Loads the snapshot at `path`, unless it is missing, malformed, or older than `max_age`.
+    pub fn load(path: &Path, max_age: Duration) -> Option<Snapshot> {
+        let file = File::open(path).ok()?;
+        let snapshot: Snapshot = match serde_json::from_reader(BufReader::new(file)) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                log::warn!("failed to parse cache snapshot: {:?}", err);
+                return None;
+            }
+        };
+
+        let age = snapshot.age();
+        if age > max_age.as_secs() {
+            log::info!("discarding cache snapshot from {}s ago", age);
+            return None;
+        }
+
+        Some(snapshot)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The placeholder in a profile endpoint replaced by the player's UUID.
+pub const UUID_PLACEHOLDER: &str = "{uuid}";
+/// The placeholder in a texture endpoint replaced by the texture's hash.
+pub const HASH_PLACEHOLDER: &str = "{hash}";
+
+const MOJANG: &str = "mojang";
+
+/// A service serving player profiles and skin textures in the same format as Mojang, such as a
+/// mirror or a network's own skin server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SourceConfig {
+    /// Where to fetch a profile, with `{uuid}` standing for the player's UUID.
+    pub profile_endpoint: String,
+    /// Where to fetch a texture by hash, with `{hash}` standing for the hash.
+    pub texture_endpoint: String,
+    pub timeout_secs: u64,
+    /// Sources with a higher priority are asked first.
+    pub priority: i32,
+    /// A directory that a co-located server dumps the profiles and skins of its players into,
+    /// read in place of the endpoints so that no request leaves the machine. It holds
+    /// `profiles/{uuid}.json`, each a profile as the session server serves it, and
+    /// `textures/{hash}.png`, which the texture URLs in those profiles are resolved to by hash.
+    pub directory: Option<PathBuf>,
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        SourceConfig {
+            profile_endpoint: "https://sessionserver.mojang.com/session/minecraft/profile/{uuid}".to_owned(),
+            texture_endpoint: "https://textures.minecraft.net/texture/{hash}".to_owned(),
+            timeout_secs: 10,
+            priority: 0,
+            directory: None,
+        }
+    }
+}
+
+pub fn default_sources() -> BTreeMap<String, SourceConfig> {
+    let mut sources = BTreeMap::new();
+    sources.insert(MOJANG.to_owned(), SourceConfig::default());
+    sources
+}
+
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    profile_endpoint: String,
+    texture_endpoint: String,
+    pub timeout: Duration,
+    pub directory: Option<PathBuf>,
+}
+
+impl Source {
+    pub fn new(name: String, config: &SourceConfig) -> Source {
+        Source {
+            name,
+            profile_endpoint: config.profile_endpoint.clone(),
+            texture_endpoint: config.texture_endpoint.clone(),
+            timeout: Duration::from_secs(config.timeout_secs),
+            directory: config.directory.clone(),
+        }
+    }
+
+    /// Every configured source, in the order they should be asked.
+    pub fn all(configs: &BTreeMap<String, SourceConfig>) -> Vec<Source> {
+        let mut configs: Vec<_> = configs.iter().collect();
+        configs.sort_by_key(|(_, config)| Reverse(config.priority));
+
+        configs.into_iter()
+            .map(|(name, config)| Source::new(name.clone(), config))
+            .collect()
+    }
+
+    #[inline]
+    pub fn profile_url(&self, uuid: Uuid) -> String {
+        self.profile_endpoint.replace(UUID_PLACEHOLDER, &uuid.to_string())
+    }
+
+    #[inline]
+    pub fn texture_url(&self, hash: &str) -> String {
+        self.texture_endpoint.replace(HASH_PLACEHOLDER, hash)
+    }
+
+    /// Whether `url` points into this source's texture endpoint. Profiles give texture URLs
+    /// as `http` even where the endpoint is `https`, so the scheme is not compared.
+    pub fn serves_texture(&self, url: &str) -> bool {
+        let prefix = self.texture_endpoint.split(HASH_PLACEHOLDER).next().unwrap_or_default();
+        let prefix = without_scheme(prefix);
+        !prefix.is_empty() && without_scheme(url).starts_with(prefix)
+    }
+}
+
+#[inline]
+fn without_scheme(url: &str) -> &str {
+    url.split_once("://").map_or(url, |(_, rest)| rest)
+}
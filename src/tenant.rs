@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+
+use governor::clock::DefaultClock;
+use governor::state::{InMemoryState, NotKeyed};
+use governor::{Jitter, Quota, RateLimiter};
+use serde::{Deserialize, Serialize};
+use warp::http::{HeaderMap, Uri};
+
+use crate::options::OutputFormat;
+use crate::usage::{Usage, UsageCounts};
+
+/// The header services send their tenant's API key in.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The features tenants may be restricted to, named after the routes that serve them.
+pub const FEATURES: [&str; 21] = [
+    "face", "head", "compass", "views", "preview", "chibi", "diff", "color", "banner", "cape", "card",
+    "layout", "skin", "info", "watch", "names", "uuid", "profile", "server", "metrics", "graphql",
+];
+
+const RATE_LIMIT_JITTER: Duration = Duration::from_millis(50);
+
+/// A site or service given limits and features of its own, rather than those of anonymous
+/// clients.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TenantConfig {
+    /// Keys the tenant's services send in `X-Api-Key`. A request with a key that belongs to no
+    /// tenant is rejected.
+    pub api_keys: Vec<String>,
+    /// Hosts of the sites the tenant embeds renders in, matched against the `Origin` or else
+    /// the `Referer` that browsers send along. Either is easily forged, so a tenant known only by
+    /// origin should be given no more than its visitors need.
+    pub origins: Vec<String>,
+    /// Requests per minute across every client of the tenant, which replaces each client's own
+    /// quota.
+    pub requests_per_minute: u32,
+    /// How many requests the tenant may make at once. Defaults to `requests_per_minute`.
+    pub rate_limit_burst: Option<u32>,
+    /// The features the tenant may use, out of [`FEATURES`], or every feature if unset.
+    pub features: Option<BTreeSet<String>>,
+    /// The output formats the tenant may ask for, or every format if unset.
+    pub formats: Option<Vec<OutputFormat>>,
+}
+
+impl Default for TenantConfig {
+    fn default() -> Self {
+        TenantConfig {
+            api_keys: Vec::new(),
+            origins: Vec::new(),
+            requests_per_minute: 600,
+            rate_limit_burst: None,
+            features: None,
+            formats: None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown api key")]
+pub struct UnknownApiKey;
+
+pub struct Tenant {
+    name: Arc<str>,
+    requests_per_minute: u32,
+    burst: u32,
+    rate_limiter: RateLimiter<NotKeyed, InMemoryState, DefaultClock>,
+    features: Option<BTreeSet<String>>,
+    formats: Option<Vec<OutputFormat>>,
+}
+
+impl Tenant {
+    fn new(name: &str, config: &TenantConfig) -> Tenant {
+        let burst = config.rate_limit_burst.unwrap_or(config.requests_per_minute);
+        let quota = Quota::per_minute(NonZeroU32::new(config.requests_per_minute).unwrap())
+            .allow_burst(NonZeroU32::new(burst).unwrap());
+        Tenant {
+            name: Arc::from(name),
+            requests_per_minute: config.requests_per_minute,
+            burst,
+            rate_limiter: RateLimiter::direct(quota),
+            features: config.features.clone(),
+            formats: config.formats.clone(),
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn requests_per_minute(&self) -> u32 {
+        self.requests_per_minute
+    }
+
+    #[inline]
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    /// Whether the tenant may use `feature`, asking for `format` if any.
+    pub fn allows(&self, feature: &str, format: Option<OutputFormat>) -> bool {
+        let feature = self.features.as_ref().is_none_or(|features| features.contains(feature));
+        let format = match (&self.formats, format) {
+            (Some(formats), Some(format)) => formats.contains(&format),
+            _ => true,
+        };
+        feature && format
+    }
+
+    /// Waits up to `max_wait` for the tenant to have a token to spend, returning whether it got
+    /// one.
+    pub async fn acquire_locally(&self, max_wait: Duration) -> bool {
+        if self.rate_limiter.check().is_ok() {
+            return true;
+        }
+        if max_wait.is_zero() {
+            return false;
+        }
+
+        let ready = self.rate_limiter.until_ready_with_jitter(Jitter::up_to(RATE_LIMIT_JITTER));
+        tokio::time::timeout(max_wait, ready).await.is_ok()
+    }
+}
+
+/// Every configured tenant, by the API keys and origins they are known by.
+pub struct Tenants {
+    by_api_key: HashMap<String, Arc<Tenant>>,
+    by_origin: HashMap<String, Arc<Tenant>>,
+    usage: Usage<Arc<str>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TenantUsage {
+    pub tenant: String,
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+}
+
+impl Tenants {
+    pub fn new(configs: &BTreeMap<String, TenantConfig>) -> Tenants {
+        let mut by_api_key = HashMap::new();
+        let mut by_origin = HashMap::new();
+        for (name, config) in configs {
+            let tenant = Arc::new(Tenant::new(name, config));
+            for key in &config.api_keys {
+                by_api_key.insert(key.clone(), tenant.clone());
+            }
+            for origin in &config.origins {
+                by_origin.insert(origin.to_ascii_lowercase(), tenant.clone());
+            }
+        }
+
+        Tenants {
+            by_api_key,
+            by_origin,
+            usage: Usage::new(),
+        }
+    }
+
+    /// The tenant a request with `headers` was made on behalf of, if any. The API key takes
+    /// precedence over the origin, since only the key is hard to forge.
+    pub fn identify(&self, headers: &HeaderMap) -> Result<Option<Arc<Tenant>>, UnknownApiKey> {
+        // Keys mean nothing unless tenants are configured, so aren't refused then either.
+        if let Some(key) = headers.get(API_KEY_HEADER).filter(|_| !self.by_api_key.is_empty()) {
+            let key = key.to_str().map_err(|_| UnknownApiKey)?;
+            return self.by_api_key.get(key).cloned().map(Some).ok_or(UnknownApiKey);
+        }
+
+        let origin = headers.get("origin")
+            .and_then(|origin| origin_host(origin.to_str().ok()?))
+            .or_else(|| origin_host(headers.get("referer")?.to_str().ok()?));
+        Ok(origin.and_then(|host| self.by_origin.get(&host).cloned()))
+    }
+
+    pub fn record_usage(&self, tenant: &Tenant, bytes: u64) {
+        self.usage.record(tenant.name.clone(), bytes);
+    }
+
+    /// Every tenant that made requests over the last hour, busiest first.
+    pub fn usage(&self) -> Vec<TenantUsage> {
+        self.usage.top(usize::MAX).into_iter()
+            .map(|(tenant, counts)| TenantUsage { tenant: tenant.to_string(), counts })
+            .collect()
+    }
+}
+
+fn origin_host(origin: &str) -> Option<String> {
+    let uri: Uri = origin.parse().ok()?;
+    uri.host().map(str::to_ascii_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::HeaderValue;
+
+    use super::*;
+
+    fn tenants() -> Tenants {
+        let mut configs = BTreeMap::new();
+        configs.insert("service".to_owned(), TenantConfig {
+            api_keys: vec!["secret".to_owned()],
+            requests_per_minute: 60,
+            rate_limit_burst: Some(1),
+            features: Some(vec!["face".to_owned()].into_iter().collect()),
+            formats: Some(vec![OutputFormat::Png]),
+            ..TenantConfig::default()
+        });
+        configs.insert("site".to_owned(), TenantConfig {
+            origins: vec!["Example.com".to_owned()],
+            ..TenantConfig::default()
+        });
+        Tenants::new(&configs)
+    }
+
+    fn identify(tenants: &Tenants, headers: &[(&'static str, &'static str)]) -> Result<Option<String>, UnknownApiKey> {
+        let mut map = HeaderMap::new();
+        for &(name, value) in headers {
+            map.insert(name, HeaderValue::from_static(value));
+        }
+        tenants.identify(&map).map(|tenant| tenant.map(|tenant| tenant.name().to_owned()))
+    }
+
+    #[test]
+    fn identifies_tenants_by_key_then_origin() {
+        let tenants = tenants();
+        assert_eq!(identify(&tenants, &[(API_KEY_HEADER, "secret")]).unwrap().as_deref(), Some("service"));
+        assert!(identify(&tenants, &[(API_KEY_HEADER, "guess")]).is_err());
+        assert!(identify(&tenants, &[(API_KEY_HEADER, "guess"), ("origin", "https://example.com")]).is_err());
+
+        assert_eq!(identify(&tenants, &[("origin", "https://EXAMPLE.com:8443")]).unwrap().as_deref(), Some("site"));
+        assert_eq!(identify(&tenants, &[("referer", "https://example.com/page")]).unwrap().as_deref(), Some("site"));
+        assert_eq!(identify(&tenants, &[("origin", "https://example.org")]).unwrap(), None);
+        assert_eq!(identify(&tenants, &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn keys_are_ignored_without_keyed_tenants() {
+        let tenants = Tenants::new(&BTreeMap::new());
+        assert_eq!(identify(&tenants, &[(API_KEY_HEADER, "secret")]).unwrap(), None);
+    }
+
+    #[test]
+    fn tenants_are_held_to_their_features_and_formats() {
+        let tenants = tenants();
+        let service = tenants.by_api_key["secret"].clone();
+        assert!(service.allows("face", None));
+        assert!(service.allows("face", Some(OutputFormat::Png)));
+        assert!(!service.allows("face", Some(OutputFormat::Svg)));
+        assert!(!service.allows("head", None));
+
+        let site = tenants.by_origin["example.com"].clone();
+        assert!(site.allows("head", Some(OutputFormat::Svg)));
+    }
+
+    #[tokio::test]
+    async fn tenants_share_one_quota() {
+        let tenants = tenants();
+        let service = tenants.by_api_key["secret"].clone();
+        assert!(service.acquire_locally(Duration::ZERO).await);
+        assert!(!service.acquire_locally(Duration::ZERO).await);
+
+        tenants.record_usage(&service, 100);
+        tenants.record_usage(&service, 50);
+        let usage = tenants.usage();
+        assert_eq!(usage.len(), 1);
+        assert_eq!((usage[0].tenant.as_str(), usage[0].counts.requests_1m, usage[0].counts.bytes_1m), ("service", 2, 150));
+    }
+}
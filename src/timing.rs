@@ -0,0 +1,151 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use uuid::Uuid;
+
+tokio::task_local! {
+    static TIMELINE: Arc<Timeline>;
+}
+
+thread_local! {
+    /// The timeline of the request a blocking task is working for, which task locals don't reach.
+    static BLOCKING_TIMELINE: RefCell<Option<Arc<Timeline>>> = const { RefCell::new(None) };
+}
+
+/// The phases a request has passed through and when each began, so that a request which
+/// overruns its deadline can report where the time went. Also carries what logs written while
+/// handling the request are tagged with.
+pub struct Timeline {
+    request_id: String,
+    /// The player the request is for, once known.
+    player: Mutex<Option<Uuid>>,
+    start: Instant,
+    started_at: SystemTime,
+    phases: Mutex<Vec<(&'static str, Instant)>>,
+}
+
+impl Timeline {
+    pub fn new(request_id: String) -> Arc<Timeline> {
+        Arc::new(Timeline {
+            request_id,
+            player: Mutex::new(None),
+            start: Instant::now(),
+            started_at: SystemTime::now(),
+            phases: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Runs `future` with this timeline recording the phases it enters.
+    pub async fn scope<F: Future>(self: Arc<Self>, future: F) -> F::Output {
+        TIMELINE.scope(self, future).await
+    }
+
+    #[inline]
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    #[inline]
+    pub fn player(&self) -> Option<Uuid> {
+        *self.player.lock().unwrap()
+    }
+
+    #[inline]
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn enter(&self, phase: &'static str) {
+        let mut phases = self.phases.lock().unwrap();
+        // Work done in pieces, such as encoding several images, counts as one phase.
+        if phases.last().map(|&(last, _)| last) != Some(phase) {
+            phases.push((phase, Instant::now()));
+        }
+    }
+
+    /// Every phase so far with when it began and ended, relative to the start of the request.
+    /// Time before the first phase is spent in the handler itself.
+    pub fn phases(&self) -> Vec<(&'static str, Duration, Duration)> {
+        let phases = self.phases.lock().unwrap();
+
+        let mut spans = Vec::with_capacity(phases.len() + 1);
+        let mut phase = "handler";
+        let mut start = Duration::ZERO;
+        for &(next, next_start) in phases.iter() {
+            let end = next_start.duration_since(self.start);
+            spans.push((phase, start, end));
+            phase = next;
+            start = end;
+        }
+        spans.push((phase, start, self.start.elapsed()));
+
+        spans
+    }
+
+    /// The total time spent in each phase, however many times it was entered.
+    pub fn breakdown(&self) -> BTreeMap<&'static str, Duration> {
+        let mut breakdown = BTreeMap::new();
+        for (phase, start, end) in self.phases() {
+            *breakdown.entry(phase).or_default() += end - start;
+        }
+        breakdown
+    }
+}
+
+/// Marks the start of `phase` in the current request, if any.
+pub fn enter(phase: &'static str) {
+    if TIMELINE.try_with(|timeline| timeline.enter(phase)).is_err() {
+        BLOCKING_TIMELINE.with(|timeline| {
+            if let Some(timeline) = &*timeline.borrow() {
+                timeline.enter(phase);
+            }
+        });
+    }
+}
+
+/// Notes that the current request is for `uuid`, if there is one. Requests comparing players
+/// keep the first.
+pub fn enter_player(uuid: Uuid) {
+    if let Some(timeline) = context() {
+        timeline.player.lock().unwrap().get_or_insert(uuid);
+    }
+}
+
+/// The timeline of the request being handled, whether on its own task or in blocking work.
+pub fn context() -> Option<Arc<Timeline>> {
+    current().or_else(|| BLOCKING_TIMELINE.with(|timeline| timeline.borrow().clone()))
+}
+
+/// The timeline of the current request, to carry into blocking work through [`blocking`].
+pub fn current() -> Option<Arc<Timeline>> {
+    TIMELINE.try_with(|timeline| timeline.clone()).ok()
+}
+
+/// Runs `f` on this thread with phases it enters recorded to `timeline`.
+pub fn blocking<T>(timeline: Option<Arc<Timeline>>, f: impl FnOnce() -> T) -> T {
+    let previous = BLOCKING_TIMELINE.with(|current| current.replace(timeline));
+    let result = f();
+    BLOCKING_TIMELINE.with(|current| current.replace(previous));
+    result
+}
+
+impl fmt::Display for Timeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (phase, start, end)) in self.phases().into_iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} {:.3}s", phase, (end - start).as_secs_f32())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use warp::http::{Method, StatusCode};
+
+use crate::timing::Timeline;
+use crate::web::API_VERSION;
+
+/// Spans waiting to be exported beyond this are dropped rather than held in memory.
+const QUEUE_CAPACITY: usize = 4096;
+const MAX_BATCH: usize = 512;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_SERVER: u8 = 2;
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// Export of request traces to an OpenTelemetry collector over OTLP/HTTP with JSON encoding.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TracingConfig {
+    /// The collector's traces endpoint.
+    pub endpoint: String,
+    pub service_name: String,
+    /// The fraction of requests to trace, unless the caller already decided through a
+    /// `traceparent` header.
+    pub sample_ratio: f64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            endpoint: "http://localhost:4318/v1/traces".to_owned(),
+            service_name: "player-face-api".to_owned(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+/// Turns request timelines into spans, one for the request and one for each phase within it,
+/// and exports them in batches from a background task.
+pub struct Tracer {
+    sample_ratio: f64,
+    spans: mpsc::Sender<Value>,
+}
+
+/// The request details recorded on its span.
+pub struct Request<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub addr: SocketAddr,
+    /// The caller's `traceparent` header, to continue its trace.
+    pub traceparent: Option<&'a str>,
+}
+
+impl Tracer {
+    /// Starts the exporter, which must happen within the runtime.
+    pub fn start(config: &TracingConfig) -> Tracer {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(export(config.clone(), receiver));
+
+        Tracer {
+            sample_ratio: config.sample_ratio,
+            spans: sender,
+        }
+    }
+
+    pub fn record(&self, request: Request, status: StatusCode, timeline: &Timeline) {
+        let parent = request.traceparent.and_then(TraceParent::parse);
+        let sampled = match &parent {
+            Some(parent) => parent.sampled,
+            None => rand::thread_rng().gen_bool(self.sample_ratio),
+        };
+        if !sampled {
+            return;
+        }
+
+        let trace_id = parent.as_ref().map(|parent| parent.trace_id.clone()).unwrap_or_else(|| random_id(16));
+        let span_id = random_id(8);
+        let started_at = timeline.started_at();
+        let phases = timeline.phases();
+        let end = phases.last().map(|&(_, _, end)| end).unwrap_or_default();
+
+        let mut request_span = json!({
+            "traceId": trace_id,
+            "spanId": span_id,
+            "name": format!("{} {}", request.method, route(request.path)),
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": unix_nanos(started_at, Duration::ZERO),
+            "endTimeUnixNano": unix_nanos(started_at, end),
+            "attributes": [
+                attribute("http.request.method", json!({ "stringValue": request.method.as_str() })),
+                attribute("url.path", json!({ "stringValue": request.path })),
+                attribute("client.address", json!({ "stringValue": request.addr.ip().to_string() })),
+                attribute("http.response.status_code", json!({ "intValue": status.as_u16().to_string() })),
+            ],
+        });
+        if let Some(parent) = &parent {
+            request_span["parentSpanId"] = json!(parent.span_id);
+        }
+        if status.is_server_error() {
+            request_span["status"] = json!({ "code": STATUS_CODE_ERROR });
+        }
+
+        let phase_spans = phases.into_iter().map(|(phase, start, end)| json!({
+            "traceId": trace_id,
+            "spanId": random_id(8),
+            "parentSpanId": span_id,
+            "name": phase,
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": unix_nanos(started_at, start),
+            "endTimeUnixNano": unix_nanos(started_at, end),
+        }));
+
+        for span in std::iter::once(request_span).chain(phase_spans) {
+            if self.spans.try_send(span).is_err() {
+                log::debug!("dropping trace span, export queue is full");
+                return;
+            }
+        }
+    }
+}
+
+async fn export(config: TracingConfig, mut spans: mpsc::Receiver<Value>) {
+    let client = match reqwest::Client::builder().timeout(EXPORT_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => {
+            log::error!("failed to create trace exporter: {:?}", err);
+            return;
+        }
+    };
+
+    let resource = json!({
+        "attributes": [attribute("service.name", json!({ "stringValue": config.service_name }))],
+    });
+
+    let mut batch = Vec::new();
+    let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+    loop {
+        let closed = tokio::select! {
+            span = spans.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < MAX_BATCH {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = interval.tick() => false,
+        };
+
+        if !batch.is_empty() {
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": resource,
+                    "scopeSpans": [{
+                        "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                        "spans": std::mem::take(&mut batch),
+                    }],
+                }],
+            });
+
+            let result = client.post(&config.endpoint).json(&body).send().await
+                .and_then(|response| response.error_for_status());
+            if let Err(err) = result {
+                log::warn!("failed to export traces to {}: {}", config.endpoint, err);
+            }
+        }
+
+        if closed {
+            return;
+        }
+    }
+}
+
+/// A W3C `traceparent` header: `00-<trace id>-<parent span id>-<flags>`.
+struct TraceParent {
+    trace_id: String,
+    span_id: String,
+    sampled: bool,
+}
+
+impl TraceParent {
+    fn parse(header: &str) -> Option<TraceParent> {
+        let mut parts = header.trim().split('-');
+        let (version, trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+
+        let is_id = |id: &str, len: usize| {
+            id.len() == len && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) && id.bytes().any(|b| b != b'0')
+        };
+        if version != "00" || !is_id(trace_id, 32) || !is_id(span_id, 16) {
+            return None;
+        }
+
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(TraceParent {
+            trace_id: trace_id.to_owned(),
+            span_id: span_id.to_owned(),
+            sampled: flags & 1 == 1,
+        })
+    }
+}
+
+/// Names request spans by their first path segment, since full paths hold UUIDs and would make
+/// every span name unique.
+fn route(path: &str) -> String {
+    let segment = path.split('/')
+        .find(|segment| !segment.is_empty() && *segment != API_VERSION)
+        .unwrap_or_default();
+    format!("/{}", segment)
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+pub(crate) fn random_id(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bytes).map(|_| format!("{:02x}", rng.gen::<u8>())).collect()
+}
+
+/// OTLP's JSON encoding carries 64-bit integers as strings.
+fn unix_nanos(started_at: SystemTime, offset: Duration) -> String {
+    let time = started_at + offset;
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
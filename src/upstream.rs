@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use governor::{Quota, RateLimiter};
+use governor::clock::DefaultClock;
+use governor::state::keyed::DashMapStateStore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+type PlayerRateLimiter = RateLimiter<Uuid, DashMapStateStore<Uuid>, DefaultClock>;
+
+/// The Mojang hosts we make requests to, which are rate limited independently.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Upstream {
+    SessionServer,
+    Textures,
+    Api,
+}
+
+impl Upstream {
+    pub const ALL: [Upstream; 3] = [Upstream::SessionServer, Upstream::Textures, Upstream::Api];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Upstream::SessionServer => "session_server",
+            Upstream::Textures => "textures",
+            Upstream::Api => "api",
+        }
+    }
+
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Ceilings on requests per upstream within the sliding window. Hosts without a ceiling are
+/// only tracked.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BudgetConfig {
+    pub window_secs: u64,
+    pub session_server: Option<u32>,
+    pub textures: Option<u32>,
+    pub api: Option<u32>,
+    /// Ceiling on profile requests for any one player per minute, so that a single player
+    /// requested from everywhere at once can't spend the budget of everyone else.
+    pub per_player_per_minute: Option<u32>,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        BudgetConfig {
+            window_secs: 600,
+            session_server: None,
+            textures: None,
+            api: None,
+            per_player_per_minute: Some(10),
+        }
+    }
+}
+
+/// Whether Mojang has been answering our requests, for readiness checks.
+#[derive(Default)]
+pub struct Reachability {
+    failing_since: Mutex<Option<Instant>>,
+}
+
+impl Reachability {
+    pub fn record_success(&self) {
+        *self.failing_since.lock().unwrap() = None;
+    }
+
+    pub fn record_failure(&self) {
+        self.failing_since.lock().unwrap().get_or_insert_with(Instant::now);
+    }
+
+    /// Whether requests have not been failing continuously for at least `window`.
+    pub fn is_reachable(&self, window: Duration) -> bool {
+        self.failing_since.lock().unwrap().is_none_or(|since| since.elapsed() < window)
+    }
+}
+
+/// Tracks outbound requests to each upstream in a sliding window, refusing requests that would
+/// exceed the configured ceiling so that we throttle ourselves before Mojang does.
+pub struct Budget {
+    window: Duration,
+    limits: [Option<u32>; 3],
+    recent: [Mutex<VecDeque<Instant>>; 3],
+    requests: [AtomicU64; 3],
+    throttled: [AtomicU64; 3],
+    players: Option<PlayerRateLimiter>,
+    players_throttled: AtomicU64,
+}
+
+impl Budget {
+    pub fn new(config: &BudgetConfig) -> Budget {
+        Budget {
+            window: Duration::from_secs(config.window_secs),
+            limits: [config.session_server, config.textures, config.api],
+            recent: Default::default(),
+            requests: Default::default(),
+            throttled: Default::default(),
+            players: config.per_player_per_minute
+                .map(|limit| RateLimiter::dashmap(Quota::per_minute(NonZeroU32::new(limit).unwrap()))),
+            players_throttled: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a request for the profile of `player`, or returns `false` if they have had their
+    /// share. The request still needs to be acquired from its upstream.
+    pub fn try_acquire_player(&self, player: Uuid) -> bool {
+        match &self.players {
+            Some(players) if players.check_key(&player).is_err() => {
+                self.players_throttled.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Records a request to `upstream`, or returns `false` if it would exceed the ceiling.
+    pub fn try_acquire(&self, upstream: Upstream) -> bool {
+        let index = upstream.index();
+        let now = Instant::now();
+
+        let mut recent = self.recent[index].lock().unwrap();
+        self.prune(&mut recent, now);
+
+        if let Some(limit) = self.limits[index] {
+            if recent.len() >= limit as usize {
+                self.throttled[index].fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+
+        recent.push_back(now);
+        self.requests[index].fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Requests made to `upstream` within the current window.
+    pub fn in_window(&self, upstream: Upstream) -> usize {
+        let mut recent = self.recent[upstream.index()].lock().unwrap();
+        self.prune(&mut recent, Instant::now());
+        recent.len()
+    }
+
+    #[inline]
+    pub fn limit(&self, upstream: Upstream) -> Option<u32> {
+        self.limits[upstream.index()]
+    }
+
+    #[inline]
+    pub fn requests(&self, upstream: Upstream) -> u64 {
+        self.requests[upstream.index()].load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn throttled(&self, upstream: Upstream) -> u64 {
+        self.throttled[upstream.index()].load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn players_throttled(&self) -> u64 {
+        self.players_throttled.load(Ordering::Relaxed)
+    }
+
+    fn prune(&self, recent: &mut VecDeque<Instant>, now: Instant) {
+        while let Some(&oldest) = recent.front() {
+            if now.duration_since(oldest) < self.window {
+                break;
+            }
+            recent.pop_front();
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+const BUCKET: Duration = Duration::from_secs(60);
+/// The longest window reported, in buckets.
+const BUCKETS: u64 = 60;
+/// Clients tracked at once, so that a scan from many addresses can't exhaust memory.
+const MAX_CLIENTS: usize = 65536;
+
+/// Requests and response bytes per client, such as a client address, over the last minute and
+/// hour, for spotting heavy integrators and scrapers.
+pub struct Usage<K> {
+    start: Instant,
+    clients: Mutex<HashMap<K, VecDeque<Bucket>>>,
+}
+
+#[derive(Copy, Clone)]
+struct Bucket {
+    index: u64,
+    requests: u64,
+    bytes: u64,
+}
+
+/// One client's usage, where the `_1m` figures cover the current minute and the `_1h` figures
+/// the last sixty.
+#[derive(Serialize, Debug, Default)]
+pub struct UsageCounts {
+    pub requests_1m: u64,
+    pub requests_1h: u64,
+    pub bytes_1m: u64,
+    pub bytes_1h: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClientUsage {
+    pub ip: IpAddr,
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+}
+
+impl<K: Hash + Eq + Clone> Usage<K> {
+    pub fn new() -> Usage<K> {
+        Usage {
+            start: Instant::now(),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, client: K, bytes: u64) {
+        let index = self.bucket_index();
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() >= MAX_CLIENTS && !clients.contains_key(&client) {
+            clients.retain(|_, buckets| buckets.back().is_some_and(|bucket| index - bucket.index < BUCKETS));
+            if clients.len() >= MAX_CLIENTS {
+                return;
+            }
+        }
+
+        let buckets = clients.entry(client).or_default();
+        match buckets.back_mut() {
+            Some(bucket) if bucket.index == index => {
+                bucket.requests += 1;
+                bucket.bytes += bytes;
+            }
+            _ => buckets.push_back(Bucket { index, requests: 1, bytes }),
+        }
+
+        while buckets.front().is_some_and(|bucket| index - bucket.index >= BUCKETS) {
+            buckets.pop_front();
+        }
+    }
+
+    /// The `limit` clients with the most requests in the last hour, busiest first.
+    pub fn top(&self, limit: usize) -> Vec<(K, UsageCounts)> {
+        let index = self.bucket_index();
+        let clients = self.clients.lock().unwrap();
+
+        let mut usage: Vec<(K, UsageCounts)> = clients.iter()
+            .map(|(client, buckets)| {
+                let mut usage = UsageCounts::default();
+                for bucket in buckets.iter().filter(|bucket| index - bucket.index < BUCKETS) {
+                    usage.requests_1h += bucket.requests;
+                    usage.bytes_1h += bucket.bytes;
+                    if bucket.index == index {
+                        usage.requests_1m += bucket.requests;
+                        usage.bytes_1m += bucket.bytes;
+                    }
+                }
+                (client.clone(), usage)
+            })
+            .filter(|(_, usage)| usage.requests_1h > 0)
+            .collect();
+
+        usage.sort_by(|(_, a), (_, b)| b.requests_1h.cmp(&a.requests_1h).then(b.bytes_1h.cmp(&a.bytes_1h)));
+        usage.truncate(limit);
+        usage
+    }
+
+    #[inline]
+    fn bucket_index(&self) -> u64 {
+        self.start.elapsed().as_secs() / BUCKET.as_secs()
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::time::{Instant, MissedTickBehavior};
+use uuid::Uuid;
+
+use crate::api::{ApiAccess, PlayerInfo};
+
+/// How many changes a slow watcher may fall behind by before missing the oldest.
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Checks on watched players' skins, each with a single task however many are watching them.
+pub struct Watcher {
+    interval: Duration,
+    max_players: usize,
+    players: Mutex<HashMap<Uuid, broadcast::Sender<PlayerInfo>>>,
+}
+
+impl Watcher {
+    pub fn new(interval: Duration, max_players: usize) -> Watcher {
+        Watcher {
+            interval,
+            max_players,
+            players: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Subscribes to changes to the skin of the player currently described by `current`, which
+    /// are checked for through `api` unless someone is already watching them. Returns `None`
+    /// if as many players as we may watch are being watched already.
+    pub fn watch(self: &Arc<Self>, api: ApiAccess, current: &PlayerInfo) -> Option<broadcast::Receiver<PlayerInfo>> {
+        let mut players = self.players.lock().unwrap();
+        if let Some(changes) = players.get(&current.uuid) {
+            return Some(changes.subscribe());
+        }
+
+        if players.len() >= self.max_players {
+            return None;
+        }
+
+        let (changes, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+        players.insert(current.uuid, changes.clone());
+        tokio::spawn(self.clone().check(api, current.uuid, current.texture_hash.clone(), changes));
+
+        Some(receiver)
+    }
+
+    /// Revalidates the player every interval until nobody is watching them anymore.
+    async fn check(self: Arc<Self>, api: ApiAccess, uuid: Uuid, mut hash: Option<String>, changes: broadcast::Sender<PlayerInfo>) {
+        let mut interval = tokio::time::interval_at(Instant::now() + self.interval, self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            // Checked under the lock so that nobody subscribes to a channel about to be dropped.
+            {
+                let mut players = self.players.lock().unwrap();
+                if changes.receiver_count() == 0 {
+                    players.remove(&uuid);
+                    return;
+                }
+            }
+
+            match api.revalidate(uuid).await {
+                Ok(Some(info)) if info.texture_hash != hash => {
+                    hash = info.texture_hash.clone();
+                    let _ = changes.send(info);
+                }
+                Ok(_) => (),
+                Err(err) => log::debug!("failed to revalidate watched player {}: {:?}", uuid, err),
+            }
+        }
+    }
+}
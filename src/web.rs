@@ -1,77 +1,1918 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::Write;
 use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures::{FutureExt, StreamExt};
+use hmac::{Hmac, Mac};
+use hyper::{Body, Request, Response, Server};
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::service::{make_service_fn, service_fn, Service};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::{broadcast, Semaphore};
 use uuid::Uuid;
-use warp::Filter;
-use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+use warp::http::{header, HeaderMap, HeaderValue, Method, StatusCode, Version};
+use warp::hyper::body::HttpBody;
+use warp::path::FullPath;
 
-use crate::api::Api;
+use crate::activation;
+use crate::admin::{self, Unauthorized};
+use crate::audit::AuditLog;
+use crate::access_log::{self, AccessLogFormat};
+use crate::api::{self, AccessDenied, Api, Client, ImageBytes};
+use crate::cache;
+use crate::graphql;
+use crate::metrics;
+use crate::ping;
+use crate::minecraft::{self, PlayerTextureRef};
+use crate::options::{allowed_sizes, parse_size, BannerOptions, BodyOptions, CapeOptions, CardOptions, ChibiOptions, CompassOptions, FaceOptions, HeadOptions, OutputFormat, MAX_LAYOUT_SCALE};
+use crate::tenant::Tenants;
+use crate::timing::Timeline;
+use crate::trace::{self, Tracer};
 use crate::Config;
 
+/// Routes are served under this prefix so that breaking changes can ship under the next one.
+pub(crate) const API_VERSION: &str = "v1";
+
+/// Responses smaller than this aren't worth the overhead of compressing.
+const MIN_COMPRESSED_BYTES: u64 = 256;
+
+/// Brotli's highest qualities are far too slow to compress responses on the fly.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+const SIGNATURE_HEADER: &str = "x-signature";
+
+const PROBE_PATHS: [&str; 2] = ["/healthz", "/readyz"];
+
+/// How long clients turned away for too many requests being in flight are asked to wait.
+const OVERLOADED_RETRY_AFTER_SECS: u64 = 1;
+
+/// The header a request ID is taken from and answered in, so that a proxy's ID carries through
+/// to our logs.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Serves the API on the configured addresses until shut down.
 pub async fn run(api: Api, config: Config) {
+    let routes = routes(api, &config);
+    serve(routes, &config).await;
+}
+
+/// Builds every route of the API, ready to be served or mounted within another warp server.
+pub fn routes(api: Api, config: &Config) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone {
     let cors = warp::cors()
         .allow_any_origin();
 
+    let player_uuid = player_uuid(config.allow_offline_uuids);
+    let tenants = api.tenants();
+    let url_signing_key: Option<Arc<[u8]>> = config.url_signing_key.as_deref().map(|key| Arc::from(key.as_bytes()));
+
     let face = warp::path("face")
-        .and(warp::addr::remote())
+        .and(client(&tenants, "face"))
+        .and(source())
+        .and(warp::path::param::<u32>())
+        .and(player_uuid.clone())
+        .and(warp::query::<FaceOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, size, uuid, options, if_none_match| get_face(api.clone(), client, source, size, uuid, options, if_none_match)
+        });
+
+    let face_query = warp::path("face")
+        .and(warp::path::end())
+        .and(client(&tenants, "face"))
+        .and(source())
+        .and(face_query(config.allow_offline_uuids))
+        .and(warp::query::<FaceOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, query: FaceQuery, options, if_none_match| get_face(api.clone(), client, source, query.size, query.uuid, options, if_none_match)
+        });
+
+    let texture_face = warp::path("face")
+        .and(client(&tenants, "face"))
+        .and(source())
+        .and(warp::path::param::<u32>())
+        .and(warp::path("texture"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::query::<FaceOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, size, hash, options, if_none_match| get_texture_face(api.clone(), client, source, size, hash, options, if_none_match)
+        });
+
+    let favicon = warp::path("face")
+        .and(client(&tenants, "face"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path("favicon.ico"))
+        .and(warp::path::end())
+        .and(warp::query::<FaceOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, uuid, options, if_none_match| get_favicon(api.clone(), client, source, uuid, options, if_none_match)
+        });
+
+    let face_manifest = warp::path("face")
+        .and(warp::path("manifest"))
+        .and(client(&tenants, "face"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<FaceOptions>())
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and_then({
+            let api = api.clone();
+            let key = url_signing_key.clone();
+            move |client, source, uuid, options, path: FullPath, query| {
+                get_face_manifest(api.clone(), client, source, uuid, options, (path, query), key.clone())
+            }
+        });
+
+    let head = warp::path("head")
+        .and(client(&tenants, "head"))
+        .and(source())
+        .and(warp::path::param::<u32>())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<HeadOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, size, uuid, options, if_none_match| get_head(api.clone(), client, source, size, uuid, options, if_none_match)
+        });
+
+    let compass = warp::path("compass")
+        .and(client(&tenants, "compass"))
+        .and(source())
+        .and(warp::path::param::<u32>())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<CompassOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, size, uuid, options, if_none_match| get_compass(api.clone(), client, source, size, uuid, options, if_none_match)
+        });
+
+    let views = warp::path("views")
+        .and(client(&tenants, "views"))
+        .and(source())
+        .and(warp::path::param::<u32>())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<BodyOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, size, uuid, options, if_none_match| get_views(api.clone(), client, source, size, uuid, options, if_none_match)
+        });
+
+    let preview = warp::path("preview")
+        .and(client(&tenants, "preview"))
+        .and(source())
+        .and(warp::path::param::<u32>())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<BodyOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, size, uuid, options, if_none_match| get_preview(api.clone(), client, source, size, uuid, options, if_none_match)
+        });
+
+    let chibi = warp::path("chibi")
+        .and(client(&tenants, "chibi"))
+        .and(source())
+        .and(warp::path::param::<u32>())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<ChibiOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, size, uuid, options, if_none_match| get_chibi(api.clone(), client, source, size, uuid, options, if_none_match)
+        });
+
+    let diff = warp::path("diff")
+        .and(client(&tenants, "diff"))
+        .and(source())
+        .and(warp::path::param::<u32>())
+        .and(player_uuid.clone())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<DiffQuery>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, size, a, b, query, if_none_match| get_diff(api.clone(), client, source, size, (a, b), query, if_none_match)
+        });
+
+    let color = warp::path("color")
+        .and(client(&tenants, "color"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and_then({
+            let api = api.clone();
+            move |client, source, uuid| get_color(api.clone(), client, source, uuid)
+        });
+
+    let banner = warp::path("banner")
+        .and(client(&tenants, "banner"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<BannerOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, uuid, options, if_none_match| get_banner(api.clone(), client, source, uuid, options, if_none_match)
+        });
+
+    let cape = warp::path("cape")
+        .and(client(&tenants, "cape"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<CapeOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, uuid, options, if_none_match| get_cape(api.clone(), client, source, uuid, options, if_none_match)
+        });
+
+    let server_status = warp::path("server")
+        .and(client(&tenants, "server"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("status"))
+        .and(warp::path::end())
+        .and_then({
+            let api = api.clone();
+            move |client, address| get_server_status(api.clone(), client, address)
+        });
+
+    let server_icon = warp::path("server")
+        .and(client(&tenants, "server"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("icon"))
+        .and(warp::path::end())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, address, if_none_match| get_server_icon(api.clone(), client, address, if_none_match)
+        });
+
+    let card = warp::path("card")
+        .and(client(&tenants, "card"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::query::<CardOptions>())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, uuid, options, if_none_match| get_card(api.clone(), client, source, uuid, options, if_none_match)
+        });
+
+    let layout = warp::path("layout")
+        .and(client(&tenants, "layout"))
+        .and(source())
         .and(warp::path::param::<u32>())
-        .and(warp::path::param::<Uuid>())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, scale, uuid, if_none_match| get_layout(api.clone(), client, source, scale, uuid, if_none_match)
+        });
+
+    let skin = warp::path("skin")
+        .and(client(&tenants, "skin"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and(warp::header::optional("if-none-match"))
+        .and_then({
+            let api = api.clone();
+            move |client, source, uuid, if_none_match| get_skin(api.clone(), client, source, uuid, if_none_match)
+        });
+
+    let normalized_skin = warp::path("skin")
+        .and(warp::path("normalized"))
+        .and(client(&tenants, "skin"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
         .and(warp::header::optional("if-none-match"))
         .and_then({
             let api = api.clone();
-            move |addr, size, uuid, if_none_match| get_face(api.clone(), addr, size, uuid, if_none_match)
+            move |client, source, uuid, if_none_match| get_normalized_skin(api.clone(), client, source, uuid, if_none_match)
+        });
+
+    let info = warp::path("info")
+        .and(client(&tenants, "info"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and_then({
+            let api = api.clone();
+            move |client, source, uuid| get_info(api.clone(), client, source, uuid)
+        });
+
+    let watch = warp::path("watch")
+        .and(client(&tenants, "watch"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and_then({
+            let api = api.clone();
+            move |client, source, uuid| watch_player(api.clone(), client, source, uuid)
+        });
+
+    let names = warp::path("names")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(client(&tenants, "names"))
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::json())
+        .and_then({
+            let api = api.clone();
+            move |client, names| resolve_names(api.clone(), client, names)
+        });
+
+    let uuid = warp::path("uuid")
+        .and(client(&tenants, "uuid"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and_then({
+            let api = api.clone();
+            move |client, name| resolve_name(api.clone(), client, name)
+        });
+
+    let profile = warp::path("profile")
+        .and(client(&tenants, "profile"))
+        .and(source())
+        .and(player_uuid.clone())
+        .and(warp::path::end())
+        .and_then({
+            let api = api.clone();
+            move |client, source, uuid| get_profile(api.clone(), client, source, uuid)
+        });
+
+    let metrics = warp::path("metrics")
+        .and(warp::path::end())
+        .and(client(&tenants, "metrics"))
+        .and_then({
+            let api = api.clone();
+            move |client| get_metrics(api.clone(), client)
+        });
+
+    // Probes come from the orchestrator, so they skip the IP filter and rate limiter.
+    let healthz = warp::path("healthz")
+        .and(warp::path::end())
+        .map(|| "ok");
+
+    let readyz = warp::path("readyz")
+        .and(warp::path::end())
+        .map({
+            let api = api.clone();
+            move || {
+                let readiness = api.readiness();
+                let status = if readiness.is_ready() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+                warp::reply::with_status(warp::reply::json(&readiness), status)
+            }
         });
 
-    warp::serve(face.with(cors))
-        .run(([127, 0, 0, 1], config.port))
-        .await;
+    let routes = signed_url(url_signing_key).and(face_query.or(face).or(texture_face).or(favicon).or(face_manifest).or(head).or(compass).or(views).or(preview).or(chibi).or(diff).or(color).or(banner).or(cape).or(card).or(layout).or(skin).or(normalized_skin).or(info).or(watch).or(profile).or(server_status).or(server_icon).or(names).or(uuid))
+        // Boxed so that the combined filter's futures don't overflow the stack in debug builds.
+        .boxed();
+
+    // Unprefixed paths stay as aliases of the first version for embeds that predate versioning.
+    let routes = warp::path(API_VERSION).and(routes.clone()).or(routes)
+        .or(metrics)
+        .or(healthz)
+        .or(readyz)
+        .or(admin::routes(api.clone(), config.admin_token.clone(), AuditLog::new(config.audit_log.clone())))
+        .or(graphql::routes(api.clone(), config.graphql, config.allow_offline_uuids))
+        .with(cors)
+        .recover(recover);
+    let routes = with_signature(routes, config.signing_key.as_deref().map(|key| Arc::from(key.as_bytes())));
+    let routes = with_compression(routes);
+    with_access_log(routes, api, config.access_log)
 }
 
-async fn get_face(
-    api: Api, addr: Option<SocketAddr>,
-    size: u32, uuid: Uuid,
-    if_none_match: Option<String>,
-) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    log::debug!("receiving face request for {0} ({1}x{1}) from {2:?}", uuid, size, addr);
+/// The address of the client on the other end of a connection, attached to each request since
+/// we drive hyper ourselves rather than through warp's server.
+#[derive(Copy, Clone)]
+struct RemoteAddr(SocketAddr);
+
+/// The client address, whether we are driving hyper ourselves or mounted within a warp server.
+pub(crate) fn remote_addr() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::ext::optional::<RemoteAddr>()
+        .and(warp::addr::remote())
+        .map(|ours: Option<RemoteAddr>, warp: Option<SocketAddr>| ours.map(|addr| addr.0).or(warp))
+}
+
+#[derive(Debug)]
+struct UnknownApiKey;
+
+impl warp::reject::Reject for UnknownApiKey {}
+
+#[derive(Debug)]
+struct FeatureDisabled;
+
+impl warp::reject::Reject for FeatureDisabled {}
+
+#[derive(Deserialize)]
+struct FormatQuery {
+    format: Option<OutputFormat>,
+}
+
+/// The client behind a request and the tenant it was made on behalf of, if any. Requests from
+/// tenants not allowed `feature`, or the output format asked for, are rejected.
+pub(crate) fn client(tenants: &Arc<Tenants>, feature: &'static str) -> impl Filter<Extract = (Client,), Error = Rejection> + Clone {
+    let tenants = tenants.clone();
+    remote_addr()
+        .and(warp::header::headers_cloned())
+        .and(warp::query::<FormatQuery>())
+        .and_then(move |addr, headers: HeaderMap, query: FormatQuery| {
+            let tenant = tenants.identify(&headers);
+            async move {
+                let tenant = tenant.map_err(|_| warp::reject::custom(UnknownApiKey))?;
+                if tenant.as_ref().is_some_and(|tenant| !tenant.allows(feature, query.format)) {
+                    return Err(warp::reject::custom(FeatureDisabled));
+                }
+                Ok(Client { addr, tenant })
+            }
+        })
+}
+
+/// Serves `routes` over HTTP/1.1 and cleartext HTTP/2 on every configured address until a
+/// shutdown signal is received. Open connections are then given a grace period to drain.
+async fn serve<F>(routes: F, config: &Config)
+    where F: Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    serve_on(routes, config, listeners(config), shutdown_signal()).await
+}
+
+/// Serves `routes` on each of `listeners` until `shutdown` resolves, and then until open
+/// connections drain or the grace period runs out.
+async fn serve_on<F, S>(routes: F, config: &Config, listeners: Vec<AddrIncoming>, shutdown: S)
+    where F: Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone + Send + Sync + 'static,
+          S: Future<Output = ()> + Send + 'static,
+{
+    let service = warp::service(routes);
+    let timeout = Duration::from_secs(config.request_timeout_secs);
+    let slow_request = config.slow_request_ms.map(Duration::from_millis);
 
-    let api = match api.try_access(addr.as_ref()) {
-        Some(api) => api,
-        None => return Ok(Box::new(StatusCode::TOO_MANY_REQUESTS)),
+    let tracer = config.tracing.as_ref().map(|tracing| Arc::new(Tracer::start(tracing)));
+    let in_flight = config.max_in_flight_requests.map(|max| Arc::new(Semaphore::new(max)));
+
+    let draining = Arc::new(AtomicBool::new(false));
+    let shutdown = {
+        let draining = draining.clone();
+        shutdown.map(move |_| draining.store(true, Ordering::Relaxed)).shared()
     };
 
-    let scale = match parse_scale(size) {
-        Some(scale) => scale,
-        None => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    let mut servers = Vec::new();
+    for mut incoming in listeners {
+        let service = service.clone();
+        let draining = draining.clone();
+        let tracer = tracer.clone();
+        let in_flight = in_flight.clone();
+        let make_service = make_service_fn(move |conn: &AddrStream| {
+            let mut service = service.clone();
+            let draining = draining.clone();
+            let tracer = tracer.clone();
+            let in_flight = in_flight.clone();
+            let addr = RemoteAddr(conn.remote_addr());
+            // Held by the connection's service, which hyper drops along with the connection.
+            let connection = metrics::OPEN_CONNECTIONS.enter();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                    let _ = &connection;
+                    let in_flight_request = metrics::IN_FLIGHT_REQUESTS.enter();
+                    request.extensions_mut().insert(addr);
+                    let path = request.uri().path().to_owned();
+                    let method = request.method().clone();
+                    let version = request.version();
+                    let traceparent = request.headers().get("traceparent")
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_owned);
+                    let request_id = request_id(request.headers());
+
+                    // Probes are answered however busy we are, as they are by the rate limiter.
+                    let permit = match &in_flight {
+                        Some(in_flight) if !PROBE_PATHS.contains(&path.as_str()) => Some(in_flight.clone().try_acquire_owned()),
+                        _ => None,
+                    };
+                    let overloaded = matches!(permit, Some(Err(_)));
+
+                    let timeline = Timeline::new(request_id.clone());
+                    let response = (!overloaded).then(|| AssertUnwindSafe(timeline.clone().scope(service.call(request))).catch_unwind());
+                    let draining = draining.clone();
+                    let tracer = tracer.clone();
+                    async move {
+                        let _in_flight_request = in_flight_request;
+                        let response = match response {
+                            Some(response) => response,
+                            None => {
+                                log::debug!("shedding request to {} from {} with too many in flight", path, addr.0);
+                                return Ok(overloaded_response());
+                            }
+                        };
+                        let _permit = permit;
+
+                        let mut response = match tokio::time::timeout(timeout, response).await {
+                            Ok(Ok(response)) => response?,
+                            Ok(Err(panic)) => {
+                                log::error!("request to {} from {} panicked: {}", path, addr.0, panic_message(&*panic));
+                                let mut response = Response::new(Body::empty());
+                                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                                response
+                            }
+                            Err(_) => {
+                                log::warn!("request to {} from {} timed out: {}", path, addr.0, timeline);
+                                let mut response = Response::new(Body::empty());
+                                *response.status_mut() = StatusCode::GATEWAY_TIMEOUT;
+                                response
+                            }
+                        };
+
+                        if slow_request.is_some_and(|threshold| timeline.elapsed() >= threshold) {
+                            log_slow_request(&method, &path, response.status(), &timeline);
+                        }
+
+                        if let Some(tracer) = &tracer {
+                            let request = trace::Request { method: &method, path: &path, addr: addr.0, traceparent: traceparent.as_deref() };
+                            tracer.record(request, response.status(), &timeline);
+                        }
+
+                        if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+                            response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+                        }
+
+                        // HTTP/2 clients are told to go away at the connection level instead.
+                        if draining.load(Ordering::Relaxed) && version <= Version::HTTP_11 {
+                            response.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("close"));
+                        }
+
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+
+        incoming.set_nodelay(true);
+        incoming.set_keepalive(config.http.tcp_keep_alive_secs.map(Duration::from_secs));
+        log::info!("listening on {}", incoming.local_addr());
+
+        let server = Server::builder(incoming)
+            .http1_keepalive(config.http.keep_alive)
+            .http2_keep_alive_interval(config.http.http2_keep_alive_interval_secs.map(Duration::from_secs))
+            .http2_keep_alive_timeout(Duration::from_secs(config.http.http2_keep_alive_timeout_secs))
+            .http2_max_concurrent_streams(config.http.http2_max_concurrent_streams)
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown.clone());
+        servers.push(server);
+    }
+
+    let drain_timeout = Duration::from_secs(config.drain_timeout_secs);
+    let drained = shutdown.then(|_| tokio::time::sleep(drain_timeout));
+
+    tokio::select! {
+        results = futures::future::join_all(servers) => {
+            for result in results {
+                if let Err(err) = result {
+                    log::error!("server error: {}", err);
+                }
+            }
+        }
+        _ = drained => log::warn!("connections still open after {:?}, closing them", drain_timeout),
+    }
+}
+
+/// The ID the client or a proxy in front of us gave the request, or a fresh one.
+fn request_id(headers: &HeaderMap) -> String {
+    headers.get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(str::to_owned)
+        .unwrap_or_else(|| trace::random_id(8))
+}
+
+fn overloaded_response() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(OVERLOADED_RETRY_AFTER_SECS));
+    response
+}
+
+/// Logs a request that went over the slow request threshold as a single JSON object, with the
+/// time it spent in each phase.
+fn log_slow_request(method: &Method, path: &str, status: StatusCode, timeline: &Timeline) {
+    let phases: serde_json::Map<_, _> = timeline.breakdown().into_iter()
+        .map(|(phase, duration)| (phase.to_owned(), serde_json::json!(duration.as_secs_f64() * 1000.0)))
+        .collect();
+    let entry = serde_json::json!({
+        "method": method.as_str(),
+        "path": path,
+        "status": status.as_u16(),
+        "duration_ms": timeline.elapsed().as_secs_f64() * 1000.0,
+        "phases_ms": phases,
+    });
+    log::warn!("slow request: {}", entry);
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Listens on the sockets passed by systemd socket activation if there are any, or otherwise
+/// on the configured addresses.
+fn listeners(config: &Config) -> Vec<AddrIncoming> {
+    let inherited = activation::listeners();
+    if !inherited.is_empty() {
+        return inherited.into_iter().map(inherit_listener).collect();
+    }
+
+    config.listen_addrs().iter()
+        .map(|addr| {
+            AddrIncoming::bind(addr)
+                .unwrap_or_else(|err| panic!("error binding to {}: {}", addr, err))
+        })
+        .collect()
+}
+
+fn inherit_listener(listener: std::net::TcpListener) -> AddrIncoming {
+    let listener = listener.set_nonblocking(true)
+        .and_then(|_| tokio::net::TcpListener::from_std(listener))
+        .unwrap_or_else(|err| panic!("error using inherited socket: {}", err));
+    AddrIncoming::from_listener(listener)
+        .unwrap_or_else(|err| panic!("error using inherited socket: {}", err))
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => { terminate.recv().await; }
+            Err(_) => futures::future::pending::<()>().await,
+        }
     };
+    #[cfg(not(unix))]
+    let terminate = futures::future::pending::<()>();
 
-    match api.get_face(uuid, scale).await {
-        Ok(face) => {
-            if !face.matches(if_none_match) {
-                Ok(Box::new(face))
-            } else {
-                Ok(Box::new(StatusCode::NOT_MODIFIED))
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate => (),
+    }
+
+    log::info!("shutting down");
+}
+
+/// Compresses textual responses such as JSON and SVG with brotli or gzip, whichever the client
+/// prefers. Images are already compressed and pass through untouched.
+fn with_compression<F, R>(routes: F) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+    where F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+          R: Reply,
+{
+    warp::header::headers_cloned()
+        .and(routes)
+        .and_then(|headers: HeaderMap, reply: R| async move {
+            let response = reply.into_response();
+            let coding = headers.get(header::ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .and_then(negotiate_coding);
+
+            match coding {
+                Some(coding) if is_compressible(&response) => Ok::<_, Infallible>(compress(response, coding).await),
+                _ => Ok(response),
+            }
+        })
+}
+
+/// Signs the bodies of successful responses with HMAC-SHA256 under `key`, if there is one. The
+/// signature covers the body before any compression, as clients see it once decoded.
+fn with_signature<F, R>(routes: F, key: Option<Arc<[u8]>>) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+    where F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+          R: Reply,
+{
+    routes.and_then(move |reply: R| {
+        let key = key.clone();
+        async move {
+            let response = reply.into_response();
+            match key {
+                // Streamed bodies are never whole, so can't be signed.
+                Some(key) if response.status().is_success() && response.body().size_hint().exact().is_some() => {
+                    Ok::<_, Infallible>(sign(response, &key).await)
+                }
+                _ => Ok(response),
             }
         }
+    })
+}
+
+async fn sign(response: warp::reply::Response, key: &[u8]) -> warp::reply::Response {
+    let (mut parts, body) = response.into_parts();
+    let body = match warp::hyper::body::to_bytes(body).await {
+        Ok(body) => body,
         Err(err) => {
-            log::error!("internal server error: {:?}", err);
-            Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+            log::error!("failed to read response body for signing: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(&body);
+    let signature: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    let value = HeaderValue::from_str(&format!("sha256={}", signature)).expect("hex is a valid header value");
+    parts.headers.insert(SIGNATURE_HEADER, value);
+    warp::reply::Response::from_parts(parts, Body::from(body))
+}
+
+/// The content codings responses can be compressed with, in order of preference.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Coding {
+    Brotli,
+    Gzip,
+}
+
+impl Coding {
+    const ALL: [Coding; 2] = [Coding::Brotli, Coding::Gzip];
+
+    fn name(self) -> &'static str {
+        match self {
+            Coding::Brotli => "br",
+            Coding::Gzip => "gzip",
         }
     }
 }
 
-#[inline]
-fn parse_scale(size: u32) -> Option<u32> {
-    if size % 8 == 0 && size >= 8 && size <= 256 {
-        log2(size / 8)
-    } else {
-        return None;
+/// Picks the coding the client weighs highest, preferring brotli between equals. Codings the
+/// client doesn't name take the weight of `*`, and those with a malformed weight are ignored.
+fn negotiate_coding(accept_encoding: &str) -> Option<Coding> {
+    let mut weights = [None; Coding::ALL.len()];
+    let mut wildcard = None;
+    for coding in accept_encoding.split(',') {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let weight = params.find(|param| param.get(..2).is_some_and(|key| key.eq_ignore_ascii_case("q=")))
+            .map_or(Some(1.0), |param| param[2..].parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q)));
+        let weight = match weight {
+            Some(weight) => weight,
+            None => continue,
+        };
+
+        if name == "*" {
+            wildcard = Some(weight);
+        } else if let Some(index) = Coding::ALL.iter().position(|coding| name.eq_ignore_ascii_case(coding.name())) {
+            weights[index] = Some(weight);
+        }
+    }
+
+    let mut best: Option<(Coding, f32)> = None;
+    for (&coding, weight) in Coding::ALL.iter().zip(weights) {
+        let weight = weight.or(wildcard).unwrap_or(0.0);
+        if weight > 0.0 && best.is_none_or(|(_, best)| weight > best) {
+            best = Some((coding, weight));
+        }
     }
+    best.map(|(coding, _)| coding)
 }
 
-#[inline]
-fn log2(value: u32) -> Option<u32> {
-    if value > 0 && value.is_power_of_two() {
-        Some((u32::BITS - 1) - value.leading_zeros())
-    } else {
-        None
+fn is_compressible(response: &warp::reply::Response) -> bool {
+    let content_type = response.headers().get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let textual = content_type.starts_with("application/json")
+        || content_type.starts_with("image/svg+xml")
+        || content_type.starts_with("text/");
+
+    let large_enough = response.body().size_hint().exact()
+        .is_some_and(|bytes| bytes >= MIN_COMPRESSED_BYTES);
+
+    textual && large_enough && response.status() == StatusCode::OK
+        && !response.headers().contains_key(header::CONTENT_ENCODING)
+}
+
+async fn compress(response: warp::reply::Response, coding: Coding) -> warp::reply::Response {
+    let (mut parts, body) = response.into_parts();
+    let body = match warp::hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            log::error!("failed to read response body for compression: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let compressed = match coding {
+        Coding::Brotli => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::with_capacity(body.len() / 2), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+            encoder.write_all(&body).and_then(|_| encoder.flush()).map(|_| encoder.into_inner())
+        }
+        Coding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 2), Compression::default());
+            encoder.write_all(&body).and_then(|_| encoder.finish())
+        }
+    };
+    let compressed = match compressed {
+        Ok(compressed) => compressed,
+        Err(err) => {
+            log::error!("failed to compress response body: {:?}", err);
+            return warp::reply::Response::from_parts(parts, Body::from(body));
+        }
+    };
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(coding.name()));
+    parts.headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    warp::reply::Response::from_parts(parts, Body::from(compressed))
+}
+
+/// Logs each response, if configured to, and counts it towards its client's usage statistics.
+fn with_access_log<F, R>(
+    routes: F,
+    api: Api,
+    format: Option<AccessLogFormat>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+    where F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+          R: Reply,
+{
+    let raw_query = warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify();
+
+    let tenants = api.tenants();
+    warp::any()
+        .map(Instant::now)
+        .and(remote_addr())
+        .and(warp::header::headers_cloned())
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(raw_query)
+        .and(routes)
+        .map(move |start: Instant, addr: Option<SocketAddr>, headers: HeaderMap, method: Method, path: FullPath, query: String, reply: R| {
+            let response = reply.into_response();
+            let bytes = response.body().size_hint().exact();
+
+            // Requests refused for an unknown key count towards the client alone.
+            let tenant = tenants.identify(&headers).ok().flatten();
+            api.record_usage(&Client { addr, tenant }, bytes.unwrap_or(0));
+
+            if let Some(format) = format {
+                let entry = access_log::Entry {
+                    addr,
+                    method: &method,
+                    path: path.as_str(),
+                    query: &query,
+                    status: response.status(),
+                    bytes,
+                    duration: start.elapsed(),
+                    cache: response.extensions().get::<cache::Outcome>().copied(),
+                };
+                entry.log(format);
+            }
+
+            response
+        })
+}
+
+#[derive(Debug)]
+struct InvalidUuid;
+
+impl warp::reject::Reject for InvalidUuid {}
+
+/// A UUID path parameter in any accepted spelling, rejected before reaching any upstream API if
+/// it cannot belong to a player.
+fn player_uuid(allow_offline: bool) -> impl Filter<Extract = (Uuid,), Error = Rejection> + Clone {
+    warp::path::param::<Uuid>()
+        .and_then(move |uuid: Uuid| async move {
+            if minecraft::is_valid_player_uuid(&uuid, allow_offline) {
+                Ok(uuid)
+            } else {
+                Err(warp::reject::custom(InvalidUuid))
+            }
+        })
+}
+
+#[derive(Deserialize)]
+struct SourceQuery {
+    source: Option<String>,
+}
+
+/// The source a trusted client asked to load the player from with `?source=`, if any.
+fn source() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::query::<SourceQuery>().map(|query: SourceQuery| query.source)
+}
+
+#[derive(Deserialize)]
+struct SignedQuery {
+    exp: Option<u64>,
+    sig: Option<String>,
+}
+
+impl SignedQuery {
+    /// Checks the signature against the path and every other parameter in `query`, so that
+    /// none of them can be changed without invalidating it.
+    fn verify(&self, key: &[u8], path: &str, query: &str) -> bool {
+        let (exp, sig) = match (self.exp, self.sig.as_deref().and_then(decode_hex)) {
+            (Some(exp), Some(sig)) => (exp, sig),
+            _ => return false,
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mac = match url_mac(key, path, query) {
+            Some(mac) => mac,
+            None => return false,
+        };
+        mac.verify_slice(&sig).is_ok() && exp >= now
+    }
+}
+
+/// The query string a URL signature covers: every parameter but `sig` itself, sorted and
+/// re-encoded so that neither their order nor their escaping matters. `None` if the query string
+/// is malformed.
+fn canonical_query(query: &str) -> Option<String> {
+    let mut pairs: Vec<(String, String)> = serde_urlencoded::from_str(query).ok()?;
+    pairs.retain(|(name, _)| name != "sig");
+    pairs.sort();
+    serde_urlencoded::to_string(&pairs).ok()
+}
+
+/// The MAC of `"<path>?<canonical query>"` that signs a URL.
+fn url_mac(key: &[u8], path: &str, query: &str) -> Option<Hmac<Sha256>> {
+    let query = canonical_query(query)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(format!("{}?{}", path, query).as_bytes());
+    Some(mac)
+}
+
+#[derive(Debug)]
+struct BadSignature;
+
+impl warp::reject::Reject for BadSignature {}
+
+/// Passes requests through unless there is a `key` to check their URL's signature against, in
+/// which case requests without a valid and unexpired one are rejected.
+fn signed_url(key: Option<Arc<[u8]>>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(warp::query::<SignedQuery>())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and_then(move |path: FullPath, signed: SignedQuery, query: String| {
+            let key = key.clone();
+            async move {
+                match key {
+                    Some(key) if !signed.verify(&key, path.as_str(), &query) => Err(warp::reject::custom(BadSignature)),
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// `path` with `query`, signed with `key` if there is one. Any signature already in `query` is
+/// replaced, while its expiry carries over.
+fn url_to(key: Option<&[u8]>, path: &str, query: &str) -> String {
+    let key = match key {
+        Some(key) => key,
+        None if query.is_empty() => return path.to_owned(),
+        None => return format!("{}?{}", path, query),
+    };
+
+    let query = canonical_query(query).unwrap_or_default();
+    let sig: String = match url_mac(key, path, &query) {
+        Some(mac) => mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect(),
+        None => String::new(),
+    };
+    format!("{}?{}&sig={}", path, query, sig)
+}
+
+/// Odd lengths fail on the last byte, which is cut short.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+/// The player and size of a face requested through the query string rather than the path.
+#[derive(Deserialize)]
+struct FaceQuery {
+    uuid: Uuid,
+    size: u32,
+}
+
+/// Like [`player_uuid`], but for faces requested as `/face?uuid=...&size=...`.
+fn face_query(allow_offline: bool) -> impl Filter<Extract = (FaceQuery,), Error = Rejection> + Clone {
+    warp::query::<FaceQuery>()
+        .and_then(move |query: FaceQuery| async move {
+            if minecraft::is_valid_player_uuid(&query.uuid, allow_offline) {
+                Ok(query)
+            } else {
+                Err(warp::reject::custom(InvalidUuid))
+            }
+        })
+}
+
+async fn recover(rejection: Rejection) -> Result<warp::reply::Response, Infallible> {
+    let status = if rejection.is_not_found() {
+        StatusCode::NOT_FOUND
+    } else if rejection.find::<Unauthorized>().is_some() {
+        return Ok(Unauthorized::reply());
+    } else if rejection.find::<UnknownApiKey>().is_some() {
+        StatusCode::UNAUTHORIZED
+    } else if rejection.find::<BadSignature>().is_some() || rejection.find::<FeatureDisabled>().is_some() {
+        StatusCode::FORBIDDEN
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        StatusCode::METHOD_NOT_ALLOWED
+    } else if rejection.find::<warp::filters::cors::CorsForbidden>().is_some() {
+        StatusCode::FORBIDDEN
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    Ok(status.into_response())
+}
+
+async fn get_face(
+    api: Api, client: Client, source: Option<String>,
+    size: u32, uuid: Uuid,
+    options: FaceOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving face request for {0} ({1}x{1}) from {2:?}", uuid, size, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let size = match parse_size(size) {
+        Some(size) if options.is_valid() => size,
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+
+    Ok(image_reply(api.get_face(uuid, size, options).await, if_none_match))
+}
+
+async fn get_texture_face(
+    api: Api, client: Client, source: Option<String>,
+    size: u32, hash: String,
+    options: FaceOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving texture face request for {0} ({1}x{1}) from {2:?}", hash, size, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let size = match parse_size(size) {
+        Some(size) if options.is_valid() && PlayerTextureRef::is_valid_hash(&hash) => size,
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+
+    // the texture behind a hash never changes, so the response can be cached indefinitely
+    match api.get_texture_face(hash, size, options).await {
+        Ok(Some(face)) => Ok(Box::new(warp::reply::with_header(
+            image_reply(Ok(face), if_none_match),
+            header::CACHE_CONTROL,
+            IMMUTABLE_CACHE_CONTROL,
+        ))),
+        Ok(None) => Ok(Box::new(StatusCode::NOT_FOUND)),
+        Err(err) => Ok(image_reply(Err(err), None)),
+    }
+}
+
+async fn get_metrics(api: Api, client: Client) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if let Err(denied) = api.try_access(&client, None).await {
+        return Ok(denied_reply(denied));
+    }
+
+    Ok(Box::new(warp::reply::with_header(api.metrics(), header::CONTENT_TYPE, metrics::CONTENT_TYPE)))
+}
+
+async fn get_favicon(
+    api: Api, client: Client, source: Option<String>,
+    uuid: Uuid,
+    options: FaceOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving favicon request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    if !options.is_valid() {
+        return Ok(Box::new(StatusCode::BAD_REQUEST));
+    }
+
+    let options = FaceOptions { format: OutputFormat::Ico, ..options };
+    Ok(image_reply(api.get_face(uuid, 0, options).await, if_none_match))
+}
+
+#[derive(Serialize)]
+struct FaceManifest {
+    uuid: Uuid,
+    sizes: Vec<FaceManifestEntry>,
+    srcset: String,
+}
+
+#[derive(Serialize)]
+struct FaceManifestEntry {
+    size: u32,
+    url: String,
+    /// Only known for faces already rendered, as the manifest doesn't render any itself.
+    etag: Option<String>,
+}
+
+/// Lists the URL of the face at every size, under the same prefix as the manifest was requested
+/// and signed for the same expiry if URLs must be signed.
+async fn get_face_manifest(
+    api: Api, client: Client, source: Option<String>,
+    uuid: Uuid,
+    options: FaceOptions,
+    (path, query): (FullPath, String),
+    url_signing_key: Option<Arc<[u8]>>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving face manifest request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    if !options.is_valid() {
+        return Ok(Box::new(StatusCode::BAD_REQUEST));
+    }
+
+    // Whatever the API is mounted under, such as `/v1`, comes before the route's own path.
+    let prefix = match path.as_str().rfind("/face/manifest/") {
+        Some(end) => &path.as_str()[..end],
+        None => "",
+    };
+
+    let mut sizes = Vec::new();
+    for size in allowed_sizes() {
+        let etag = match api.cached_face_etag(uuid, size, options.clone()).await {
+            Ok(etag) => etag,
+            Err(err) => return Ok(error_reply(err)),
+        };
+
+        let path = format!("{}/face/{}/{}", prefix, size, uuid);
+        sizes.push(FaceManifestEntry {
+            size,
+            url: url_to(url_signing_key.as_deref(), &path, &query),
+            etag,
+        });
+    }
+
+    let srcset = sizes.iter()
+        .map(|entry| format!("{} {}w", entry.url, entry.size))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Ok(json_reply(&FaceManifest { uuid, sizes, srcset }))
+}
+
+async fn get_head(
+    api: Api, client: Client, source: Option<String>,
+    size: u32, uuid: Uuid,
+    options: HeadOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving head request for {0} ({1}x{1}) from {2:?}", uuid, size, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let size = match parse_size(size) {
+        Some(size) if options.is_valid() => size,
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+
+    Ok(image_reply(api.get_head(uuid, size, options).await, if_none_match))
+}
+
+async fn get_compass(
+    api: Api, client: Client, source: Option<String>,
+    size: u32, uuid: Uuid,
+    options: CompassOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving compass request for {} ({}) from {:?}", uuid, size, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let size = match parse_size(size) {
+        Some(size) if options.is_valid() => size,
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+
+    Ok(image_reply(api.get_compass(uuid, size, options).await, if_none_match))
+}
+
+async fn get_views(
+    api: Api, client: Client, source: Option<String>,
+    size: u32, uuid: Uuid,
+    options: BodyOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving views request for {} ({}) from {:?}", uuid, size, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let size = match parse_size(size) {
+        Some(size) if size >= 8 => size,
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+
+    Ok(image_reply(api.get_views(uuid, size, options).await, if_none_match))
+}
+
+async fn get_preview(
+    api: Api, client: Client, source: Option<String>,
+    size: u32, uuid: Uuid,
+    options: BodyOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving preview request for {} ({}) from {:?}", uuid, size, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let size = match parse_size(size) {
+        Some(size) if size >= 8 => size,
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+
+    Ok(image_reply(api.get_preview(uuid, size, options).await, if_none_match))
+}
+
+async fn get_chibi(
+    api: Api, client: Client, source: Option<String>,
+    size: u32, uuid: Uuid,
+    options: ChibiOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving chibi request for {} ({}) from {:?}", uuid, size, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let size = match parse_size(size) {
+        Some(size) if size >= 8 && options.is_valid() => size,
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+
+    Ok(image_reply(api.get_chibi(uuid, size, options).await, if_none_match))
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    /// Whether to answer with how alike the faces are as JSON rather than with a diff image.
+    #[serde(default)]
+    score: bool,
+}
+
+async fn get_diff(
+    api: Api, client: Client, source: Option<String>,
+    size: u32, (a, b): (Uuid, Uuid),
+    query: DiffQuery,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving diff request for {} and {} ({}) from {:?}", a, b, size, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let size = match parse_size(size) {
+        Some(size) if size >= 8 => size,
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+
+    if query.score {
+        return Ok(match api.compare_faces(a, b).await {
+            Ok(similarity) => json_reply(&similarity),
+            Err(err) => error_reply(err),
+        });
+    }
+    Ok(image_reply(api.get_diff(a, b, size).await, if_none_match))
+}
+
+async fn get_color(api: Api, client: Client, source: Option<String>, uuid: Uuid) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving color request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    Ok(match api.get_face_colors(uuid).await {
+        Ok(colors) => json_reply(&colors),
+        Err(err) => error_reply(err),
+    })
+}
+
+async fn get_banner(
+    api: Api, client: Client, source: Option<String>,
+    uuid: Uuid,
+    options: BannerOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving banner request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    if !options.is_valid() {
+        return Ok(Box::new(StatusCode::BAD_REQUEST));
+    }
+
+    Ok(image_reply(api.get_banner(uuid, options).await, if_none_match))
+}
+
+async fn get_cape(
+    api: Api, client: Client, source: Option<String>,
+    uuid: Uuid,
+    options: CapeOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving cape request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    if !options.is_valid() {
+        return Ok(Box::new(StatusCode::BAD_REQUEST));
+    }
+
+    match api.get_cape(uuid, options).await {
+        Ok(Some(cape)) => Ok(image_reply(Ok(cape), if_none_match)),
+        Ok(None) => Ok(Box::new(StatusCode::NOT_FOUND)),
+        Err(err) => Ok(image_reply(Err(err), None)),
+    }
+}
+
+async fn get_card(
+    api: Api, client: Client, source: Option<String>,
+    uuid: Uuid,
+    options: CardOptions,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving card request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    if !options.is_valid() {
+        return Ok(Box::new(StatusCode::BAD_REQUEST));
+    }
+
+    Ok(image_reply(api.get_card(uuid, options).await, if_none_match))
+}
+
+async fn get_layout(
+    api: Api, client: Client, source: Option<String>,
+    scale: u32, uuid: Uuid,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving layout request for {} (x{}) from {:?}", uuid, scale, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    if !(1..=MAX_LAYOUT_SCALE).contains(&scale) {
+        return Ok(Box::new(StatusCode::BAD_REQUEST));
+    }
+
+    Ok(image_reply(api.get_layout(uuid, scale).await, if_none_match))
+}
+
+async fn get_skin(
+    api: Api, client: Client, source: Option<String>,
+    uuid: Uuid,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving skin request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    Ok(image_reply(api.get_skin_png(uuid).await, if_none_match))
+}
+
+async fn get_normalized_skin(
+    api: Api, client: Client, source: Option<String>,
+    uuid: Uuid,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving normalized skin request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    Ok(image_reply(api.get_normalized_skin_png(uuid).await, if_none_match))
+}
+
+async fn get_info(api: Api, client: Client, source: Option<String>, uuid: Uuid) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving info request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    match api.get_info(uuid).await {
+        Ok(Some(info)) => Ok(json_reply(&info)),
+        Ok(None) => Ok(Box::new(StatusCode::NOT_FOUND)),
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+/// Streams the player's info as server-sent `skin` events, starting with what it is now and
+/// followed by each change to their skin as it is noticed.
+async fn watch_player(api: Api, client: Client, source: Option<String>, uuid: Uuid) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving watch request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let info = match api.get_info(uuid).await {
+        Ok(Some(info)) => info,
+        Ok(None) => return Ok(Box::new(StatusCode::NOT_FOUND)),
+        Err(err) => return Ok(error_reply(err)),
+    };
+
+    let changes = match api.watch(&info) {
+        Some(changes) => changes,
+        None => return Ok(Box::new(StatusCode::SERVICE_UNAVAILABLE)),
+    };
+
+    let changes = futures::stream::unfold(changes, |mut changes| async move {
+        loop {
+            match changes.recv().await {
+                Ok(info) => return Some((info, changes)),
+                // A slow client only cares about the latest skin anyway.
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = futures::stream::once(async { info }).chain(changes)
+        .map(|info| warp::sse::Event::default().event("skin").json_data(&info));
+    Ok(Box::new(warp::sse::reply(warp::sse::keep_alive().stream(events))))
+}
+
+#[derive(Serialize)]
+struct ServerSummary {
+    online: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    protocol: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    players: Option<ServerPlayerCounts>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    motd: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ServerPlayerCounts {
+    online: u32,
+    max: u32,
+    /// Names of the players the server chose to list, usually a dozen at most.
+    sample: Vec<String>,
+}
+
+/// Summarizes a Minecraft server's status, answering with `"online": false` if it can't be
+/// reached.
+async fn get_server_status(api: Api, client: Client, address: String) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving server status request for {} from {:?}", address, client.addr);
+
+    let api = match api.try_access(&client, None).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let summary = match api.get_server_status(&address).await {
+        Ok(status) => ServerSummary {
+            online: true,
+            version: status.version.as_ref().map(|version| version.name.clone()),
+            protocol: status.version.as_ref().map(|version| version.protocol),
+            players: status.players.as_ref().map(|players| ServerPlayerCounts {
+                online: players.online,
+                max: players.max,
+                sample: players.sample.iter().map(|player| player.name.clone()).collect(),
+            }),
+            motd: Some(status.motd()),
+        },
+        Err(ping::Error::InvalidAddress) => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+        Err(ping::Error::NotPublic) | Err(ping::Error::PortNotAllowed) => return Ok(Box::new(StatusCode::FORBIDDEN)),
+        Err(_) => ServerSummary { online: false, version: None, protocol: None, players: None, motd: None },
+    };
+
+    let cache_control = format!("public, max-age={}", api.server_cache_duration().as_secs());
+    Ok(Box::new(warp::reply::with_header(warp::reply::json(&summary), header::CACHE_CONTROL, cache_control)))
+}
+
+async fn get_server_icon(
+    api: Api, client: Client,
+    address: String,
+    if_none_match: Option<String>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving server icon request for {} from {:?}", address, client.addr);
+
+    let api = match api.try_access(&client, None).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    let cache_control = format!("public, max-age={}", api.server_cache_duration().as_secs());
+    match api.get_server_icon(&address).await {
+        Ok(Some(icon)) => Ok(Box::new(warp::reply::with_header(image_reply(Ok(icon), if_none_match), header::CACHE_CONTROL, cache_control))),
+        Ok(None) => Ok(Box::new(StatusCode::NOT_FOUND)),
+        Err(ping::Error::InvalidAddress) => Ok(Box::new(StatusCode::BAD_REQUEST)),
+        Err(ping::Error::NotPublic) | Err(ping::Error::PortNotAllowed) => Ok(Box::new(StatusCode::FORBIDDEN)),
+        Err(_) => Ok(Box::new(StatusCode::BAD_GATEWAY)),
+    }
+}
+
+const MAX_NAMES_PER_BATCH: usize = 100;
+
+#[derive(Serialize)]
+struct NameReply<'a> {
+    id: Uuid,
+    name: &'a str,
+}
+
+/// Resolves a JSON array of usernames to an object mapping each name to its UUID, or null if no
+/// account has that name.
+async fn resolve_names(api: Api, client: Client, names: Vec<String>) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving request to resolve {} names from {:?}", names.len(), client.addr);
+
+    let api = match api.try_access(&client, None).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    if names.len() > MAX_NAMES_PER_BATCH || !names.iter().all(|name| minecraft::is_valid_username(name)) {
+        return Ok(Box::new(StatusCode::BAD_REQUEST));
+    }
+
+    match api.resolve_usernames(&names).await {
+        Ok(uuids) => {
+            let resolved: HashMap<&str, Option<Uuid>> = names.iter().map(String::as_str).zip(uuids).collect();
+            Ok(json_reply(&resolved))
+        }
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+async fn resolve_name(api: Api, client: Client, name: String) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving request to resolve {} from {:?}", name, client.addr);
+
+    let api = match api.try_access(&client, None).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    if !minecraft::is_valid_username(&name) {
+        return Ok(Box::new(StatusCode::BAD_REQUEST));
+    }
+
+    match api.resolve_usernames(std::slice::from_ref(&name)).await {
+        Ok(uuids) => match uuids.first().copied().flatten() {
+            Some(id) => Ok(json_reply(&NameReply { id, name: &name })),
+            None => Ok(Box::new(StatusCode::NOT_FOUND)),
+        },
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+async fn get_profile(api: Api, client: Client, source: Option<String>, uuid: Uuid) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    log::debug!("receiving profile request for {} from {:?}", uuid, client.addr);
+
+    let api = match api.try_access(&client, source.as_deref()).await {
+        Ok(api) => api,
+        Err(denied) => return Ok(denied_reply(denied)),
+    };
+
+    match api.get_profile(uuid).await {
+        Ok((profile, outcome)) => {
+            let reply: Box<dyn warp::Reply> = match profile {
+                Some(profile) => json_reply(&profile),
+                None => Box::new(StatusCode::NOT_FOUND),
+            };
+            let mut response = reply.into_response();
+            response.extensions_mut().insert(outcome);
+            Ok(Box::new(response))
+        }
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+fn json_reply<T: Serialize>(value: &T) -> Box<dyn warp::Reply> {
+    let cache_control = format!("public, max-age={}, stale-while-revalidate", api::CACHE_MAX_AGE);
+    Box::new(warp::reply::with_header(warp::reply::json(value), header::CACHE_CONTROL, cache_control))
+}
+
+fn error_reply(err: api::Error) -> Box<dyn warp::Reply> {
+    match err {
+        api::Error::UpstreamThrottled | api::Error::Overloaded => Box::new(StatusCode::SERVICE_UNAVAILABLE),
+        api::Error::Blocked => Box::new(StatusCode::GONE),
+        api::Error::UnknownBadge | api::Error::NoStatusServer | api::Error::NoPlayer => Box::new(StatusCode::BAD_REQUEST),
+        api::Error::NoStatsApi => Box::new(StatusCode::NOT_FOUND),
+        api::Error::StatsApi | api::Error::InvalidCape => Box::new(StatusCode::BAD_GATEWAY),
+        err => {
+            log::error!("internal server error: {:?}", err);
+            Box::new(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn denied_reply(denied: AccessDenied) -> Box<dyn warp::Reply> {
+    match denied {
+        AccessDenied::Forbidden => Box::new(StatusCode::FORBIDDEN),
+        AccessDenied::RateLimited => Box::new(StatusCode::TOO_MANY_REQUESTS),
+        AccessDenied::UnknownSource => Box::new(StatusCode::BAD_REQUEST),
+    }
+}
+
+fn image_reply(
+    result: api::Result<(ImageBytes, cache::Outcome)>,
+    if_none_match: Option<String>,
+) -> Box<dyn warp::Reply> {
+    match result {
+        Ok((image, outcome)) => {
+            let mut response = if !image.matches(if_none_match) {
+                image.into_response()
+            } else {
+                StatusCode::NOT_MODIFIED.into_response()
+            };
+            response.extensions_mut().insert(outcome);
+            Box::new(response)
+        }
+        Err(err) => error_reply(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+
+    use super::*;
+
+    const KEY: &[u8] = b"secret";
+
+    /// A server answering `/slow` after `delay` and any other path straight away, along with its
+    /// address and what tells it to shut down.
+    fn start_server(config: Config, delay: Duration) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+        let routes = warp::path::full().and_then(move |path: FullPath| async move {
+            if path.as_str() == "/slow" {
+                tokio::time::sleep(delay).await;
+            }
+            Ok::<_, Infallible>(warp::reply().into_response())
+        });
+
+        let incoming = AddrIncoming::bind(&([127, 0, 0, 1], 0).into()).unwrap();
+        let addr = incoming.local_addr();
+        let (shutdown, shutdown_received) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve_on(routes, &config, vec![incoming], shutdown_received.map(|_| ())).await
+        });
+        (addr, shutdown, server)
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> reqwest::Response {
+        reqwest::get(format!("http://{}{}", addr, path)).await.unwrap()
+    }
+
+    fn verify(path: &str, query: &str) -> bool {
+        let signed: SignedQuery = serde_urlencoded::from_str(query).unwrap();
+        signed.verify(KEY, path, query)
+    }
+
+    fn in_an_hour() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 3600
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(decode_hex("00ff10"), Some(vec![0x00, 0xff, 0x10]));
+        assert_eq!(decode_hex("ABcd"), Some(vec![0xab, 0xcd]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("+f"), None);
+        assert_eq!(decode_hex("é0"), None);
+    }
+
+    #[test]
+    fn signed_urls_verify() {
+        let url = url_to(Some(KEY), "/face/64/uuid", &format!("exp={}&shape=circle", in_an_hour()));
+        let (path, query) = url.split_once('?').unwrap();
+        assert!(verify(path, query));
+    }
+
+    #[test]
+    fn signatures_ignore_parameter_order() {
+        let exp = in_an_hour();
+        let url = url_to(Some(KEY), "/face/64/uuid", &format!("shape=circle&exp={}", exp));
+        let sig = url.split_once("&sig=").unwrap().1;
+        assert!(verify("/face/64/uuid", &format!("sig={}&shape=circle&exp={}", sig, exp)));
+    }
+
+    #[test]
+    fn tampered_signed_urls_fail() {
+        let exp = in_an_hour();
+        let url = url_to(Some(KEY), "/face/64/uuid", &format!("exp={}&shape=circle", exp));
+        let (path, query) = url.split_once('?').unwrap();
+
+        assert!(!verify("/face/128/uuid", query));
+        assert!(!verify(path, &query.replace("circle", "square")));
+        assert!(!verify(path, &format!("{}&flip=horizontal", query)));
+        assert!(!verify(path, &query.replace(&format!("exp={}", exp), &format!("exp={}", exp + 1))));
+
+        let signed: SignedQuery = serde_urlencoded::from_str(query).unwrap();
+        assert!(!signed.verify(b"other", path, query));
+    }
+
+    #[test]
+    fn expired_and_unsigned_urls_fail() {
+        let url = url_to(Some(KEY), "/face/64/uuid", "exp=1");
+        let (path, query) = url.split_once('?').unwrap();
+        assert!(!verify(path, query));
+
+        assert!(!verify("/face/64/uuid", &format!("exp={}", in_an_hour())));
+        assert!(!verify("/face/64/uuid", ""));
+    }
+
+    #[test]
+    fn url_to_without_key_leaves_url_unsigned() {
+        assert_eq!(url_to(None, "/face/64/uuid", ""), "/face/64/uuid");
+        assert_eq!(url_to(None, "/face/64/uuid", "shape=circle"), "/face/64/uuid?shape=circle");
+    }
+
+    #[test]
+    fn negotiates_coding_by_weight() {
+        assert_eq!(negotiate_coding("gzip"), Some(Coding::Gzip));
+        assert_eq!(negotiate_coding("gzip, deflate, br"), Some(Coding::Brotli));
+        assert_eq!(negotiate_coding("br;q=0.5, GZIP"), Some(Coding::Gzip));
+        assert_eq!(negotiate_coding("br;q=0.5, gzip;q=0.25"), Some(Coding::Brotli));
+        assert_eq!(negotiate_coding("gzip;Q=0.9, br;q=.8"), Some(Coding::Gzip));
+        assert_eq!(negotiate_coding("*"), Some(Coding::Brotli));
+        assert_eq!(negotiate_coding("br;q=0, *;q=0.1"), Some(Coding::Gzip));
+    }
+
+    #[test]
+    fn refused_or_unknown_codings_are_not_negotiated() {
+        assert_eq!(negotiate_coding("gzip;q=0"), None);
+        assert_eq!(negotiate_coding("br, gzip; q=0.000"), Some(Coding::Brotli));
+        assert_eq!(negotiate_coding("gzip;q=0.000, br;q=0"), None);
+        assert_eq!(negotiate_coding("*;q=0"), None);
+        assert_eq!(negotiate_coding("deflate, identity"), None);
+        assert_eq!(negotiate_coding(""), None);
+    }
+
+    #[test]
+    fn malformed_weights_are_ignored() {
+        assert_eq!(negotiate_coding("gzip;q=high"), None);
+        assert_eq!(negotiate_coding("gzip;q=2"), None);
+        assert_eq!(negotiate_coding("br;q=-1, gzip"), Some(Coding::Gzip));
+    }
+
+    #[tokio::test]
+    async fn compressed_bodies_decode_to_the_original() {
+        use std::io::Read;
+
+        let body = "{\"name\":\"jeb_\"}".repeat(64);
+        for coding in Coding::ALL {
+            let response = warp::reply::with_header(body.clone(), header::CONTENT_TYPE, "application/json").into_response();
+            let response = compress(response, coding).await;
+            assert_eq!(response.headers()[header::CONTENT_ENCODING], coding.name());
+
+            let compressed = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert!(compressed.len() < body.len());
+            let mut decoded = String::new();
+            match coding {
+                Coding::Brotli => brotli::Decompressor::new(&compressed[..], 4096).read_to_string(&mut decoded),
+                Coding::Gzip => flate2::read::GzDecoder::new(&compressed[..]).read_to_string(&mut decoded),
+            }.unwrap();
+            assert_eq!(decoded, body);
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_time_out() {
+        let config = Config { request_timeout_secs: 1, ..Config::default() };
+        let (addr, _shutdown, _) = start_server(config, Duration::from_secs(10));
+        assert_eq!(get(addr, "/").await.status(), StatusCode::OK);
+        assert_eq!(get(addr, "/slow").await.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn requests_past_the_limit_are_shed() {
+        let config = Config { max_in_flight_requests: Some(1), ..Config::default() };
+        let (addr, _shutdown, _) = start_server(config, Duration::from_millis(300));
+        let slow = tokio::spawn(get(addr, "/slow"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let shed = get(addr, "/").await;
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(shed.headers().contains_key(header::RETRY_AFTER));
+        assert_eq!(get(addr, "/healthz").await.status(), StatusCode::OK);
+
+        assert_eq!(slow.await.unwrap().status(), StatusCode::OK);
+        assert_eq!(get(addr, "/").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn shutdown_lets_open_requests_finish() {
+        let (addr, shutdown, server) = start_server(Config::default(), Duration::from_millis(300));
+        let slow = tokio::spawn(get(addr, "/slow"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.send(()).unwrap();
+
+        let slow = slow.await.unwrap();
+        assert_eq!(slow.status(), StatusCode::OK);
+        assert_eq!(slow.headers()[header::CONNECTION], "close");
+        tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_waiting_after_the_grace_period() {
+        let config = Config { drain_timeout_secs: 0, ..Config::default() };
+        let (addr, shutdown, server) = start_server(config, Duration::from_secs(10));
+        let slow = tokio::spawn(get(addr, "/slow"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.send(()).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap();
+        slow.abort();
     }
 }